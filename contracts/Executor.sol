@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.19;
+
+/// @notice Runs a batch of calls for its owner in one tx, e.g. a WETH wrap, the
+/// lend pool approval and several auctions, then pays the block's builder only
+/// if every call went through.
+contract Executor {
+    struct Call {
+        address target;
+        uint256 value;
+        bytes data;
+        /// a failure is skipped instead of reverting the batch
+        bool allowFailure;
+    }
+
+    address public immutable owner;
+
+    event CallFailed(uint256 index, bytes reason);
+
+    error NotOwner();
+    error CallReverted(uint256 index, bytes reason);
+    error CoinbasePaymentFailed();
+
+    constructor() {
+        owner = msg.sender;
+    }
+
+    receive() external payable {}
+
+    /// @return failures calls that failed and were allowed to
+    function execute(Call[] calldata calls, uint256 coinbasePayment)
+        external
+        payable
+        returns (uint256 failures)
+    {
+        if (msg.sender != owner) revert NotOwner();
+
+        for (uint256 i = 0; i < calls.length; i++) {
+            Call calldata call = calls[i];
+            (bool success, bytes memory reason) = call.target.call{value: call.value}(call.data);
+
+            if (success) continue;
+            if (!call.allowFailure) revert CallReverted(i, reason);
+
+            failures++;
+            emit CallFailed(i, reason);
+        }
+
+        if (coinbasePayment > 0 && failures == 0) {
+            (bool paid,) = block.coinbase.call{value: coinbasePayment}("");
+            if (!paid) revert CoinbasePaymentFailed();
+        }
+    }
+}
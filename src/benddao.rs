@@ -1,26 +1,45 @@
+pub mod bid_rules;
 pub mod loan;
+pub mod nft_configuration;
+pub mod projection;
 pub mod status;
 
 use self::status::Status;
 use crate::{
-    constants::OUR_EOA_ADDRESS,
+    bundle_tracker::{BidBundle, BundlePolicy},
+    constants::{
+        AUCTION_GAS, BLOCK_TIME, HEALTH_FACTOR_THRESHOLD_TO_RECHECK, LIQUIDATE_GAS, ONE_DAY,
+        OUR_EOA_ADDRESS,
+    },
     global_provider::GlobalProvider,
+    health_factor::{HealthFactorEngine, LoanPosition},
+    loan_index::LoanIndex,
+    price_guard::PriceRejection,
     prices_client::PricesClient,
+    profit::{to_eth, Opportunity, ProfitModel},
+    rpc_pool::RpcPool,
+    state_cache::CachedBlock,
+    store::{BidRecord, FineRecord, Record},
     types::*,
-    utils::{calculate_bidding_amount, get_repaid_defaulted_loans, save_repaid_defaulted_loans},
-    AuctionFilter, Config, LiquidateFilter, RedeemFilter,
+    AuctionFilter, Config, LendPoolEvents, LendPoolLoanEvents, LiquidateFilter, RedeemFilter,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use bid_rules::{min_first_bid, min_outbid};
 use ethers::{
-    providers::{Middleware, Provider, Ws},
-    types::{spoof::State, BlockNumber, Transaction, U256},
+    contract::LogMeta,
+    providers::{Middleware, Provider},
+    types::{Address, BlockNumber, Transaction, U256, U64},
 };
-use ethers_flashbots::BundleRequest;
 use loan::{Loan, NftAsset, ReserveAsset};
-use log::{info, warn};
+use log::{error, info, warn};
 use messenger_rs::slack_hook::SlackClient;
-use std::{collections::BTreeSet, sync::Arc};
-use tokio::sync::RwLock;
+use projection::projected_liquidation_timestamp;
+use std::{
+    collections::BTreeSet,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{Mutex, RwLock};
 
 #[allow(dead_code)]
 pub struct BendDao {
@@ -29,6 +48,12 @@ pub struct BendDao {
     global_provider: GlobalProvider,
     prices_client: Arc<RwLock<PricesClient>>,
     pub slack_bot: SlackClient,
+    health_factor_engine: HealthFactorEngine,
+    profit_model: ProfitModel,
+    bundle_policy: BundlePolicy,
+    pub liquidation_schedule: LiquidationSchedule,
+    loan_index: LoanIndex,
+    reorg_confirmation_depth: u64,
 }
 
 impl BendDao {
@@ -37,16 +62,41 @@ impl BendDao {
         prices_client: Arc<RwLock<PricesClient>>,
         slack_bot: SlackClient,
     ) -> Result<BendDao> {
+        let global_provider = GlobalProvider::try_new(config_vars.clone()).await?;
+
+        // pick up where we left off so a restart mid-auction still outbids and liquidates
+        let stored = global_provider.store.state().await;
+        let now = U256::from(chrono::Utc::now().timestamp());
+        let mut pending_auctions = PendingAuctions::default();
+        for auction in stored.auctions {
+            // an auction that ended is only worth liquidating if we won it
+            if auction.bid_end_timestamp > now || auction.current_bidder == OUR_EOA_ADDRESS.into() {
+                pending_auctions.add_update_auction(auction);
+            }
+        }
+
+        info!(
+            "restored {} pending auctions and {} monitored loans",
+            pending_auctions.pending_auctions.len(),
+            stored.monitored_loans.len()
+        );
+
         Ok(BendDao {
-            monitored_loans: vec![],
-            pending_auctions: PendingAuctions::default(),
-            global_provider: GlobalProvider::try_new(config_vars.clone()).await?,
+            monitored_loans: stored.monitored_loans,
+            pending_auctions,
+            global_provider,
             prices_client,
             slack_bot,
+            health_factor_engine: HealthFactorEngine::default(),
+            profit_model: ProfitModel::from_config(&config_vars),
+            bundle_policy: config_vars.bundle_policy,
+            liquidation_schedule: LiquidationSchedule::default(),
+            loan_index: LoanIndex::load().await?,
+            reorg_confirmation_depth: config_vars.reorg_confirmation_depth,
         })
     }
 
-    pub fn get_provider(&self) -> Arc<Provider<Ws>> {
+    pub fn get_provider(&self) -> Arc<Provider<RpcPool>> {
         self.global_provider.provider.clone()
     }
 
@@ -69,10 +119,17 @@ impl BendDao {
             .unwrap()
             .timestamp;
 
+        let nft_asset: NftAsset = evt.nft_asset.try_into().unwrap();
+        let first_bidder = self
+            .pending_auctions
+            .get(nft_asset, evt.nft_token_id)
+            .map_or(evt.on_behalf_of, |auction| auction.first_bidder);
+
         let auction = Auction {
             current_bid: evt.bid_price,
             current_bidder: evt.on_behalf_of,
-            nft_asset: evt.nft_asset.try_into().unwrap(),
+            first_bidder,
+            nft_asset,
             nft_token_id: evt.nft_token_id,
             bid_end_timestamp,
             reserve_asset: evt.reserve.try_into().unwrap(),
@@ -88,6 +145,8 @@ impl BendDao {
             return;
         }
 
+        self.global_provider.record(Record::Auction(auction)).await;
+
         let msg = match self.pending_auctions.add_update_auction(auction) {
             true => format!(
                 "New bid for {:?} #{} by {} | Auction time remaining: {} seconds",
@@ -121,15 +180,58 @@ impl BendDao {
 
         info!("{msg}");
         self.slack_bot.send_message(msg).await.ok();
+
+        if first_bidder == OUR_EOA_ADDRESS.into() && evt.on_behalf_of == first_bidder {
+            match self
+                .global_provider
+                .get_bid_fine(nft_asset, evt.nft_token_id)
+                .await
+            {
+                Ok(bid_fine) => info!(
+                    "first bidder on {:?} #{}, a redeem would pay us a fine of {} {:?}",
+                    nft_asset, evt.nft_token_id, bid_fine, auction.reserve_asset
+                ),
+                Err(e) => error!("failed to read bid fine: {e}"),
+            }
+        }
     }
 
     pub async fn react_to_redeem(&mut self, evt: RedeemFilter) {
         let nft_asset = NftAsset::try_from(evt.nft_asset).unwrap();
+        let first_bidder = self
+            .pending_auctions
+            .get(nft_asset, evt.nft_token_id)
+            .map(|auction| auction.first_bidder);
+
         self.pending_auctions
             .remove_auction(nft_asset, evt.nft_token_id);
+        self.global_provider
+            .record(Record::AuctionRemoved {
+                nft_asset,
+                nft_token_id: evt.nft_token_id,
+            })
+            .await;
 
-        let nft_asset = NftAsset::try_from(evt.nft_asset).unwrap();
-        let msg = format!("Redeem happened on {:?} #{}", nft_asset, evt.nft_token_id);
+        // the fine goes to whoever bid first
+        if first_bidder == Some(OUR_EOA_ADDRESS.into()) {
+            self.global_provider
+                .record(Record::Fine(FineRecord {
+                    nft_asset,
+                    nft_token_id: evt.nft_token_id,
+                    reserve_asset: evt.reserve.try_into().unwrap(),
+                    fine: evt.fine_amount,
+                    timestamp: chrono::Utc::now().timestamp() as u64,
+                }))
+                .await;
+        }
+
+        let msg = match first_bidder == Some(OUR_EOA_ADDRESS.into()) {
+            true => format!(
+                "Redeem happened on {:?} #{}, we earned a fine of {}",
+                nft_asset, evt.nft_token_id, evt.fine_amount
+            ),
+            false => format!("Redeem happened on {:?} #{}", nft_asset, evt.nft_token_id),
+        };
         info!("{msg}");
         self.slack_bot.send_message(&msg).await.ok();
     }
@@ -138,6 +240,12 @@ impl BendDao {
         let nft_asset = NftAsset::try_from(evt.nft_asset).unwrap();
         self.pending_auctions
             .remove_auction(nft_asset, evt.nft_token_id);
+        self.global_provider
+            .record(Record::AuctionRemoved {
+                nft_asset,
+                nft_token_id: evt.nft_token_id,
+            })
+            .await;
 
         let msg = format!(
             "liquidation happened for {:?} #{}",
@@ -147,50 +255,287 @@ impl BendDao {
         self.slack_bot.send_message(&msg).await.ok();
     }
 
+    /// Applies a live `LendPool` log to `PendingAuctions`.
+    ///
+    /// Every change is journaled with the log's block so it can be reverted if the block
+    /// is reorged out, which shows either as the log coming back `removed` or as a log of
+    /// a different block at a height that was already applied.
+    pub async fn react_to_lend_pool_event(
+        &mut self,
+        evt: LendPoolEvents,
+        meta: LogMeta,
+        removed: bool,
+    ) {
+        let reorged_block = if removed {
+            self.pending_auctions.journaled_block(meta.block_hash)
+        } else {
+            self.pending_auctions
+                .reorged_block(meta.block_number, meta.block_hash)
+        };
+
+        if let Some(block_number) = reorged_block {
+            self.revert_auction_changes_from(block_number).await;
+        }
+
+        if removed {
+            return;
+        }
+
+        let (nft_asset, nft_token_id) = match &evt {
+            LendPoolEvents::AuctionFilter(evt) => (evt.nft_asset, evt.nft_token_id),
+            LendPoolEvents::RedeemFilter(evt) => (evt.nft_asset, evt.nft_token_id),
+            LendPoolEvents::LiquidateFilter(evt) => (evt.nft_asset, evt.nft_token_id),
+            _ => return,
+        };
+
+        let Ok(nft_asset) = NftAsset::try_from(nft_asset) else {
+            return;
+        };
+        if !nft_asset.is_allowed_in_production() {
+            return;
+        }
+
+        let previous = self.pending_auctions.get(nft_asset, nft_token_id).copied();
+
+        match evt {
+            LendPoolEvents::AuctionFilter(evt) => self.react_to_auction(evt).await,
+            LendPoolEvents::RedeemFilter(evt) => self.react_to_redeem(evt).await,
+            LendPoolEvents::LiquidateFilter(evt) => self.react_to_liquidation(evt).await,
+            _ => {}
+        }
+
+        if self.pending_auctions.get(nft_asset, nft_token_id).copied() != previous {
+            self.pending_auctions.journal(AuctionChange {
+                block_number: meta.block_number,
+                block_hash: meta.block_hash,
+                log_index: meta.log_index,
+                nft_asset,
+                nft_token_id,
+                previous,
+            });
+        }
+
+        self.pending_auctions
+            .prune_journal(meta.block_number, self.reorg_confirmation_depth);
+    }
+
+    /// Reverts the `PendingAuctions` changes from `block_number` on, and in the store
+    async fn revert_auction_changes_from(&mut self, block_number: U64) {
+        let reverted = self.pending_auctions.revert_from(block_number);
+
+        if reverted.is_empty() {
+            return;
+        }
+
+        let records = reverted
+            .iter()
+            .map(|change| match change.previous {
+                Some(auction) => Record::Auction(auction),
+                None => Record::AuctionRemoved {
+                    nft_asset: change.nft_asset,
+                    nft_token_id: change.nft_token_id,
+                },
+            })
+            .collect();
+
+        if let Err(e) = self.global_provider.store.append_all(records).await {
+            error!("failed to record reverted auction changes: {e}");
+        }
+
+        let msg = format!(
+            "Reorg from block {block_number}: reverted {} auction change(s)",
+            reverted.len()
+        );
+        warn!("{msg}");
+        self.slack_bot.send_message(&msg).await.ok();
+    }
+
+    /// Applies a live `LendPoolLoan` log to the loan index and keeps the health factor
+    /// engine's position for that loan in step. Auctions leave the engine, they are
+    /// tracked through `PendingAuctions`.
+    ///
+    /// Reorged out blocks are reverted in the index the same way as in `PendingAuctions`.
+    pub fn react_to_loan_event(&mut self, evt: LendPoolLoanEvents, meta: LogMeta, removed: bool) {
+        let block_number = meta.block_number.as_u64();
+
+        let reorged_block = if removed {
+            self.loan_index.journaled_block(meta.block_hash)
+        } else {
+            self.loan_index.reorged_block(block_number, meta.block_hash)
+        };
+
+        if let Some(reorged_block) = reorged_block {
+            let reverted = self.loan_index.revert_from(reorged_block);
+            warn!(
+                "loan index: reorg from block {reorged_block}, reverted {} loan(s)",
+                reverted.len()
+            );
+            for loan_id in reverted {
+                self.refresh_position(loan_id);
+            }
+        }
+
+        if removed
+            || !self
+                .loan_index
+                .apply_live(&evt, block_number, meta.block_hash, meta.log_index)
+        {
+            return;
+        }
+
+        let loan_id = match &evt {
+            LendPoolLoanEvents::LoanCreatedFilter(evt) => evt.loan_id,
+            LendPoolLoanEvents::LoanUpdatedFilter(evt) => evt.loan_id,
+            LendPoolLoanEvents::LoanAuctionedFilter(evt) => evt.loan_id,
+            LendPoolLoanEvents::LoanRedeemedFilter(evt) => evt.loan_id,
+            LendPoolLoanEvents::LoanRepaidFilter(evt) => evt.loan_id,
+            LendPoolLoanEvents::LoanLiquidatedFilter(evt) => evt.loan_id,
+            _ => return,
+        };
+
+        self.refresh_position(loan_id);
+
+        self.loan_index
+            .prune_journal(block_number, self.reorg_confirmation_depth);
+    }
+
+    /// Puts the indexed loan in the health factor engine, or takes it out if it's not active
+    fn refresh_position(&mut self, loan_id: U256) {
+        let position = self
+            .loan_index
+            .get(loan_id)
+            .and_then(|loan| loan.to_position())
+            .filter(|position| position.nft_asset.is_allowed_in_production());
+
+        match position {
+            Some(position) => self.health_factor_engine.insert_position(position),
+            None => {
+                self.health_factor_engine.remove_position(loan_id);
+            }
+        }
+    }
+
     pub async fn initiate_auctions_if_any(
         &mut self,
         nft_oracle_tx: Transaction,
-        modded_state: Option<State>,
-    ) -> Result<Option<BundleRequest>> {
-        let iter = self.monitored_loans.iter().map(|x| x.as_u64());
-        let monitored_loans = self
+        twaps: &[(Address, U256)],
+    ) -> Result<Vec<BidBundle>> {
+        // the oracle tx lands in the next block at the earliest
+        let timestamp = U256::from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+
+        // only loans the engine can't clear are checked against the lend pool
+        let candidates: Vec<U256> = self
+            .monitored_loans
+            .iter()
+            .copied()
+            .filter(|&loan_id| {
+                self.health_factor_engine
+                    .loan(loan_id, timestamp, twaps)
+                    .is_none_or(|loan| {
+                        loan.health_factor < HEALTH_FACTOR_THRESHOLD_TO_RECHECK.into()
+                    })
+            })
+            .collect();
+
+        let bundle = BidBundle {
+            leading_txs: vec![nft_oracle_tx],
+            twaps: twaps.to_vec(),
+            ..Default::default()
+        };
+
+        self.package_auction_bundle(bundle, &candidates).await
+    }
+
+    /// Auctions loans the `LiquidationSchedule` expects to have crossed by `target_block`
+    /// from interest alone. They are checked against the lend pool first since the
+    /// projection assumes the borrow rate didn't change.
+    ///
+    /// Loans still monitored that didn't get a bid go back in the schedule at `due`,
+    /// the projection can be a little early and without a state cache the check runs
+    /// at the latest block rather than the target one. The next refresh reschedules them.
+    pub async fn initiate_scheduled_auctions(
+        &mut self,
+        loan_ids: &[U256],
+        target_block: U64,
+        due: U256,
+    ) -> Result<Vec<BidBundle>> {
+        let bundle = BidBundle {
+            block: Some(target_block),
+            ..Default::default()
+        };
+
+        let bundles = self.package_auction_bundle(bundle, loan_ids).await?;
+
+        for &loan_id in loan_ids {
+            let Some(position) = self.health_factor_engine.position(loan_id) else {
+                continue;
+            };
+            let has_bid = bundles.iter().flat_map(|bundle| &bundle.bids).any(|bid| {
+                bid.nft_asset == position.nft_asset.into()
+                    && bid.nft_token_id == position.nft_token_id
+            });
+            if !has_bid {
+                self.liquidation_schedule.schedule(due, loan_id);
+            }
+        }
+
+        Ok(bundles)
+    }
+
+    /// Adds a bid to `bundle` for every loan in `loan_ids` that is auctionable once its
+    /// twaps are posted, split up as the bundle policy says. Empty if there are none.
+    async fn package_auction_bundle(
+        &mut self,
+        mut bundle: BidBundle,
+        loan_ids: &[U256],
+    ) -> Result<Vec<BidBundle>> {
+        if loan_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let twaps = &bundle.twaps;
+        // a scheduled bundle is checked as of the block it targets, not the head
+        let block = self.global_provider.block_env(bundle.block);
+
+        let loans = self
             .global_provider
-            .get_loans_from_iter(iter, modded_state)
+            .get_cached_loans(loan_ids, twaps, block)
             .await?;
 
-        let mut balances = self.global_provider.get_balances().await?;
+        let timestamp = self.expected_timestamp(bundle.block).await?;
 
         let loans_ready_to_auction = self
-            .package_loans_ready_to_auction(monitored_loans, &mut balances)
-            .await;
+            .package_loans_ready_to_auction(loans, twaps, timestamp, block)
+            .await?;
 
         if loans_ready_to_auction.is_empty() {
-            return Ok(None);
+            return Ok(vec![]);
         }
 
-        let mut bundle = BundleRequest::new();
-        bundle.add_transaction(nft_oracle_tx);
+        let bids = loans_ready_to_auction
+            .iter()
+            .map(|bid| Record::Bid(BidRecord::new(bid)))
+            .collect();
+        self.global_provider.store.append_all(bids).await?;
 
-        Ok(Some(
-            self.global_provider
-                .create_auction_bundle(bundle, loans_ready_to_auction, false)
-                .await?,
-        ))
+        bundle.bids = loans_ready_to_auction;
+
+        Ok(bundle.split(self.bundle_policy))
     }
 
+    /// Bids are sized for `timestamp`, when the bundle is expected to land.
+    /// Whether they can be paid for is left to `Treasury::fund_bids` when they're signed
     async fn package_loans_ready_to_auction(
         &self,
         loans: Vec<Loan>,
-        balances: &mut Balances,
-    ) -> Vec<AuctionBid> {
+        twaps: &[(Address, U256)],
+        timestamp: U256,
+        block: Option<CachedBlock>,
+    ) -> Result<Vec<AuctionBid>> {
         let mut loans_for_auction = vec![];
-        let (prices, eth_usd) = {
-            let prices_client = &self.prices_client.read().await;
-            (
-                &prices_client.prices.clone(),
-                prices_client.get_eth_usd_price(),
-            )
-        };
+        let gas_price = self.global_provider.provider.get_gas_price().await?;
+        let prices_client = self.prices_client.read().await;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
 
         for loan in loans {
             if loan.status != Status::Active || !loan.is_auctionable() {
@@ -198,104 +543,164 @@ impl BendDao {
                 continue;
             }
 
-            if !balances.is_usdt_lend_pool_approved || !balances.is_weth_lend_pool_approved {
-                warn!("Wallet has not approved WETH/USDT allowance");
-                continue;
-            }
+            let (value, reserve_price) = match self.checked_prices(
+                &prices_client,
+                loan.nft_asset,
+                loan.nft_token_id,
+                loan.reserve_asset,
+                now,
+            ) {
+                Ok(prices) => prices,
+                Err(reason) => {
+                    warn!(
+                        "refusing to auction {:?} #{}: {}",
+                        loan.nft_asset, loan.nft_token_id, reason
+                    );
+                    continue;
+                }
+            };
 
-            if balances.eth < U256::exp10(16) {
-                warn!("Not enough ETH for txn");
+            let Some(nft_configuration) =
+                self.health_factor_engine.nft_configuration(loan.nft_asset)
+            else {
+                warn!(
+                    "refusing to auction {:?}: no nft configuration",
+                    loan.nft_asset
+                );
                 continue;
-            }
+            };
 
-            let bid_amount = calculate_bidding_amount(loan.total_debt);
-            match loan.reserve_asset {
-                ReserveAsset::Usdt => {
-                    let best_bid = prices.get(&loan.nft_asset).unwrap() * U256::exp10(6) / eth_usd;
-                    if best_bid < bid_amount {
-                        info!(
-                            "{}\nbest_bid = {} < bid_amount = {}",
-                            loan, best_bid, bid_amount
-                        );
-                        continue;
-                    }
-                    if balances.usdt < bid_amount {
-                        warn!("Not enough USDT to initiate auction.\nUSDT balance = {} < bid_amount = {}", balances.usdt, bid_amount);
-                        continue;
-                    }
-                    balances.usdt -= bid_amount;
-                    balances.eth -= U256::exp10(16);
-                }
-                ReserveAsset::Weth => {
-                    let best_bid = *prices.get(&loan.nft_asset).unwrap();
-                    if best_bid < bid_amount {
-                        info!(
-                            "{}\nbest_bid = {} < bid_amount = {}",
-                            loan, best_bid, bid_amount
-                        );
-                        continue;
-                    }
-                    if balances.weth < bid_amount {
-                        warn!("Not enough WETH to initiate auction.\nWETH balance = {} < bid_amount = {}", balances.weth, bid_amount);
-                        continue;
-                    }
-                    balances.weth -= bid_amount;
-                    balances.eth -= U256::exp10(16);
+            let bid_amount = match self.first_bid_amount(&loan, twaps, timestamp, block).await {
+                Ok(bid_amount) => bid_amount,
+                Err(e) => {
+                    warn!(
+                        "refusing to auction {:?} #{}: {e}",
+                        loan.nft_asset, loan.nft_token_id
+                    );
+                    continue;
                 }
+            };
+            let debt = to_eth(loan.total_debt, loan.reserve_asset, reserve_price);
+
+            // as the first bidder we get the bid fine if the borrower redeems
+            let estimate = self.profit_model.estimate(&Opportunity {
+                bid: to_eth(bid_amount, loan.reserve_asset, reserve_price),
+                value,
+                gas_price,
+                bid_gas: AUCTION_GAS.into(),
+                liquidate_gas: LIQUIDATE_GAS.into(),
+                holding: nft_configuration.auction_duration_secs(),
+                redeem_fine: nft_configuration.bid_fine(debt),
+            });
+
+            if !estimate.is_profitable() {
+                info!(
+                    "not auctioning {:?} #{} for {}: {}",
+                    loan.nft_asset, loan.nft_token_id, bid_amount, estimate
+                );
+                continue;
             }
 
+            info!(
+                "auctioning {:?} #{} for {}: {}",
+                loan.nft_asset, loan.nft_token_id, bid_amount, estimate
+            );
+
             let auction_bid = AuctionBid {
                 bid_price: bid_amount,
                 nft_asset: loan.nft_asset.into(),
                 nft_token_id: loan.nft_token_id,
+                reserve_asset: loan.reserve_asset,
+                expected_profit: estimate.expected_profit(),
             };
 
             loans_for_auction.push(auction_bid)
         }
 
-        loans_for_auction
+        Ok(loans_for_auction)
     }
 
     pub async fn refresh_monitored_loans(&mut self) -> Result<()> {
-        let mut repaid_defaulted_loans_set = get_repaid_defaulted_loans()
-            .await
-            .unwrap_or_else(|_| BTreeSet::new());
+        let closed_loans = self.global_provider.store.state().await.closed_loans;
+        let mut newly_closed_loans = BTreeSet::new();
 
-        // this loan has not yet existed so not inclusive range
-        let end_loan_id: u64 = self
+        let latest_block = self
             .global_provider
-            .lend_pool_loan
-            .get_current_loan_id()
+            .provider
+            .get_block(BlockNumber::Latest)
             .await?
-            .as_u64();
-
-        let iter = (1..end_loan_id).filter(|x| !repaid_defaulted_loans_set.contains(x));
+            .ok_or_else(|| anyhow!("latest block not found"))?;
+        let timestamp = latest_block.timestamp;
+
+        self.loan_index
+            .sync(
+                &self.global_provider.lend_pool_loan,
+                latest_block.number.unwrap_or_default().as_u64(),
+                self.reorg_confirmation_depth,
+            )
+            .await?;
+        self.loan_index.save().await?;
 
-        info!("querying information for {} loans", iter.clone().count());
+        let mut health_factor_engine = HealthFactorEngine::default();
+        let mut auctioned_loans = vec![];
 
-        let all_loans = self.global_provider.get_loans_from_iter(iter, None).await?;
-        let mut loans_to_monitor = vec![];
+        for loan in self.loan_index.loans() {
+            if !loan.is_open() {
+                newly_closed_loans.insert(loan.loan_id.as_u64());
+                continue;
+            }
 
-        for loan in all_loans {
             // collections not allowed to trade in production
-            if !loan.nft_asset.is_allowed_in_production() {
+            if !NftAsset::try_from(loan.nft_asset).is_ok_and(|a| a.is_allowed_in_production()) {
                 continue;
             }
 
-            if loan.status == Status::RepaidDefaulted {
-                repaid_defaulted_loans_set.insert(loan.loan_id.as_u64());
+            match loan.to_position() {
+                Some(position) => health_factor_engine.insert_position(position),
+                None => auctioned_loans.push(loan.loan_id.as_u64()),
+            }
+        }
+
+        // whether an auction can still be bid on depends on its end time
+        // so those are the only loans we still ask the lend pool about
+        info!(
+            "querying information for {} auctioned loans",
+            auctioned_loans.len()
+        );
+
+        let positions = self
+            .global_provider
+            .get_loan_positions_from_iter(auctioned_loans.into_iter())
+            .await?;
+
+        for position in positions {
+            if position.status == Status::RepaidDefaulted {
+                newly_closed_loans.insert(position.loan_id.as_u64());
                 continue;
             }
 
-            if let Status::Auction(auction) = loan.status {
+            if let Status::Auction(auction) = position.status {
                 self.pending_auctions.add_update_auction(auction);
+                self.global_provider.record(Record::Auction(auction)).await;
             }
 
+            health_factor_engine.insert_position(position);
+        }
+
+        health_factor_engine
+            .refresh_market_data(&self.global_provider)
+            .await?;
+
+        let mut loans_to_monitor = vec![];
+
+        for loan in health_factor_engine.loans(timestamp, &[]) {
             if loan.should_monitor() {
                 loans_to_monitor.push((loan.loan_id, loan.health_factor));
             }
         }
 
+        self.health_factor_engine = health_factor_engine;
+
         loans_to_monitor.sort_by(|a, b| a.1.cmp(&b.1));
 
         self.monitored_loans = loans_to_monitor
@@ -303,21 +708,77 @@ impl BendDao {
             .map(|(loan_id, _hf)| loan_id)
             .collect();
 
-        save_repaid_defaulted_loans(&repaid_defaulted_loans_set).await?;
+        self.schedule_debt_liquidations(timestamp);
+
+        let mut records: Vec<Record> = newly_closed_loans
+            .difference(&closed_loans)
+            .map(|&loan_id| Record::ClosedLoan { loan_id })
+            .collect();
+        records.push(Record::MonitoredLoans {
+            loan_ids: self.monitored_loans.clone(),
+        });
+        self.global_provider.store.append_all(records).await?;
 
         self.log_monitored_loans().await;
 
         Ok(())
     }
 
+    /// Rebuilds the `LiquidationSchedule` with every active loan whose
+    /// health factor will cross 1 from interest within the next day
+    fn schedule_debt_liquidations(&mut self, timestamp: U256) {
+        self.liquidation_schedule.clear();
+
+        let active_loans: Vec<U256> = self
+            .health_factor_engine
+            .positions()
+            .filter(|position| position.status == Status::Active)
+            .map(|position| position.loan_id)
+            .collect();
+
+        for loan_id in active_loans {
+            if let Some(crossing) = projected_liquidation_timestamp(
+                &self.health_factor_engine,
+                loan_id,
+                timestamp,
+                ONE_DAY,
+            ) {
+                info!("loan {loan_id} projected to become auctionable at {crossing}");
+                self.liquidation_schedule.schedule(crossing, loan_id);
+            }
+        }
+    }
+
+    /// Values the tokens of monitored loans and of loans in auction individually,
+    /// the only ones we may bid on. `bend_dao` is only locked to pick them, the
+    /// valuations are fetched without holding it
+    pub async fn refresh_token_values(bend_dao: &Mutex<BendDao>) -> Result<()> {
+        let (prices_client, tokens) = {
+            let bend_dao = bend_dao.lock().await;
+            (bend_dao.prices_client.clone(), bend_dao.tokens_to_value())
+        };
+
+        PricesClient::refresh_token_values(&prices_client, &tokens).await
+    }
+
+    fn tokens_to_value(&self) -> Vec<(NftAsset, U256)> {
+        self.health_factor_engine
+            .positions()
+            .filter(|position| {
+                matches!(position.status, Status::Auction(_))
+                    || self.monitored_loans.contains(&position.loan_id)
+            })
+            .map(|position| (position.nft_asset, position.nft_token_id))
+            .collect()
+    }
+
     /// Logs monitored loans
     pub async fn log_monitored_loans(&self) {
         let mut msg = format!("~~~ MONITORED LOANS ~~~\n");
 
-        let range = self.monitored_loans.iter().map(|loan_id| loan_id.as_u64());
         let mut loans = self
             .global_provider
-            .get_loans_from_iter(range, None)
+            .get_cached_loans(&self.monitored_loans, &[], None)
             .await
             .unwrap();
         loans.sort_by_key(|x| x.health_factor);
@@ -334,36 +795,208 @@ impl BendDao {
         info!("{msg}");
     }
 
-    /// Bids first auction
-    pub async fn verify_and_package_outbids(
-        &mut self,
-        auctions: &Vec<Auction>,
-    ) -> Result<Vec<BundleRequest>> {
-        let mut bundles = Vec::new();
+    /// Smallest first bid on `loan` the lend pool accepts at `timestamp`, in the `block` env
+    async fn first_bid_amount(
+        &self,
+        loan: &Loan,
+        twaps: &[(Address, U256)],
+        timestamp: U256,
+        block: Option<CachedBlock>,
+    ) -> Result<U256> {
+        let position = self
+            .health_factor_engine
+            .position(loan.loan_id)
+            .ok_or_else(|| anyhow!("loan {} is not tracked", loan.loan_id))?;
+        let debt = self.projected_debt(position, timestamp)?;
+
+        let (liquidate_price, payback_amount) = self
+            .global_provider
+            .get_nft_liquidate_price(loan.nft_asset, loan.nft_token_id, twaps, block)
+            .await?;
 
-        let (prices, eth_usd_price) = {
-            let prices_client = self.prices_client.read().await;
+        Ok(min_first_bid(debt, liquidate_price, payback_amount))
+    }
+
+    /// Smallest bid that outbids `auction` up to its end
+    fn outbid_amount(&self, auction: &Auction) -> Result<U256> {
+        // auctioned loans leave the health factor engine, the index still has them
+        let position = self
+            .loan_index
+            .open_loan_of(auction.nft_asset.into(), auction.nft_token_id)
+            .and_then(|loan| loan.to_auction_position(auction))
+            .ok_or_else(|| anyhow!("loan is not tracked"))?;
 
-            (&prices_client.prices.clone(), prices_client.eth_usd_price)
+        // debt only grows so the last second of the auction is the most it can be
+        let debt = self.projected_debt(&position, auction.bid_end_timestamp)?;
+
+        Ok(min_outbid(debt, auction.current_bid))
+    }
+
+    fn projected_debt(&self, position: &LoanPosition, timestamp: U256) -> Result<U256> {
+        self.health_factor_engine
+            .total_debt(position, timestamp)
+            .ok_or_else(|| anyhow!("{:?} reserve is not loaded", position.reserve_asset))
+    }
+
+    /// Timestamp `block` will have, the next block if it isn't set
+    async fn expected_timestamp(&self, block: Option<U64>) -> Result<U256> {
+        let latest = self
+            .global_provider
+            .provider
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| anyhow!("latest block is missing"))?;
+
+        let blocks_ahead = match (block, latest.number) {
+            (Some(block), Some(latest)) if block > latest => (block - latest).as_u64(),
+            _ => 1,
         };
 
+        Ok(latest.timestamp + blocks_ahead * BLOCK_TIME)
+    }
+
+    /// What a token can be sold for and the price of `reserve_asset`, both in ETH and
+    /// as long as they pass the `PriceGuard`. Checked against the twap the health
+    /// factor engine last saw
+    fn checked_prices(
+        &self,
+        prices_client: &PricesClient,
+        nft_asset: NftAsset,
+        nft_token_id: U256,
+        reserve_asset: ReserveAsset,
+        now: u64,
+    ) -> Result<(U256, U256), PriceRejection> {
+        let twap = self.health_factor_engine.twap(nft_asset);
+        let value = prices_client.checked_value(nft_asset, nft_token_id, twap, now)?;
+
+        let reserve_price = match reserve_asset {
+            ReserveAsset::Weth => U256::exp10(18),
+            ReserveAsset::Usdt => prices_client.checked_eth_usd_price(now)?,
+        };
+
+        Ok((value, reserve_price))
+    }
+
+    /// Logs what liquidating auctions we won is expected to make, the bid is spent
+    /// so they are liquidated whatever the outcome
+    pub async fn log_liquidations(&self, auctions: &[Auction]) -> Result<()> {
+        if auctions.is_empty() {
+            return Ok(());
+        }
+
+        let gas_price = self.global_provider.provider.get_gas_price().await?;
+        let prices_client = self.prices_client.read().await;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
         for auction in auctions {
-            let mut nft_best_bid_price = *prices.get(&auction.nft_asset).unwrap();
+            let (value, reserve_price) = match self.checked_prices(
+                &prices_client,
+                auction.nft_asset,
+                auction.nft_token_id,
+                auction.reserve_asset,
+                now,
+            ) {
+                Ok(prices) => prices,
+                Err(reason) => {
+                    warn!(
+                        "liquidating {:?} #{} without a trusted price: {}",
+                        auction.nft_asset, auction.nft_token_id, reason
+                    );
+                    continue;
+                }
+            };
 
-            if auction.reserve_asset == ReserveAsset::Usdt {
-                nft_best_bid_price = nft_best_bid_price * U256::exp10(6) / eth_usd_price;
+            let estimate = self.profit_model.estimate_liquidation(
+                to_eth(auction.current_bid, auction.reserve_asset, reserve_price),
+                value,
+                gas_price,
+            );
+
+            let msg = format!(
+                "liquidating {:?} #{}: {}",
+                auction.nft_asset, auction.nft_token_id, estimate
+            );
+            if estimate.is_profitable() {
+                info!("{msg}");
+            } else {
+                warn!("{msg}");
             }
+        }
+
+        Ok(())
+    }
+
+    /// Bundles an outbid for every auction worth it, each with the auction it bids on
+    pub async fn verify_and_package_outbids(
+        &mut self,
+        auctions: &[Auction],
+    ) -> Result<Vec<(Auction, BidBundle)>> {
+        let mut bundles = Vec::new();
+
+        let gas_price = self.global_provider.provider.get_gas_price().await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let prices: Vec<_> = {
+            let prices_client = self.prices_client.read().await;
+            auctions
+                .iter()
+                .map(|auction| {
+                    self.checked_prices(
+                        &prices_client,
+                        auction.nft_asset,
+                        auction.nft_token_id,
+                        auction.reserve_asset,
+                        now,
+                    )
+                })
+                .collect()
+        };
 
-            let outbid = auction.current_bid * 101 / 100;
+        for (auction, prices) in auctions.iter().zip(prices) {
+            let (value, reserve_price) = match prices {
+                Ok(prices) => prices,
+                Err(reason) => {
+                    warn!(
+                        "refusing to bid on {:?} #{}: {}",
+                        auction.nft_asset, auction.nft_token_id, reason
+                    );
+                    continue;
+                }
+            };
 
-            if nft_best_bid_price > outbid {
-                // not sending as one bundle bc we may get a revert chain
-                // if one bid get frontrun, all bids will revert
-                bundles.push(self.send_bid(auction, outbid).await?)
+            let outbid = match self.outbid_amount(auction) {
+                Ok(outbid) => outbid,
+                Err(e) => {
+                    warn!(
+                        "refusing to bid on {:?} #{}: {e}",
+                        auction.nft_asset, auction.nft_token_id
+                    );
+                    continue;
+                }
+            };
+
+            // the first bidder gets the redeem fine, not us
+            let estimate = self.profit_model.estimate(&Opportunity {
+                bid: to_eth(outbid, auction.reserve_asset, reserve_price),
+                value,
+                gas_price,
+                bid_gas: AUCTION_GAS.into(),
+                liquidate_gas: LIQUIDATE_GAS.into(),
+                holding: auction.bid_end_timestamp.saturating_sub(U256::from(now)),
+                redeem_fine: U256::zero(),
+            });
+
+            if estimate.is_profitable() {
+                info!(
+                    "outbidding {:?} #{} with {}: {}",
+                    auction.nft_asset, auction.nft_token_id, outbid, estimate
+                );
+                // one bundle per auction since each targets its own bid end
+                let auction_bid = AuctionBid::new(auction, outbid, estimate.expected_profit());
+                bundles.push((*auction, self.send_bid(auction, auction_bid).await?))
             } else {
                 info!(
-                    "bid on {:?} #{} was not profitable for {} as price is: {}",
-                    auction.nft_asset, auction.nft_token_id, outbid, nft_best_bid_price
+                    "bid on {:?} #{} was not profitable for {}: {}",
+                    auction.nft_asset, auction.nft_token_id, outbid, estimate
                 );
             }
         }
@@ -371,14 +1004,18 @@ impl BendDao {
         Ok(bundles)
     }
 
-    async fn send_bid(&self, auction: &Auction, bid: U256) -> Result<BundleRequest> {
-        let bundle = BundleRequest::new()
-            .set_max_timestamp(auction.bid_end_timestamp.as_u64())
+    async fn send_bid(&self, auction: &Auction, auction_bid: AuctionBid) -> Result<BidBundle> {
+        self.global_provider
+            .record(Record::Bid(BidRecord::new(&auction_bid)))
+            .await;
+
+        Ok(BidBundle {
+            bids: vec![auction_bid],
+            max_timestamp: Some(auction.bid_end_timestamp.as_u64()),
             // 14 is arbitrary
             // can change in future
-            .set_min_timestamp(auction.bid_end_timestamp.as_u64() - 14);
-        self.global_provider
-            .create_auction_bundle(bundle, vec![AuctionBid::new(auction, bid)], true)
-            .await
+            min_timestamp: Some(auction.bid_end_timestamp.as_u64() - 14),
+            ..Default::default()
+        })
     }
 }
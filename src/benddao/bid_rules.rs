@@ -0,0 +1,25 @@
+use crate::{constants::MIN_BID_DELTA, math::percent_mul};
+use ethers::types::U256;
+
+/// Smallest first bid `LendPool.auction` accepts once the debt has grown to `debt`.
+///
+/// `liquidate_price` and `payback_amount` come from `getNftLiquidatePrice` at an earlier
+/// block. When the NFT is worth less than the debt the lend pool prices the liquidation
+/// at the debt plus the bid delta, which then has to be projected too. That's also the
+/// case once the debt has grown past a liquidate price the NFT set.
+pub fn min_first_bid(debt: U256, liquidate_price: U256, payback_amount: U256) -> U256 {
+    if liquidate_price == payback_amount + min_bid_delta(payback_amount) || liquidate_price < debt {
+        return debt + min_bid_delta(debt);
+    }
+
+    liquidate_price
+}
+
+/// Smallest bid that outbids `previous_bid` once the debt has grown to `debt`
+pub fn min_outbid(debt: U256, previous_bid: U256) -> U256 {
+    (previous_bid + min_bid_delta(debt)).max(debt)
+}
+
+fn min_bid_delta(debt: U256) -> U256 {
+    percent_mul(debt, MIN_BID_DELTA.into())
+}
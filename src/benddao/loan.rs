@@ -3,6 +3,7 @@ use crate::constants::*;
 use anyhow::{bail, Result};
 use core::fmt;
 use ethers::types::*;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Formatter};
 
 #[derive(Debug, Clone)]
@@ -46,12 +47,21 @@ impl Loan {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Hash, Eq, Serialize, Deserialize)]
 pub enum ReserveAsset {
     Weth,
     Usdt,
 }
 
+impl ReserveAsset {
+    pub fn decimals(&self) -> u32 {
+        match self {
+            ReserveAsset::Weth => 18,
+            ReserveAsset::Usdt => 6,
+        }
+    }
+}
+
 impl TryFrom<Address> for ReserveAsset {
     type Error = anyhow::Error;
 
@@ -67,7 +77,16 @@ impl TryFrom<Address> for ReserveAsset {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Hash, Eq)]
+impl From<ReserveAsset> for Address {
+    fn from(value: ReserveAsset) -> Address {
+        match value {
+            ReserveAsset::Weth => WETH.into(),
+            ReserveAsset::Usdt => USDT.into(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Eq, Serialize, Deserialize)]
 pub enum NftAsset {
     Azuki,
     Bayc,
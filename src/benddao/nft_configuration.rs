@@ -0,0 +1,58 @@
+use crate::{constants::ONE_HOUR, math::percent_mul};
+use ethers::types::U256;
+
+/// `NftConfigurationMap.data` of a collection, as returned by
+/// `LendPool.getNftConfiguration`. Percentages have two decimals
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NftConfiguration {
+    pub ltv: U256,
+    pub liquidation_threshold: U256,
+    pub liquidation_bonus: U256,
+    /// hours the borrower can redeem after the first bid
+    pub redeem_duration: U256,
+    /// hours the auction runs after the first bid
+    pub auction_duration: U256,
+    /// share of the debt the borrower pays the first bidder on redeem
+    pub redeem_fine: U256,
+    /// share of the debt the borrower must repay to redeem
+    pub redeem_threshold: U256,
+    /// floor of the redeem fine, in 0.0001 ETH
+    pub min_bid_fine: U256,
+}
+
+impl NftConfiguration {
+    /// seconds the borrower can redeem after the first bid
+    pub fn redeem_duration_secs(&self) -> U256 {
+        self.redeem_duration * ONE_HOUR
+    }
+
+    /// seconds the auction runs after the first bid
+    pub fn auction_duration_secs(&self) -> U256 {
+        self.auction_duration * ONE_HOUR
+    }
+
+    /// What a redeem pays the first bidder on a loan of `debt`, both in ETH (1e18).
+    /// Mirrors `GenericLogic.calculateLoanBidFine`
+    pub fn bid_fine(&self, debt: U256) -> U256 {
+        let min_bid_fine = self.min_bid_fine * U256::exp10(14);
+
+        percent_mul(debt, self.redeem_fine).max(min_bid_fine)
+    }
+}
+
+impl From<U256> for NftConfiguration {
+    fn from(data: U256) -> NftConfiguration {
+        let bits = |offset: usize, len: usize| (data >> offset) & ((U256::one() << len) - 1);
+
+        NftConfiguration {
+            ltv: bits(0, 16),
+            liquidation_threshold: bits(16, 16),
+            liquidation_bonus: bits(32, 16),
+            redeem_duration: bits(64, 8),
+            auction_duration: bits(72, 8),
+            redeem_fine: bits(80, 16),
+            redeem_threshold: bits(96, 16),
+            min_bid_fine: bits(112, 16),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::health_factor::HealthFactorEngine;
+use ethers::types::U256;
+
+/// First timestamp in `[from, from + horizon]` at which the health factor of `loan_id`
+/// falls below `1e18` from interest accrual alone, i.e. with the current borrow rate
+/// and twap unchanged. `None` if it stays healthy for the whole horizon or the engine
+/// can't value the loan.
+pub fn projected_liquidation_timestamp(
+    engine: &HealthFactorEngine,
+    loan_id: U256,
+    from: U256,
+    horizon: u64,
+) -> Option<U256> {
+    let position = engine.position(loan_id)?;
+    let is_unhealthy = |timestamp: U256| -> Option<bool> {
+        Some(engine.health_factor(position, timestamp, &[])? < U256::exp10(18))
+    };
+
+    if is_unhealthy(from)? {
+        return Some(from);
+    }
+
+    let (mut healthy, mut unhealthy) = (from, from + horizon);
+
+    if !is_unhealthy(unhealthy)? {
+        return None;
+    }
+
+    // debt only grows with time so the health factor is monotonic
+    while unhealthy - healthy > U256::one() {
+        let mid = healthy + (unhealthy - healthy) / 2;
+        if is_unhealthy(mid)? {
+            unhealthy = mid;
+        } else {
+            healthy = mid;
+        }
+    }
+
+    Some(unhealthy)
+}
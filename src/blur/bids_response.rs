@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Result};
+use ethers::{types::U256, utils::parse_ether};
+use serde::Deserialize;
+
+/// `v1/collections/{address}/executable-bids`
+#[derive(Debug, Deserialize)]
+pub struct ExecutableBidsResponse {
+    #[serde(rename = "priceLevels")]
+    price_levels: Vec<PriceLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PriceLevel {
+    /// in ETH, e.g. "25.5"
+    price: String,
+    #[serde(rename = "executableSize")]
+    executable_size: u64,
+}
+
+impl ExecutableBidsResponse {
+    /// Best bid that can be sold into
+    pub fn get_best_bid(&self) -> Result<U256> {
+        let mut best_bid = None;
+
+        for level in &self.price_levels {
+            if level.executable_size == 0 {
+                continue;
+            }
+
+            best_bid = best_bid.max(Some(parse_ether(&level.price)?));
+        }
+
+        best_bid.ok_or_else(|| anyhow!("no executable bids found"))
+    }
+}
@@ -0,0 +1 @@
+pub mod bids_response;
@@ -0,0 +1,348 @@
+use crate::constants::*;
+use anyhow::{anyhow, bail, Result};
+use ethers::{
+    core::rand::thread_rng,
+    signers::LocalWallet,
+    types::{Bytes, H256},
+    utils::hex,
+};
+use ethers_flashbots::{BundleRequest, Relay};
+use futures::future::join_all;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    time::timeout,
+};
+use url::Url;
+
+/// where the bundle signing key is kept when it isn't configured
+pub const BUNDLE_SIGNER_PATH: &str = "data/bundle-signer.key";
+
+/// A builder bundles are sent to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Builder {
+    /// matched against the extra data of the blocks it builds
+    pub name: String,
+    pub url: Url,
+    pub enabled: bool,
+}
+
+impl Builder {
+    /// Parses `url` or `name=url`, the name defaults to the url's domain
+    pub fn parse(entry: &str) -> Result<Builder> {
+        let (name, url) = match entry.split_once('=') {
+            Some((name, url)) if !name.contains('/') => (Some(name.trim()), url.trim()),
+            _ => (None, entry.trim()),
+        };
+        let url = Url::parse(url)?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => domain_name(&url).ok_or_else(|| anyhow!("{url} has no domain"))?,
+        };
+
+        Ok(Builder {
+            name,
+            url,
+            enabled: true,
+        })
+    }
+
+    /// Builders of `entries`, or `DEFAULT_BUILDERS` if there are none. Those named or
+    /// listed in `disabled` aren't sent bundles
+    pub fn from_config(entries: &[String], disabled: &[String]) -> Vec<Builder> {
+        let mut entries: Vec<&str> = entries
+            .iter()
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        if entries.is_empty() {
+            entries = DEFAULT_BUILDERS.to_vec();
+        }
+
+        entries
+            .into_iter()
+            .filter_map(|entry| match Builder::parse(entry) {
+                Ok(builder) => Some(builder),
+                Err(e) => {
+                    warn!("ignoring builder {entry:?}: {e}");
+                    None
+                }
+            })
+            .map(|mut builder| {
+                builder.enabled = !disabled.iter().any(|disabled| {
+                    let disabled = disabled.trim();
+                    disabled == builder.name
+                        || disabled == builder.url.as_str().trim_end_matches('/')
+                });
+                builder
+            })
+            .collect()
+    }
+
+    /// Whether this builder signed a block with `extra_data`
+    pub fn built(&self, extra_data: &Bytes) -> bool {
+        String::from_utf8_lossy(extra_data)
+            .to_lowercase()
+            .contains(&self.name.to_lowercase())
+    }
+}
+
+/// second level domain of `url`, e.g. `beaverbuild` for `https://rpc.beaverbuild.org`
+fn domain_name(url: &Url) -> Option<String> {
+    let mut labels = url.host_str()?.rsplit('.');
+    labels.next();
+    labels.next().map(str::to_string)
+}
+
+/// How a builder handled the bundles we sent it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct BuilderStats {
+    pub sent: u64,
+    pub accepted: u64,
+    /// bundles that landed in a block it built
+    pub included: u64,
+    pub consecutive_failures: u64,
+    /// of accepted bundles, in milliseconds
+    pub total_latency: u64,
+    /// unix timestamp until which the builder is skipped
+    pub dropped_until: Option<u64>,
+}
+
+impl BuilderStats {
+    pub fn record_response(&mut self, accepted: bool, latency: Duration, now: u64) {
+        self.sent += 1;
+
+        if accepted {
+            self.accepted += 1;
+            self.consecutive_failures = 0;
+            self.total_latency += latency.as_millis() as u64;
+            self.dropped_until = None;
+        } else {
+            self.consecutive_failures += 1;
+            if self.consecutive_failures >= MAX_BUILDER_FAILURES {
+                self.dropped_until = Some(now + BUILDER_COOLDOWN);
+            }
+        }
+    }
+
+    /// in milliseconds, `None` until a bundle is accepted
+    pub fn average_latency(&self) -> Option<u64> {
+        self.total_latency.checked_div(self.accepted)
+    }
+
+    /// Dead builders are retried once their cooldown is over
+    pub fn is_dropped(&self, now: u64) -> bool {
+        self.dropped_until.is_some_and(|until| now < until)
+    }
+}
+
+impl Display for BuilderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} included, {}/{} accepted",
+            self.included, self.accepted, self.sent
+        )?;
+
+        if let Some(latency) = self.average_latency() {
+            write!(f, ", {latency}ms")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How one builder answered `eth_sendBundle`
+#[derive(Debug)]
+pub struct BuilderResponse {
+    pub builder: String,
+    pub result: Result<Option<H256>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendBundleResponse {
+    bundle_hash: Option<H256>,
+}
+
+/// Sends bundles to the configured builders and keeps track of how each one does
+#[derive(Debug)]
+pub struct BuilderRegistry {
+    builders: Vec<Builder>,
+    relays: Vec<Relay<LocalWallet>>,
+    /// one per builder
+    stats: Mutex<Vec<BuilderStats>>,
+}
+
+impl BuilderRegistry {
+    /// Bundles are signed with `signer` so builders can tie our reputation to it
+    pub fn new(builders: Vec<Builder>, signer: LocalWallet) -> BuilderRegistry {
+        let relays = builders
+            .iter()
+            .map(|builder| Relay::new(builder.url.clone(), Some(signer.clone())))
+            .collect();
+        let stats = Mutex::new(vec![BuilderStats::default(); builders.len()]);
+
+        BuilderRegistry {
+            builders,
+            relays,
+            stats,
+        }
+    }
+
+    pub fn builders(&self) -> &[Builder] {
+        &self.builders
+    }
+
+    /// Picks up the stats `saved` by builder name, builders that aren't in it start afresh
+    pub fn restore(&self, saved: &BTreeMap<String, BuilderStats>) {
+        let mut stats = self.stats.lock().unwrap();
+        for (builder, stats) in self.builders.iter().zip(stats.iter_mut()) {
+            if let Some(saved) = saved.get(&builder.name) {
+                *stats = *saved;
+            }
+        }
+    }
+
+    /// Stats of the builder called `name`
+    pub fn stats_of(&self, name: &str) -> Option<BuilderStats> {
+        let i = self
+            .builders
+            .iter()
+            .position(|builder| builder.name == name)?;
+
+        Some(self.stats.lock().unwrap()[i])
+    }
+
+    pub fn stats(&self) -> Vec<(Builder, BuilderStats)> {
+        let stats = self.stats.lock().unwrap();
+        self.builders
+            .iter()
+            .cloned()
+            .zip(stats.iter().copied())
+            .collect()
+    }
+
+    /// Indexes of the enabled builders that aren't dropped, those that included the most
+    /// of our bundles first, then the fastest
+    pub fn ranked(&self, now: u64) -> Vec<usize> {
+        let stats = self.stats.lock().unwrap();
+        let mut ranked: Vec<usize> = (0..self.builders.len())
+            .filter(|&i| self.builders[i].enabled && !stats[i].is_dropped(now))
+            .collect();
+
+        ranked.sort_by_key(|&i| {
+            (
+                std::cmp::Reverse(stats[i].included),
+                stats[i].average_latency().unwrap_or(u64::MAX),
+            )
+        });
+
+        ranked
+    }
+
+    /// Sends `bundle` to the ranked builders, the most reputable first
+    pub async fn send_bundle(&self, bundle: &BundleRequest) -> Result<Vec<BuilderResponse>> {
+        if bundle.block().is_none() {
+            bail!("bundle has no target block");
+        }
+
+        let now = chrono::Utc::now().timestamp() as u64;
+        let ranked = self.ranked(now);
+        if ranked.is_empty() {
+            bail!("no builder to send the bundle to");
+        }
+
+        let requests = ranked.iter().map(|&i| async move {
+            let start = Instant::now();
+            let request = self.relays[i].request("eth_sendBundle", [bundle]);
+            let result = match timeout(Duration::from_secs(BUILDER_TIMEOUT), request).await {
+                Ok(Ok(response)) => {
+                    Ok(response.and_then(|response: SendBundleResponse| response.bundle_hash))
+                }
+                Ok(Err(e)) => Err(anyhow!(e)),
+                Err(_) => Err(anyhow!("timed out")),
+            };
+
+            (i, start.elapsed(), result)
+        });
+        let responses = join_all(requests).await;
+
+        let mut stats = self.stats.lock().unwrap();
+        let responses = responses
+            .into_iter()
+            .map(|(i, latency, result)| {
+                stats[i].record_response(result.is_ok(), latency, now);
+                if stats[i].is_dropped(now) {
+                    warn!(
+                        "dropping builder {} for {BUILDER_COOLDOWN}s after {} failures",
+                        self.builders[i].name, stats[i].consecutive_failures
+                    );
+                }
+
+                BuilderResponse {
+                    builder: self.builders[i].name.clone(),
+                    result,
+                }
+            })
+            .collect();
+
+        Ok(responses)
+    }
+
+    /// Credits the builder that signed a block with `extra_data`, returns its name
+    pub fn record_inclusion(&self, extra_data: &Bytes) -> Option<String> {
+        let i = self
+            .builders
+            .iter()
+            .position(|builder| builder.built(extra_data))?;
+        self.stats.lock().unwrap()[i].included += 1;
+
+        Some(self.builders[i].name.clone())
+    }
+}
+
+/// The configured bundle signing key, otherwise the one at `path`, created the first time
+pub async fn load_bundle_signer(key: Option<&str>, path: impl AsRef<Path>) -> Result<LocalWallet> {
+    if let Some(key) = key.map(str::trim).filter(|key| !key.is_empty()) {
+        return Ok(LocalWallet::from_str(key)?);
+    }
+
+    let path = path.as_ref();
+    if fs::try_exists(path).await? {
+        let key = fs::read_to_string(path).await?;
+        return Ok(LocalWallet::from_str(key.trim())?);
+    }
+
+    let signer = LocalWallet::new(&mut thread_rng());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+
+    // only readable by us from the start, and never left half written
+    let tmp_path = path.with_extension("key.tmp");
+    let _ = fs::remove_file(&tmp_path).await;
+    let mut tmp = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .await?;
+    tmp.write_all(hex::encode(signer.signer().to_bytes()).as_bytes())
+        .await?;
+    tmp.sync_all().await?;
+    fs::rename(&tmp_path, path).await?;
+    info!("created bundle signing key at {}", path.display());
+
+    Ok(signer)
+}
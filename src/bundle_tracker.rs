@@ -0,0 +1,298 @@
+use crate::{
+    benddao::loan::NftAsset,
+    constants::*,
+    global_provider::GlobalProvider,
+    lend_pool::{GetNftAuctionDataCall, GetNftAuctionDataReturn},
+    simulator::bundle::reverting_tx_hashes,
+    store::{BundleRecord, Record},
+    types::AuctionBid,
+    Config,
+};
+use anyhow::{anyhow, Result};
+use ethers::{
+    providers::Middleware,
+    signers::Signer,
+    types::{Address, BlockNumber, Transaction, U256, U64},
+};
+use ethers_flashbots::BundleRequest;
+use futures::future::try_join_all;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// How a tracked bundle ended
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleOutcome {
+    Included,
+    /// a competing bid landed first
+    Outbid,
+    /// the bids would revert, in simulation or against the lend pool's rules
+    Reverted,
+    /// no block of the window included it
+    Expired,
+    /// it couldn't be signed for the next attempt, so it wasn't sent
+    Aborted(String),
+}
+
+impl Display for BundleOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BundleOutcome::Included => write!(f, "included"),
+            BundleOutcome::Outbid => write!(f, "outbid"),
+            BundleOutcome::Reverted => write!(f, "reverted"),
+            BundleOutcome::Expired => write!(f, "expired"),
+            BundleOutcome::Aborted(e) => write!(f, "aborted: {e}"),
+        }
+    }
+}
+
+/// How bids on several loans are bundled
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BundlePolicy {
+    /// one bundle, the bids may revert without invalidating it so a frontrun bid
+    /// doesn't take the others down
+    #[default]
+    Batch,
+    /// one bundle per bid behind the same leading txs, sent one after another. Each is
+    /// signed with its own funding txs and nonces once the one before it resolved, so any
+    /// of them can land whether the others did or not. A bid waits for the ones before it
+    Split,
+}
+
+/// Bids to bundle, signed again with fresh nonces and fees for every block targeted
+#[derive(Debug, Clone, Default)]
+pub struct BidBundle {
+    /// txs of others the bids depend on, e.g. the `NftOracle` update
+    pub leading_txs: Vec<Transaction>,
+    pub bids: Vec<AuctionBid>,
+    /// `NftOracle` prices the bids are validated against
+    pub twaps: Vec<(Address, U256)>,
+    /// first block targeted, the next one if unset
+    pub block: Option<U64>,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+}
+
+impl BidBundle {
+    /// The bundles to send for `policy`, see `GlobalProvider::send_and_handle_bundles`
+    pub fn split(self, policy: BundlePolicy) -> Vec<BidBundle> {
+        if policy == BundlePolicy::Batch || self.bids.len() <= 1 {
+            return vec![self];
+        }
+
+        self.bids
+            .iter()
+            .map(|bid| BidBundle {
+                bids: vec![bid.clone()],
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// Whether the on chain bid moved from `before` to `now` because of someone else
+pub fn is_outbid(before: (Address, U256), now: (Address, U256), us: Address) -> bool {
+    let (bidder, _) = now;
+    now != before && !bidder.is_zero() && bidder != us
+}
+
+/// Resubmits bundles for a window of blocks until they land or become moot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BundleTracker {
+    pub target_blocks: u64,
+}
+
+impl BundleTracker {
+    pub fn from_config(config: &Config) -> BundleTracker {
+        BundleTracker {
+            target_blocks: config.bundle_target_blocks.max(1),
+        }
+    }
+
+    /// Sends `bundle` until it's included, outbid, reverts or the window is over,
+    /// and records how it ended
+    pub async fn track(
+        &self,
+        global_provider: &GlobalProvider,
+        mut bundle: BidBundle,
+    ) -> Result<BundleOutcome> {
+        let us = global_provider.local_wallet.address();
+        let mut bids_before = self.current_bids(global_provider, &bundle.bids).await?;
+
+        let mut record = BundleRecord {
+            block: None,
+            transactions: vec![],
+            bundle_hash: None,
+            included: false,
+            outcome: None,
+            timestamp: 0,
+        };
+        let mut next_block = bundle.block.unwrap_or_default();
+        let mut outcome = BundleOutcome::Expired;
+
+        for attempt in 0..=self.target_blocks {
+            // outbid bids are left out of the next attempts, the bundle is moot once all are
+            let bids_now = self.current_bids(global_provider, &bundle.bids).await?;
+            let bids = std::mem::take(&mut bundle.bids);
+            let before = std::mem::take(&mut bids_before);
+            for ((bid, before), now) in bids.into_iter().zip(before).zip(bids_now) {
+                if is_outbid(before, now, us) {
+                    info!(
+                        "bid on {:?} #{} was outbid",
+                        bid.nft_asset, bid.nft_token_id
+                    );
+                } else {
+                    bundle.bids.push(bid);
+                    bids_before.push(before);
+                }
+            }
+            if bundle.bids.is_empty() {
+                outcome = BundleOutcome::Outbid;
+                break;
+            }
+
+            // the last pass only tells an outbid from an expired bundle
+            if attempt == self.target_blocks {
+                break;
+            }
+
+            let latest = global_provider
+                .provider
+                .get_block(BlockNumber::Latest)
+                .await?
+                .ok_or_else(|| anyhow!("latest block is missing"))?;
+            let latest_number = latest.number.unwrap_or_default();
+            let block = next_block.max(latest_number + 1);
+            let blocks_ahead = (block - latest_number).as_u64();
+            let timestamp = latest.timestamp.as_u64() + blocks_ahead * BLOCK_TIME;
+
+            if bundle.max_timestamp.is_some_and(|max| timestamp > max) {
+                break;
+            }
+
+            let request = match self.sign(global_provider, &bundle, block).await {
+                Ok(request) => request,
+                Err(e) => {
+                    warn!("not resubmitting bundle: {e}");
+                    outcome = BundleOutcome::Aborted(e.to_string());
+                    break;
+                }
+            };
+
+            let reverting_tx_hashes = reverting_tx_hashes(&request);
+            match global_provider.simulate_bundle(&request).await {
+                Ok(simulation) if simulation.is_success_with(&reverting_tx_hashes) => {
+                    info!("bundle simulated: {simulation}")
+                }
+                Ok(simulation) => {
+                    warn!("bundle reverts in simulation: {simulation}");
+                    release_nonces(global_provider, &request);
+                    outcome = BundleOutcome::Reverted;
+                    break;
+                }
+                Err(e) => warn!("could not simulate bundle: {e}"),
+            }
+
+            let included = match global_provider.send_to_builders(&request).await {
+                Ok(bundle_hash) => {
+                    record.block = Some(block);
+                    record.transactions = request.transaction_hashes();
+                    record.bundle_hash = bundle_hash;
+
+                    global_provider.wait_for_bundle(&request, bundle_hash).await
+                }
+                Err(e) => Err(e),
+            };
+
+            match included {
+                Ok(Some(bundle_hash)) => {
+                    record.bundle_hash = Some(bundle_hash);
+                    outcome = BundleOutcome::Included;
+                    break;
+                }
+                // the nonces are reused by the next attempt
+                Ok(None) => release_nonces(global_provider, &request),
+                Err(e) => {
+                    release_nonces(global_provider, &request);
+                    return Err(e);
+                }
+            }
+
+            debug!("bundle missed block {block}, attempt {}", attempt + 1);
+            next_block = block + 1;
+        }
+
+        info!("bundle {outcome}");
+
+        if outcome != BundleOutcome::Included {
+            if let Err(e) = global_provider.cancel_nonce_gaps().await {
+                error!("{e}");
+            }
+        }
+
+        record.included = outcome == BundleOutcome::Included;
+        record.outcome = Some(outcome.clone());
+        record.timestamp = chrono::Utc::now().timestamp() as u64;
+        global_provider.record(Record::Bundle(record)).await;
+
+        Ok(outcome)
+    }
+
+    /// Signs the bids of `bundle` for `block`, after the leading txs that haven't landed yet
+    async fn sign(
+        &self,
+        global_provider: &GlobalProvider,
+        bundle: &BidBundle,
+        block: U64,
+    ) -> Result<BundleRequest> {
+        let mut request = BundleRequest::new().set_block(block);
+        if let Some(min_timestamp) = bundle.min_timestamp {
+            request = request.set_min_timestamp(min_timestamp);
+        }
+        if let Some(max_timestamp) = bundle.max_timestamp {
+            request = request.set_max_timestamp(max_timestamp);
+        }
+
+        for tx in &bundle.leading_txs {
+            let receipt = global_provider
+                .provider
+                .get_transaction_receipt(tx.hash)
+                .await?;
+            if receipt.is_none() {
+                request.add_transaction(tx.clone());
+            }
+        }
+
+        global_provider
+            .create_auction_bundle(request, bundle.bids.clone(), &bundle.twaps)
+            .await
+    }
+
+    /// Current bidder and bid price of the auction of every bid
+    async fn current_bids(
+        &self,
+        global_provider: &GlobalProvider,
+        bids: &[AuctionBid],
+    ) -> Result<Vec<(Address, U256)>> {
+        try_join_all(bids.iter().map(|bid| async move {
+            let nft_asset = NftAsset::try_from(bid.nft_asset)?;
+            let call = GetNftAuctionDataCall {
+                nft_asset: nft_asset.into(),
+                nft_token_id: bid.nft_token_id,
+            };
+            let auction_data: GetNftAuctionDataReturn =
+                global_provider.call_lend_pool(call, &[], None).await?;
+
+            Ok((auction_data.bidder_address, auction_data.bid_price))
+        }))
+        .await
+    }
+}
+
+fn release_nonces(global_provider: &GlobalProvider, request: &BundleRequest) {
+    if let Err(e) = global_provider.release_bundle_nonces(request) {
+        error!("failed to release the nonces of a bundle: {e}");
+    }
+}
@@ -8,3 +8,102 @@ pub const BEND_INCEPTION_BLOCK: u64 = 14_417_009;
 
 /// `1.05e18`
 pub const HEALTH_FACTOR_THRESHOLD_TO_MONITOR: &str = "0xe92596fd6290000";
+
+/// `1.01e18`. loans the health factor engine puts below this are re-checked
+/// on chain before auctioning, the margin covers reserve rates changing since the last refresh
+pub const HEALTH_FACTOR_THRESHOLD_TO_RECHECK: &str = "0xe043da617250000";
+
+/// blocks per `eth_getLogs` request when backfilling the loan index
+pub const LOG_QUERY_BLOCK_RANGE: u64 = 100_000;
+
+/// blocks after which a `LendPool` log is considered final, two epochs
+pub const DEFAULT_REORG_CONFIRMATION_DEPTH: u64 = 64;
+
+/// seconds a price is bid with after its refresh, two missed hourly refreshes
+pub const DEFAULT_MAX_PRICE_AGE: u64 = 7_200;
+
+/// weight of the `NftOracle` twap among the collection price sources. With any weight it
+/// drags the median down to the twap once few marketplaces quote, so it's left out and
+/// only checked against by the `PriceGuard`
+pub const DEFAULT_NFT_ORACLE_WEIGHT: u64 = 0;
+
+/// how far a collection price may be from the `NftOracle` twap, 30% with two decimals
+pub const DEFAULT_MAX_TWAP_DEVIATION: u64 = 3_000;
+
+/// most a token is valued at, as a multiple of its collection price, 2x with two decimals
+pub const DEFAULT_MAX_TOKEN_VALUE_MULTIPLE: u64 = 20_000;
+
+/// how far a price may move between two refreshes, 20% with two decimals
+pub const DEFAULT_MAX_PRICE_CHANGE: u64 = 2_000;
+
+/// how far the `ReserveOracle` USDT price may be from coinmarketcap's, 5% with two decimals
+pub const DEFAULT_MAX_ETH_USD_DEVIATION: u64 = 500;
+
+/// marketplace fee and royalties on resale beyond what price sources net out, 0.5%
+pub const DEFAULT_RESALE_FEE: u64 = 50;
+
+/// yearly cost of capital locked in bids, 5%
+pub const DEFAULT_ANNUAL_CAPITAL_COST: u64 = 500;
+
+/// chance a borrower redeems an auctioned loan, 20%
+pub const DEFAULT_REDEMPTION_PROBABILITY: u64 = 2_000;
+
+/// expected profit needed to bid, 3% of the bid
+pub const DEFAULT_MIN_PROFIT_MARGIN: u64 = 300;
+
+/// gas of `LendPool.auction`, a first bid or an outbid
+pub const AUCTION_GAS: u64 = 300_000;
+
+/// gas of `LendPool.liquidate` once an auction is won
+pub const LIQUIDATE_GAS: u64 = 400_000;
+
+/// `LendPool` wants outbids to beat the previous bid by 1% of the debt, two decimals
+pub const MIN_BID_DELTA: u64 = 100;
+
+/// share of a bid's expected profit paid to the builder, 20%
+pub const DEFAULT_GAS_PROFIT_SHARE: u64 = 2_000;
+
+/// ceiling of the max fee per gas of bids, in gwei
+pub const DEFAULT_MAX_GAS_PRICE: u64 = 300;
+
+/// blocks of `eth_feeHistory` the priority fee is taken from
+pub const FEE_HISTORY_BLOCKS: u64 = 5;
+
+/// builders bundles are sent to, `name=url` or `url` named after its domain
+pub const DEFAULT_BUILDERS: &[&str] = &[
+    "https://builder0x69.io",
+    "https://rpc.beaverbuild.org",
+    "https://relay.flashbots.net",
+    "https://rsync-builder.xyz",
+    "https://rpc.titanbuilder.xyz",
+    "https://api.blocknative.com/v1/auction",
+    "bloxroute=https://mev.api.blxrbdn.com",
+    "https://eth-builder.com",
+    "https://builder.gmbit.co/rpc",
+    "https://buildai.net",
+    "https://rpc.payload.de",
+    "https://rpc.lightspeedbuilder.info",
+    "https://rpc.nfactorial.xyz",
+    "https://rpc.lokibuilder.xyz",
+];
+
+/// failed `eth_sendBundle` in a row after which a builder is dropped
+pub const MAX_BUILDER_FAILURES: u64 = 5;
+
+/// seconds a dropped builder is skipped before it's tried again
+pub const BUILDER_COOLDOWN: u64 = 1_800;
+
+/// seconds a builder has to answer `eth_sendBundle`
+pub const BUILDER_TIMEOUT: u64 = 2;
+
+/// blocks a bundle is resubmitted for until it lands
+pub const DEFAULT_BUNDLE_TARGET_BLOCKS: u64 = 3;
+
+/// seconds after which a bundle's nonce reservation that was never released is dropped
+pub const NONCE_RESERVATION_TTL: u64 = 300;
+
+/// ETH in gwei never wrapped into WETH, left for gas
+pub const DEFAULT_GAS_RESERVE: u64 = 50_000_000;
+
+/// ETH set aside for the gas of every bid
+pub const BID_GAS_ETH: u64 = 10_000_000_000_000_000;
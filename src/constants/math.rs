@@ -1,4 +1,6 @@
 pub const RAY: &str = "1000000000000000000000000000";
+/// `100%` in BendDAO's two-decimal percentages
+pub const PERCENTAGE_FACTOR: u64 = 10_000;
 /// seconds in one year
 pub const ONE_YEAR: u32 = 31_536_000;
 /// seconds in one day
@@ -10,4 +12,7 @@ pub const ONE_MINUTE: u64 = 60;
 
 pub const DELAY_FOR_LAST_BID: u64 = 13;
 
-pub const BLOCKS_IN_DAY: u64 = ONE_DAY / 12;
+/// seconds between blocks
+pub const BLOCK_TIME: u64 = 12;
+
+pub const BLOCKS_IN_DAY: u64 = ONE_DAY / BLOCK_TIME;
@@ -0,0 +1,89 @@
+use crate::{
+    constants::*, erc_20::ApproveCall, executor::Call, lend_pool::AuctionCall, types::AuctionBid,
+    weth::DepositCall,
+};
+use ethers::{
+    abi::AbiEncode,
+    types::{Address, Bytes, U256},
+};
+
+/// Calls of one `Executor.execute` tx
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecutorBatch {
+    pub calls: Vec<Call>,
+    /// ETH sent along, for the WETH wrap and the coinbase payment
+    pub value: U256,
+    /// paid to the builder only if no call failed
+    pub coinbase_payment: U256,
+}
+
+impl ExecutorBatch {
+    pub fn new() -> ExecutorBatch {
+        ExecutorBatch::default()
+    }
+
+    /// Wraps `amount` of the ETH sent along into WETH held by the executor
+    pub fn wrap_eth(&mut self, amount: U256) -> &mut Self {
+        self.value += amount;
+        self.push(Address::from(WETH), amount, DepositCall.encode(), false)
+    }
+
+    /// Lets `spender` pull all of the executor's `token`
+    pub fn approve(&mut self, token: Address, spender: Address) -> &mut Self {
+        let data = ApproveCall {
+            spender,
+            amount: U256::MAX,
+        }
+        .encode();
+        self.push(token, U256::zero(), data, false)
+    }
+
+    /// Sets the executor's `token` allowance of `spender` back to zero
+    pub fn revoke(&mut self, token: Address, spender: Address) -> &mut Self {
+        let data = ApproveCall {
+            spender,
+            amount: U256::zero(),
+        }
+        .encode();
+        self.push(token, U256::zero(), data, false)
+    }
+
+    /// Bids with the executor's funds, the NFT and any refund go to `on_behalf_of`
+    pub fn auction(
+        &mut self,
+        bid: &AuctionBid,
+        on_behalf_of: Address,
+        allow_failure: bool,
+    ) -> &mut Self {
+        let data = AuctionCall {
+            nft_asset: bid.nft_asset,
+            nft_token_id: bid.nft_token_id,
+            bid_price: bid.bid_price,
+            on_behalf_of,
+        }
+        .encode();
+        self.push(Address::from(LEND_POOL), U256::zero(), data, allow_failure)
+    }
+
+    pub fn pay_coinbase(&mut self, amount: U256) -> &mut Self {
+        self.value += amount;
+        self.coinbase_payment += amount;
+        self
+    }
+
+    fn push(
+        &mut self,
+        target: Address,
+        value: U256,
+        data: Vec<u8>,
+        allow_failure: bool,
+    ) -> &mut Self {
+        self.calls.push(Call {
+            target,
+            value,
+            data: Bytes::from(data),
+            allow_failure,
+        });
+        self
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{math::percent_mul, Config};
+use ethers::types::{FeeHistory, U256};
+
+/// EIP-1559 fees of a bid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GasFees {
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+}
+
+/// Fees of the next block as seen by `eth_feeHistory`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeMarket {
+    pub base_fee: U256,
+    /// one per recent block, in wei per gas
+    pub priority_fees: Vec<U256>,
+}
+
+impl FeeMarket {
+    /// `history` must be requested with a single reward percentile
+    pub fn from_fee_history(history: &FeeHistory) -> Option<FeeMarket> {
+        // the last base fee is the one of the block after `newestBlock`
+        let base_fee = *history.base_fee_per_gas.last()?;
+        let priority_fees = history
+            .reward
+            .iter()
+            .filter_map(|rewards| rewards.first().copied())
+            .collect();
+
+        Some(FeeMarket {
+            base_fee,
+            priority_fees,
+        })
+    }
+
+    /// lower median of the recent priority fees, zero if there are none
+    pub fn priority_fee(&self) -> U256 {
+        let mut priority_fees = self.priority_fees.clone();
+        priority_fees.sort();
+
+        match priority_fees.len() {
+            0 => U256::zero(),
+            len => priority_fees[(len - 1) / 2],
+        }
+    }
+}
+
+/// Prices bids so builders get `profit_share` of what each is expected to make,
+/// never tipping less than recent blocks did nor paying more than `max_fee_per_gas`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GasStrategy {
+    /// two decimals
+    pub profit_share: U256,
+    /// wei per gas
+    pub max_fee_per_gas: U256,
+}
+
+impl GasStrategy {
+    pub fn from_config(config: &Config) -> GasStrategy {
+        GasStrategy {
+            profit_share: config.gas_profit_share.into(),
+            max_fee_per_gas: U256::from(config.max_gas_price) * U256::exp10(9),
+        }
+    }
+
+    /// Fees of a tx using `gas` that is expected to make `expected_profit` wei
+    pub fn fees(&self, market: &FeeMarket, expected_profit: U256, gas: U256) -> GasFees {
+        let profit_tip = percent_mul(expected_profit, self.profit_share)
+            .checked_div(gas)
+            .unwrap_or_default();
+        let priority_fee = market.priority_fee().max(profit_tip);
+
+        // leaves room for the base fee to rise for a few blocks
+        let max_fee_per_gas = market.base_fee * U256::from(2) + priority_fee;
+
+        GasFees {
+            max_fee_per_gas: max_fee_per_gas.min(self.max_fee_per_gas),
+            max_priority_fee_per_gas: priority_fee.min(self.max_fee_per_gas),
+        }
+    }
+}
@@ -1,66 +1,90 @@
 use crate::{
-    benddao::loan::{Loan, NftAsset},
+    benddao::{
+        bid_rules::{min_first_bid, min_outbid},
+        loan::{Loan, NftAsset},
+    },
+    builders::{load_bundle_signer, Builder, BuilderRegistry, BUNDLE_SIGNER_PATH},
+    bundle_tracker::{BidBundle, BundleOutcome, BundleTracker},
     constants::*,
+    executor_batch::ExecutorBatch,
+    gas_strategy::{FeeMarket, GasStrategy},
+    health_factor::LoanPosition,
+    lend_pool::{
+        GetNftAuctionDataCall, GetNftAuctionDataReturn, GetNftDebtDataCall, GetNftDebtDataReturn,
+        GetNftLiquidatePriceCall, GetNftLiquidatePriceReturn,
+    },
+    nonce_manager::{NonceManager, NonceUse},
+    rpc_pool::RpcPool,
+    simulator::{
+        bundle::{bundle_transactions, SimulationBlock},
+        BundleSimulation, BundleSimulatorKind, LocalSimulator, SimulatorKind,
+    },
+    spoofer::{get_new_state_with_twaps_modded, get_twap_storage_overrides},
+    state_cache::{CachedBlock, StateCache},
+    store::{LiquidationRecord, Record, Store, LEGACY_REPAID_DEFAULTED_PATH, STORE_PATH},
+    treasury::{Funding, Treasury},
     types::*,
-    utils::{get_loan_data, handle_sent_bundle},
-    Config, Erc20, LendPool, LendPoolLoan, Weth,
+    utils::{get_loan_data, get_loan_position},
+    Config, Erc20, Executor, LendPool, LendPoolAddressesProvider, LendPoolLoan, NFTOracle,
+    ReserveOracle, Weth,
 };
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use ethers::{
-    core::{k256::ecdsa::SigningKey, rand::thread_rng},
+    abi::{AbiDecode, AbiEncode},
+    core::k256::ecdsa::SigningKey,
     middleware::SignerMiddleware,
-    providers::{Middleware, Provider, Ws},
+    providers::{Middleware, Provider, RawCall},
     signers::{coins_bip39::English, LocalWallet, MnemonicBuilder, Signer, Wallet},
-    types::{spoof::State, transaction::eip2718::TypedTransaction, Address, Transaction, U256},
+    types::{
+        spoof::State, transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes,
+        Transaction, TransactionRequest, H256, U256, U64,
+    },
 };
-use ethers_flashbots::{BroadcasterMiddleware, BundleRequest};
+use ethers_flashbots::{BroadcasterMiddleware, BundleRequest, PendingBundle, PendingBundleError};
 use futures::future::join_all;
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use std::sync::Arc;
 use tokio::{task::JoinHandle, try_join};
 use url::Url;
 
-static BUILDER_URLS: &[&str] = &[
-    "https://builder0x69.io",
-    "https://rpc.beaverbuild.org",
-    "https://relay.flashbots.net",
-    "https://rsync-builder.xyz",
-    "https://rpc.titanbuilder.xyz",
-    "https://api.blocknative.com/v1/auction",
-    "https://mev.api.blxrbdn.com",
-    "https://eth-builder.com",
-    "https://builder.gmbit.co/rpc",
-    "https://buildai.net",
-    "https://rpc.payload.de",
-    "https://rpc.lightspeedbuilder.info",
-    "https://rpc.nfactorial.xyz",
-    "https://rpc.lokibuilder.xyz",
-];
-
 #[derive(Clone)]
 pub struct GlobalProvider {
     pub local_wallet: LocalWallet,
-    pub provider: Arc<Provider<Ws>>,
+    pub provider: Arc<Provider<RpcPool>>,
     pub signer_provider: Arc<
         SignerMiddleware<
-            BroadcasterMiddleware<Arc<Provider<Ws>>, Wallet<SigningKey>>,
+            BroadcasterMiddleware<Arc<Provider<RpcPool>>, Wallet<SigningKey>>,
             Wallet<SigningKey>,
         >,
     >,
-    pub lend_pool: LendPool<Provider<Ws>>,
-    pub lend_pool_loan: LendPoolLoan<Provider<Ws>>,
-    pub weth: Weth<Provider<Ws>>,
-    pub usdt: Erc20<Provider<Ws>>,
+    pub lend_pool: LendPool<Provider<RpcPool>>,
+    pub lend_pool_loan: LendPoolLoan<Provider<RpcPool>>,
+    pub weth: Weth<Provider<RpcPool>>,
+    pub usdt: Erc20<Provider<RpcPool>>,
+    pub reserve_oracle: ReserveOracle<Provider<RpcPool>>,
+    pub nft_oracle: NFTOracle<Provider<RpcPool>>,
+    pub state_cache: Option<Arc<StateCache<Provider<RpcPool>>>>,
+    pub bundle_simulator: BundleSimulatorKind,
+    pub gas_strategy: GasStrategy,
+    pub builders: Arc<BuilderRegistry>,
+    pub bundle_tracker: BundleTracker,
+    pub nonce_manager: Arc<NonceManager>,
+    /// bids go through it when set
+    pub executor: Option<Executor<Provider<RpcPool>>>,
+    /// paid to the builder by executor txs whose bids all went through
+    pub coinbase_payment: U256,
+    pub treasury: Treasury,
+    pub store: Store,
 }
 
 impl GlobalProvider {
     pub async fn try_new(config_vars: Config) -> Result<GlobalProvider> {
-        let provider = Provider::<Ws>::connect(&config_vars.mainnet_rpc_url_ws).await?;
-        let provider = Arc::new(provider);
+        let pool = RpcPool::connect(&config_vars.rpc_urls_ws()).await?;
+        let provider = Arc::new(Provider::new(pool));
 
         info!(
             "Connected to provider at URL: {}",
-            config_vars.mainnet_rpc_url_ws
+            config_vars.rpc_urls_ws().join(", ")
         );
         info!(
             "Current Ethereum block number: {}",
@@ -73,17 +97,37 @@ impl GlobalProvider {
 
         info!("Wallet: {}", local_wallet.address());
 
+        let bundle_signer =
+            load_bundle_signer(config_vars.bundle_signer_key.as_deref(), BUNDLE_SIGNER_PATH)
+                .await?;
+
+        info!("Bundle signer: {}", bundle_signer.address());
+
+        let builders = Builder::from_config(&config_vars.builders, &config_vars.disabled_builders);
+        let builders = BuilderRegistry::new(builders, bundle_signer.clone());
+
+        info!(
+            "Builders: {}",
+            builders
+                .builders()
+                .iter()
+                .filter(|builder| builder.enabled)
+                .map(|builder| builder.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        // only simulates bundles, they're sent through `builders`
         let signer_provider = SignerMiddleware::new(
             BroadcasterMiddleware::new(
                 provider.clone(),
-                BUILDER_URLS
+                builders
+                    .builders()
                     .iter()
-                    .map(|url| Url::parse(url).unwrap())
+                    .map(|builder| builder.url.clone())
                     .collect(),
                 Url::parse("https://relay.flashbots.net")?,
-                // TODO
-                // replace with a specific bundle signer
-                LocalWallet::new(&mut thread_rng()),
+                bundle_signer,
             ),
             local_wallet.clone(),
         );
@@ -101,6 +145,49 @@ impl GlobalProvider {
         let address = Address::from(USDT);
         let usdt = Erc20::new(address, provider.clone());
 
+        // the reserve oracle isn't a fixed address, resolve it the same way the lend pool does
+        let address = lend_pool.get_addresses_provider().await?;
+        let addresses_provider = LendPoolAddressesProvider::new(address, provider.clone());
+        let address = addresses_provider.get_reserve_oracle().await?;
+        let reserve_oracle = ReserveOracle::new(address, provider.clone());
+
+        let address = Address::from(NFT_ORACLE);
+        let nft_oracle = NFTOracle::new(address, provider.clone());
+
+        let state_cache = if config_vars.state_cache
+            || config_vars.simulator == SimulatorKind::Local
+            || config_vars.bundle_simulator == BundleSimulatorKind::Local
+        {
+            let accounts = [
+                Address::from(LEND_POOL),
+                Address::from(LEND_POOL_LOAN),
+                Address::from(NFT_ORACLE),
+                reserve_oracle.address(),
+                Address::from(WETH),
+                Address::from(USDT),
+            ];
+            let state_cache = StateCache::try_new(provider.clone(), &accounts).await?;
+            Some(Arc::new(state_cache))
+        } else {
+            None
+        };
+
+        let executor = match config_vars.executor_address()? {
+            Some(address) => {
+                info!("Executor: {:?}", address);
+                Some(Executor::new(address, provider.clone()))
+            }
+            None => None,
+        };
+
+        let store = Store::open(STORE_PATH).await?;
+        if store.state().await == Default::default() {
+            store
+                .import_repaid_defaulted(LEGACY_REPAID_DEFAULTED_PATH)
+                .await?;
+        }
+        builders.restore(&store.state().await.builder_stats);
+
         let global_provider = GlobalProvider {
             local_wallet,
             provider,
@@ -109,15 +196,86 @@ impl GlobalProvider {
             lend_pool_loan,
             weth,
             usdt,
+            reserve_oracle,
+            nft_oracle,
+            state_cache,
+            bundle_simulator: config_vars.bundle_simulator,
+            gas_strategy: GasStrategy::from_config(&config_vars),
+            builders: Arc::new(builders),
+            bundle_tracker: BundleTracker::from_config(&config_vars),
+            nonce_manager: Arc::new(NonceManager::new()),
+            executor,
+            coinbase_payment: config_vars.bundle_tip.into(),
+            treasury: Treasury::from_config(&config_vars),
+            store,
         };
 
         let balances = global_provider.get_balances().await?;
 
         info!("Balances: {:#?}", balances);
 
+        let balances = global_provider.get_bidding_balances().await?;
+        for shortfall in global_provider.treasury.shortfalls(&balances) {
+            warn!("{shortfall}");
+        }
+
         Ok(global_provider)
     }
 
+    /// State cache block env of a bundle targeting `block`, `None` runs at the latest block
+    pub fn block_env(&self, block: Option<U64>) -> Option<CachedBlock> {
+        let state_cache = self.state_cache.as_ref()?;
+        Some(state_cache.block_at(block?.as_u64()))
+    }
+
+    /// Gets `loan_ids` as they'd be with `twaps` posted.
+    /// Reads from the state cache when there is one, in the `block` env if it's set,
+    /// otherwise goes through the rpc at the latest block.
+    pub async fn get_cached_loans(
+        &self,
+        loan_ids: &[U256],
+        twaps: &[(Address, U256)],
+        block: Option<CachedBlock>,
+    ) -> Result<Vec<Loan>> {
+        if let Some(state_cache) = &self.state_cache {
+            let overrides = get_twap_storage_overrides(twaps);
+            return state_cache.get_loans(loan_ids, &overrides, block);
+        }
+
+        let range = loan_ids.iter().map(|loan_id| loan_id.as_u64());
+        let state = get_new_state_with_twaps_modded(twaps.to_vec());
+        self.get_loans_from_iter(range, Some(state)).await
+    }
+
+    /// Like `get_loans_from_iter` but without `getNftDebtData`,
+    /// debt and health factor are left to the `HealthFactorEngine`
+    pub async fn get_loan_positions_from_iter(
+        &self,
+        range: impl Iterator<Item = u64>,
+    ) -> Result<Vec<LoanPosition>> {
+        let mut handles = Vec::new();
+        let mut positions = Vec::new();
+
+        for loan_id in range {
+            let loan_id = U256::from(loan_id);
+            let lend_pool = self.lend_pool.clone();
+            let lend_pool_loan = self.lend_pool_loan.clone();
+            let provider = self.provider.clone();
+            let future: JoinHandle<Result<Option<LoanPosition>>> = tokio::spawn(async move {
+                get_loan_position(loan_id, provider, lend_pool, lend_pool_loan, None).await
+            });
+            handles.push(future);
+        }
+
+        for res in join_all(handles).await {
+            if let Some(position) = res?? {
+                positions.push(position)
+            }
+        }
+
+        Ok(positions)
+    }
+
     pub async fn get_loans_from_iter(
         &self,
         range: impl Iterator<Item = u64>,
@@ -162,15 +320,36 @@ impl GlobalProvider {
     }
 
     pub async fn get_balances(&self) -> Result<Balances> {
-        let local_wallet_address = self.local_wallet.address();
+        self.get_balances_of(self.local_wallet.address()).await
+    }
+
+    /// Balances bids are paid from. With an executor those are its WETH, USDT and
+    /// approvals, while the wallet pays the gas and sends the ETH wrapped and paid to
+    /// the builder
+    pub async fn get_bidding_balances(&self) -> Result<Balances> {
+        let Some(executor) = &self.executor else {
+            return self.get_balances().await;
+        };
+
+        let wallet = self.local_wallet.address();
+        let (mut balances, eth) = try_join!(
+            self.get_balances_of(executor.address()),
+            self.get_eth_balance(&wallet)
+        )?;
+        balances.eth = eth.saturating_sub(self.coinbase_payment);
+
+        Ok(balances)
+    }
+
+    async fn get_balances_of(&self, address: Address) -> Result<Balances> {
         let lend_pool_address = Address::from(LEND_POOL);
 
-        let (eth, weth, usdt, usdt_approval_amount, weth_approval_amount) = try_join!(
-            self.get_eth_balance(&local_wallet_address),
-            self.get_weth_balance(&local_wallet_address),
-            self.get_usdt_balance(&local_wallet_address),
-            self.get_weth_lend_pool_approval(&local_wallet_address, &lend_pool_address),
-            self.get_usdt_lend_pool_approval(&local_wallet_address, &lend_pool_address)
+        let (eth, weth, usdt, weth_approval_amount, usdt_approval_amount) = try_join!(
+            self.get_eth_balance(&address),
+            self.get_weth_balance(&address),
+            self.get_usdt_balance(&address),
+            self.get_weth_lend_pool_approval(&address, &lend_pool_address),
+            self.get_usdt_lend_pool_approval(&address, &lend_pool_address)
         )?;
 
         let balances = Balances {
@@ -190,62 +369,444 @@ impl GlobalProvider {
         &self,
         loans: Vec<AuctionBid>,
         oracle_update_tx: Transaction,
+        twaps: &[(Address, U256)],
     ) -> Result<BundleRequest> {
         // add oracle update
         let bundle = BundleRequest::new().push_transaction(oracle_update_tx);
         // add auction txs
-        self.create_auction_bundle(bundle, loans, false).await
+        self.create_auction_bundle(bundle, loans, twaps).await
     }
 
     /// creates a vec of tx's for auction based off loans
+    ///
+    /// Bids the lend pool would reject once `twaps` are posted are left out, it fails
+    /// if none are left. With several bids, each is allowed to revert.
     pub async fn create_auction_bundle(
         &self,
         mut bundle: BundleRequest,
         loans: Vec<AuctionBid>,
-        // temp
-        // TODO decide what to gas
-        max_gas: bool,
+        twaps: &[(Address, U256)],
     ) -> Result<BundleRequest> {
+        // bids are checked as of the block they target
+        let block = self.block_env(bundle.block());
+
+        let mut valid_bids = vec![];
         for loan in loans {
-            let nft_asset: Address = loan.nft_asset;
-
-            let mut tx: TypedTransaction = self
-                .lend_pool
-                .auction(
-                    nft_asset,
-                    loan.nft_token_id,
-                    loan.bid_price,
-                    self.local_wallet.address(),
-                )
-                .tx;
-
-            self.signer_provider.fill_transaction(&mut tx, None).await?;
-
-            // 10 gwei prio fee
-            if max_gas {
-                tx.set_gas_price(10_000_000_000_000u64);
+            match self.validate_bid(&loan, twaps, block).await {
+                Ok(()) => valid_bids.push(loan),
+                Err(e) => error!("not signing invalid bid: {e}"),
             }
+        }
 
-            let signature = self.local_wallet.sign_transaction(&tx).await?;
+        if valid_bids.is_empty() {
+            bail!("no valid bids to bundle");
+        }
 
-            bundle.add_transaction(tx.rlp_signed(&signature));
+        let fee_market = self.get_fee_market().await?;
+
+        // bids we can't pay for even after wrapping ETH are left out
+        let balances = self.get_bidding_balances().await?;
+        let (funded_bids, funding) = self.treasury.fund_bids(balances, valid_bids);
+        if funded_bids.is_empty() {
+            bail!("no funded bids to bundle");
+        }
+
+        if let Some(executor) = &self.executor {
+            let nonce = self.reserve_bundle_nonces(1).await?;
+            match self
+                .sign_executor_bids(executor, &funded_bids, &funding, nonce, &fee_market)
+                .await
+            {
+                Ok(tx) => bundle.add_transaction(tx),
+                Err(e) => {
+                    self.nonce_manager.release(nonce);
+                    return Err(e);
+                }
+            }
+
+            return Ok(bundle);
+        }
+
+        // a frontrun bid only drops itself out of a bundle with several
+        let revertible = funded_bids.len() > 1;
+        // bids can't be estimated before the txs funding them land
+        let bid_gas = (!funding.is_empty()).then(|| U256::from(AUCTION_GAS));
+
+        let mut txs = vec![];
+        for tx in self.funding_txs(&funding).await? {
+            txs.push((tx, U256::zero(), false));
+        }
+        for loan in &funded_bids {
+            txs.push((self.bid_tx(loan, bid_gas), loan.expected_profit, revertible));
+        }
+
+        // reserved once for the whole bundle, its txs take the nonces that follow
+        let base_nonce = self.reserve_bundle_nonces(txs.len()).await?;
+        let nonces: Vec<U256> = (0..txs.len()).map(|i| base_nonce + i).collect();
+        let mut signed = vec![];
+
+        let result: Result<()> = async {
+            for ((tx, expected_profit, revertible), nonce) in txs.into_iter().zip(&nonces) {
+                let tx = self
+                    .sign_priced(tx, *nonce, expected_profit, &fee_market)
+                    .await?;
+                signed.push((tx, revertible));
+            }
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            nonces
+                .into_iter()
+                .for_each(|nonce| self.nonce_manager.release(nonce));
+            return Err(e);
+        }
+
+        for (tx, revertible) in signed {
+            if revertible {
+                bundle.add_revertible_transaction(tx);
+            } else {
+                bundle.add_transaction(tx);
+            }
         }
 
         Ok(bundle)
     }
 
-    pub async fn send_and_handle_bundle(&self, bundle: BundleRequest) -> Result<()> {
-        let pending_bundle = self.signer_provider.inner().send_bundle(&bundle).await?;
+    /// Unsigned txs of `funding`, to land before the bids it pays for
+    async fn funding_txs(&self, funding: &Funding) -> Result<Vec<TypedTransaction>> {
+        let lend_pool = Address::from(LEND_POOL);
+        let mut txs = vec![];
+
+        if !funding.wrap.is_zero() {
+            txs.push(self.weth.deposit().value(funding.wrap).tx);
+        }
+        if funding.approve_weth {
+            txs.push(self.weth.approve(lend_pool, U256::MAX).tx);
+        }
+        if funding.approve_usdt {
+            // USDT reverts on changing an allowance that isn't zero
+            let allowance = self
+                .usdt
+                .allowance(self.local_wallet.address(), lend_pool)
+                .await?;
+            if !allowance.is_zero() {
+                txs.push(self.usdt.approve(lend_pool, U256::zero()).tx);
+            }
+            txs.push(self.usdt.approve(lend_pool, U256::MAX).tx);
+        }
 
-        handle_sent_bundle(pending_bundle).await
+        Ok(txs)
     }
 
-    // // simulates bundle and returns effective gas price
-    // pub async fn simulate_bundle(&self, bundle: &BundleRequest) -> Result<U256> {
-    //     let results = self.signer_provider.inner().simulate_bundle(bundle).await?;
+    /// Unsigned `LendPool.auction` tx of `loan`, `gas` is estimated when it's signed if unset
+    fn bid_tx(&self, loan: &AuctionBid, gas: Option<U256>) -> TypedTransaction {
+        let mut tx: TypedTransaction = self
+            .lend_pool
+            .auction(
+                loan.nft_asset,
+                loan.nft_token_id,
+                loan.bid_price,
+                self.local_wallet.address(),
+            )
+            .tx;
+        if let Some(gas) = gas {
+            tx.set_gas(gas);
+        }
+
+        tx
+    }
+
+    /// One `Executor.execute` tx for `bids` that wraps the WETH and approves the lend pool
+    /// as `funding` says. The builder is paid from it only if every bid goes through.
+    async fn sign_executor_bids(
+        &self,
+        executor: &Executor<Provider<RpcPool>>,
+        bids: &[AuctionBid],
+        funding: &Funding,
+        nonce: U256,
+        fee_market: &FeeMarket,
+    ) -> Result<Bytes> {
+        let lend_pool = Address::from(LEND_POOL);
+        let mut batch = ExecutorBatch::new();
+
+        if !funding.wrap.is_zero() {
+            batch.wrap_eth(funding.wrap);
+        }
+        if funding.approve_weth {
+            batch.approve(self.weth.address(), lend_pool);
+        }
+        if funding.approve_usdt {
+            // USDT reverts on changing an allowance that isn't zero
+            let allowance = self.usdt.allowance(executor.address(), lend_pool).await?;
+            if !allowance.is_zero() {
+                batch.revoke(self.usdt.address(), lend_pool);
+            }
+            batch.approve(self.usdt.address(), lend_pool);
+        }
 
-    //     Ok(results.effective_gas_price())
-    // }
+        // a frontrun bid only drops itself out of a batch with several
+        let allow_failure = bids.len() > 1;
+        for bid in bids {
+            batch.auction(bid, self.local_wallet.address(), allow_failure);
+        }
+        batch.pay_coinbase(self.coinbase_payment);
+
+        let tx: TypedTransaction = executor
+            .execute(batch.calls, batch.coinbase_payment)
+            .value(batch.value)
+            .tx;
+        let expected_profit = bids
+            .iter()
+            .fold(U256::zero(), |total, bid| total + bid.expected_profit);
+
+        self.sign_priced(tx, nonce, expected_profit, fee_market)
+            .await
+    }
+
+    /// Signs `tx` with `nonce`, priced by the gas strategy for `expected_profit`
+    async fn sign_priced(
+        &self,
+        mut tx: TypedTransaction,
+        nonce: U256,
+        expected_profit: U256,
+        fee_market: &FeeMarket,
+    ) -> Result<Bytes> {
+        tx.set_nonce(nonce);
+
+        self.signer_provider.fill_transaction(&mut tx, None).await?;
+
+        let gas = tx.gas().copied().unwrap_or_default();
+        let fees = self.gas_strategy.fees(fee_market, expected_profit, gas);
+        if let Some(tx) = tx.as_eip1559_mut() {
+            tx.max_fee_per_gas = Some(fees.max_fee_per_gas);
+            tx.max_priority_fee_per_gas = Some(fees.max_priority_fee_per_gas);
+        }
+
+        let signature = self.local_wallet.sign_transaction(&tx).await?;
+
+        Ok(tx.rlp_signed(&signature))
+    }
+
+    /// Nonce of the wallet, see `NonceManager::reserve`
+    pub async fn reserve_nonce(&self, nonce_use: NonceUse) -> Result<U256> {
+        let chain_nonce = self.get_chain_nonce().await?;
+        let now = chrono::Utc::now().timestamp() as u64;
+
+        Ok(self.nonce_manager.reserve(chain_nonce, nonce_use, now))
+    }
+
+    /// First of `count` nonces for a bundle's txs, see `NonceManager::reserve_bundle`
+    pub async fn reserve_bundle_nonces(&self, count: usize) -> Result<U256> {
+        let chain_nonce = self.get_chain_nonce().await?;
+        let now = chrono::Utc::now().timestamp() as u64;
+
+        Ok(self.nonce_manager.reserve_bundle(chain_nonce, count, now))
+    }
+
+    /// Nonce of the wallet's next tx as of the latest block
+    async fn get_chain_nonce(&self) -> Result<U256> {
+        Ok(self
+            .provider
+            .get_transaction_count(
+                self.local_wallet.address(),
+                Some(BlockNumber::Latest.into()),
+            )
+            .await?)
+    }
+
+    /// Frees the nonces of our txs in `bundle` once it can no longer land
+    pub fn release_bundle_nonces(&self, bundle: &BundleRequest) -> Result<()> {
+        let address = self.local_wallet.address();
+        for tx in bundle_transactions(bundle.transactions())? {
+            if tx.from == address {
+                self.nonce_manager.release(tx.nonce);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends an empty tx to ourselves for every free nonce a pending tx is stuck behind
+    pub async fn cancel_nonce_gaps(&self) -> Result<()> {
+        let address = self.local_wallet.address();
+        let chain_nonce = self.get_chain_nonce().await?;
+
+        for _ in self.nonce_manager.gaps(chain_nonce) {
+            let nonce = self.reserve_nonce(NonceUse::Transaction).await?;
+            let tx = TransactionRequest::new().to(address).value(0).nonce(nonce);
+
+            match self.signer_provider.send_transaction(tx, None).await {
+                Ok(pending_tx) => info!("cancelling nonce {nonce} with {:?}", *pending_tx),
+                Err(e) => {
+                    self.nonce_manager.release(nonce);
+                    bail!("failed to cancel nonce {nonce}: {e}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Base fee of the next block and the median priority fee of the last ones
+    pub async fn get_fee_market(&self) -> Result<FeeMarket> {
+        let history = self
+            .provider
+            .fee_history(FEE_HISTORY_BLOCKS, BlockNumber::Latest, &[50.0])
+            .await?;
+
+        FeeMarket::from_fee_history(&history).ok_or_else(|| anyhow!("fee history is empty"))
+    }
+
+    /// Sends `bundle` for the blocks of the tracker's window, see `BundleTracker::track`
+    pub async fn send_and_handle_bundle(&self, bundle: BidBundle) -> Result<BundleOutcome> {
+        self.bundle_tracker.track(self, bundle).await
+    }
+
+    /// Tracks `bundles` one after another. Each reserves its nonces once the one before
+    /// it resolved, so a bundle that doesn't land can't take the next ones down with it
+    pub async fn send_and_handle_bundles(
+        &self,
+        bundles: Vec<BidBundle>,
+    ) -> Vec<Result<BundleOutcome>> {
+        let mut outcomes = vec![];
+        for bundle in bundles {
+            outcomes.push(self.send_and_handle_bundle(bundle).await);
+        }
+
+        outcomes
+    }
+
+    /// Sends `bundle` to the ranked builders, returns the bundle hash one of them gave
+    pub async fn send_to_builders(&self, bundle: &BundleRequest) -> Result<Option<H256>> {
+        let responses = self.builders.send_bundle(bundle).await?;
+
+        let mut bundle_hash = None;
+        for response in responses {
+            match response.result {
+                Ok(hash) => {
+                    debug!("bundle accepted by {}", response.builder);
+                    bundle_hash = bundle_hash.or(hash);
+                }
+                Err(e) => warn!("bundle rejected by {}: {e}", response.builder),
+            }
+            self.record_builder_stats(&response.builder).await;
+        }
+
+        Ok(bundle_hash)
+    }
+
+    /// Waits for the target block of `bundle`, returns its hash if it was included and
+    /// credits the builder of the block
+    pub async fn wait_for_bundle(
+        &self,
+        bundle: &BundleRequest,
+        bundle_hash: Option<H256>,
+    ) -> Result<Option<H256>> {
+        let block = bundle
+            .block()
+            .ok_or_else(|| anyhow!("bundle has no target block"))?;
+        let pending_bundle = PendingBundle::new(
+            bundle_hash,
+            block,
+            bundle.transaction_hashes(),
+            &self.provider,
+        );
+
+        match pending_bundle.await {
+            Ok(bundle_hash) => {
+                let builder = match self.provider.get_block(block).await? {
+                    Some(block) => self.builders.record_inclusion(&block.extra_data),
+                    None => None,
+                };
+                if let Some(builder) = &builder {
+                    self.record_builder_stats(builder).await;
+                }
+                info!(
+                    "Bundle with hash {:?} was included in target block by {}",
+                    bundle_hash.unwrap_or_default(),
+                    builder.as_deref().unwrap_or("an unknown builder")
+                );
+
+                Ok(Some(bundle_hash.unwrap_or_default()))
+            }
+            Err(PendingBundleError::BundleNotIncluded) => {
+                error!("Bundle was not included in target block.");
+                Ok(None)
+            }
+            Err(e) => {
+                error!("An error occured: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Appends to the store. A failed write is only logged since
+    /// whatever it records has already happened on chain.
+    pub async fn record(&self, record: Record) {
+        if let Err(e) = self.store.append(record).await {
+            error!("failed to write to store: {e}");
+        }
+    }
+
+    /// Saves the stats of `builder` so its reputation survives restarts
+    async fn record_builder_stats(&self, builder: &str) {
+        if let Some(stats) = self.builders.stats_of(builder) {
+            self.record(Record::BuilderStats {
+                builder: builder.to_string(),
+                stats,
+            })
+            .await;
+        }
+    }
+
+    /// Simulates `bundle` in the block it targets, or the next one if it has no target
+    pub async fn simulate_bundle(&self, bundle: &BundleRequest) -> Result<BundleSimulation> {
+        let latest = self
+            .provider
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| anyhow!("latest block is missing"))?;
+        let latest_number = latest.number.unwrap_or_default();
+
+        let block = bundle.block().unwrap_or(latest_number + 1);
+        let blocks_ahead = block.saturating_sub(latest_number).as_u64().max(1);
+        let timestamp = (latest.timestamp.as_u64() + blocks_ahead * BLOCK_TIME)
+            .max(bundle.min_timestamp().unwrap_or_default());
+
+        match self.bundle_simulator {
+            BundleSimulatorKind::Relay => {
+                let bundle = bundle
+                    .clone()
+                    .set_block(block)
+                    .set_simulation_block(latest_number)
+                    .set_simulation_timestamp(timestamp);
+                let simulation = self
+                    .signer_provider
+                    .inner()
+                    .simulate_bundle(&bundle)
+                    .await?;
+
+                Ok(simulation.into())
+            }
+            BundleSimulatorKind::Local => {
+                let state_cache = self
+                    .state_cache
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("local bundle simulation needs the state cache"))?;
+                let txs = bundle_transactions(bundle.transactions())?;
+                let block = SimulationBlock {
+                    number: block.as_u64(),
+                    timestamp,
+                    base_fee: latest.base_fee_per_gas.unwrap_or_default(),
+                };
+
+                LocalSimulator::from_shared(state_cache.shared_db()).simulate_bundle(&txs, block)
+            }
+        }
+    }
 
     pub async fn liquidate_loan(&self, auction: &Auction) -> Result<()> {
         let mut tx: TypedTransaction = self
@@ -253,24 +814,43 @@ impl GlobalProvider {
             .liquidate(auction.nft_asset.into(), auction.nft_token_id, U256::zero())
             .tx;
 
-        self.signer_provider.fill_transaction(&mut tx, None).await?;
+        let nonce = self.reserve_nonce(NonceUse::Transaction).await?;
+        tx.set_nonce(nonce);
 
-        let reciept = self
-            .signer_provider
-            .send_transaction(tx, None)
-            .await?
+        let pending_tx = match self.signer_provider.send_transaction(tx, None).await {
+            Ok(pending_tx) => pending_tx,
+            Err(e) => {
+                self.nonce_manager.release(nonce);
+                return Err(e.into());
+            }
+        };
+
+        let reciept = pending_tx
             .log_msg(format!(
                 "executing liquidation for {:?} r##{}",
                 auction.nft_asset, auction.nft_token_id
             ))
             .await?;
 
+        // dropped from the mempool
+        if reciept.is_none() {
+            self.nonce_manager.release(nonce);
+        }
+
         if let Some(reciept) = reciept {
             info!(
                 "loan successfully liquidated here: https://etherscan.io/tx/{:?}",
                 reciept.transaction_hash
             );
 
+            self.record(Record::Liquidation(LiquidationRecord {
+                nft_asset: auction.nft_asset,
+                nft_token_id: auction.nft_token_id,
+                transaction_hash: reciept.transaction_hash,
+                timestamp: chrono::Utc::now().timestamp() as u64,
+            }))
+            .await;
+
             Ok(())
         } else {
             bail!("auction failed")
@@ -286,6 +866,136 @@ impl GlobalProvider {
         bid_end_timestamp
     }
 
+    /// `(liquidate_price, payback_amount)` of `getNftLiquidatePrice` once `twaps` are posted
+    pub async fn get_nft_liquidate_price(
+        &self,
+        nft_asset: NftAsset,
+        token_id: U256,
+        twaps: &[(Address, U256)],
+        block: Option<CachedBlock>,
+    ) -> Result<(U256, U256)> {
+        let call = GetNftLiquidatePriceCall {
+            nft_asset: nft_asset.into(),
+            nft_token_id: token_id,
+        };
+        let GetNftLiquidatePriceReturn {
+            liquidate_price,
+            payback_amount,
+        } = self.call_lend_pool(call, twaps, block).await?;
+
+        Ok((liquidate_price, payback_amount))
+    }
+
+    /// Smallest bid `LendPool.auction` accepts for the token once `twaps` are posted.
+    /// Fails if the loan can't be bid on at all
+    pub async fn get_min_bid(
+        &self,
+        nft_asset: NftAsset,
+        token_id: U256,
+        twaps: &[(Address, U256)],
+        block: Option<CachedBlock>,
+    ) -> Result<U256> {
+        let auction_call = GetNftAuctionDataCall {
+            nft_asset: nft_asset.into(),
+            nft_token_id: token_id,
+        };
+        let debt_call = GetNftDebtDataCall {
+            nft_asset: nft_asset.into(),
+            nft_token_id: token_id,
+        };
+        let (auction_data, debt_data, (liquidate_price, payback_amount)): (
+            GetNftAuctionDataReturn,
+            GetNftDebtDataReturn,
+            _,
+        ) = try_join!(
+            self.call_lend_pool(auction_call, twaps, block),
+            self.call_lend_pool(debt_call, twaps, block),
+            self.get_nft_liquidate_price(nft_asset, token_id, twaps, block)
+        )?;
+
+        if auction_data.loan_id.is_zero() {
+            bail!("{:?} #{} has no loan", nft_asset, token_id);
+        }
+
+        if !auction_data.bid_price.is_zero() {
+            return Ok(min_outbid(payback_amount, auction_data.bid_price));
+        }
+
+        if debt_data.health_factor >= U256::exp10(18) {
+            bail!(
+                "{:?} #{} is healthy, health factor is {}",
+                nft_asset,
+                token_id,
+                debt_data.health_factor
+            );
+        }
+
+        Ok(min_first_bid(
+            payback_amount,
+            liquidate_price,
+            payback_amount,
+        ))
+    }
+
+    /// Fails if `LendPool.auction` would reject `bid` once `twaps` are posted
+    pub async fn validate_bid(
+        &self,
+        bid: &AuctionBid,
+        twaps: &[(Address, U256)],
+        block: Option<CachedBlock>,
+    ) -> Result<()> {
+        let nft_asset = NftAsset::try_from(bid.nft_asset)?;
+        let min_bid = self
+            .get_min_bid(nft_asset, bid.nft_token_id, twaps, block)
+            .await?;
+
+        if bid.bid_price < min_bid {
+            bail!(
+                "bid of {} on {:?} #{} is below the minimum of {}",
+                bid.bid_price,
+                nft_asset,
+                bid.nft_token_id,
+                min_bid
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Calls the lend pool once `twaps` are posted, against the state cache if there is one.
+    /// `block` is only honored by the state cache, the rpc runs at the latest block
+    pub async fn call_lend_pool<C: AbiEncode, R: AbiDecode>(
+        &self,
+        call: C,
+        twaps: &[(Address, U256)],
+        block: Option<CachedBlock>,
+    ) -> Result<R> {
+        if let Some(state_cache) = &self.state_cache {
+            let overrides = get_twap_storage_overrides(twaps);
+            return state_cache.call(LEND_POOL.into(), call, &overrides, block);
+        }
+
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::from(LEND_POOL))
+            .data(call.encode())
+            .into();
+        let state = get_new_state_with_twaps_modded(twaps.to_vec());
+        let output = self.provider.call_raw(&tx).state(&state).await?;
+
+        Ok(R::decode(output)?)
+    }
+
+    /// what a redeem would pay the first bidder now, in the reserve asset
+    pub async fn get_bid_fine(&self, nft_asset: NftAsset, token_id: U256) -> Result<U256> {
+        let call = GetNftAuctionDataCall {
+            nft_asset: nft_asset.into(),
+            nft_token_id: token_id,
+        };
+        let auction_data: GetNftAuctionDataReturn = self.call_lend_pool(call, &[], None).await?;
+
+        Ok(auction_data.bid_fine)
+    }
+
     pub async fn has_auction_ended(&self, nft_asset: NftAsset, token_id: U256) -> Result<bool> {
         let latest_block = self.provider.get_block_number().await?;
         let timestamp = self
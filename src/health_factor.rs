@@ -0,0 +1,244 @@
+use crate::{
+    benddao::{
+        loan::{Loan, NftAsset, ReserveAsset},
+        nft_configuration::NftConfiguration,
+        status::Status,
+    },
+    global_provider::GlobalProvider,
+    math::{calculate_compounded_interest, percent_mul, ray_mul, wad_div},
+};
+use anyhow::Result;
+use ethers::types::{Address, U256};
+use futures::future::try_join_all;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tokio::try_join;
+
+/// Interest state of a reserve as of its last on-chain update
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReserveState {
+    pub variable_borrow_index: U256,
+    pub variable_borrow_rate: U256,
+    pub last_update_timestamp: U256,
+    /// price of one whole unit of the reserve in wei as per `ReserveOracle`
+    pub price_in_eth: U256,
+}
+
+impl ReserveState {
+    /// `ReserveLogic.getNormalizedDebt` at `timestamp`
+    pub fn normalized_debt(&self, timestamp: U256) -> U256 {
+        if timestamp <= self.last_update_timestamp {
+            return self.variable_borrow_index;
+        }
+
+        let compounded_interest = calculate_compounded_interest(
+            self.variable_borrow_rate,
+            self.last_update_timestamp,
+            timestamp,
+        );
+
+        ray_mul(compounded_interest, self.variable_borrow_index).unwrap_or(U256::MAX)
+    }
+}
+
+/// Everything about a loan that only changes when someone interacts with it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoanPosition {
+    pub loan_id: U256,
+    pub status: Status,
+    pub nft_asset: NftAsset,
+    pub nft_token_id: U256,
+    pub reserve_asset: ReserveAsset,
+    pub scaled_debt: U256,
+}
+
+/// Reproduces `LendPool.getNftDebtData` offline.
+///
+/// Holds each loan's scaled debt together with the reserve indexes, liquidation
+/// thresholds and twaps, so `total_debt` and `health_factor` can be computed for
+/// any timestamp and any hypothetical twap without calling the lend pool.
+#[derive(Debug, Clone, Default)]
+pub struct HealthFactorEngine {
+    reserves: HashMap<ReserveAsset, ReserveState>,
+    liquidation_thresholds: HashMap<NftAsset, U256>,
+    nft_configurations: HashMap<NftAsset, NftConfiguration>,
+    twaps: HashMap<NftAsset, U256>,
+    positions: BTreeMap<U256, LoanPosition>,
+}
+
+impl HealthFactorEngine {
+    pub fn set_reserve(&mut self, reserve_asset: ReserveAsset, reserve: ReserveState) {
+        self.reserves.insert(reserve_asset, reserve);
+    }
+
+    /// `liquidation_threshold` has two decimals, `8_000` = 80%
+    pub fn set_liquidation_threshold(&mut self, nft_asset: NftAsset, liquidation_threshold: U256) {
+        self.liquidation_thresholds
+            .insert(nft_asset, liquidation_threshold);
+    }
+
+    pub fn set_nft_configuration(&mut self, nft_asset: NftAsset, config: NftConfiguration) {
+        self.set_liquidation_threshold(nft_asset, config.liquidation_threshold);
+        self.nft_configurations.insert(nft_asset, config);
+    }
+
+    pub fn nft_configuration(&self, nft_asset: NftAsset) -> Option<&NftConfiguration> {
+        self.nft_configurations.get(&nft_asset)
+    }
+
+    pub fn set_twap(&mut self, nft_asset: NftAsset, twap: U256) {
+        self.twaps.insert(nft_asset, twap);
+    }
+
+    pub fn twap(&self, nft_asset: NftAsset) -> Option<U256> {
+        self.twaps.get(&nft_asset).copied()
+    }
+
+    /// Stores twaps from a `NftOracle` update, unknown collections are ignored
+    pub fn update_twaps(&mut self, twaps: &[(Address, U256)]) {
+        for &(addr, twap) in twaps {
+            if let Ok(nft_asset) = NftAsset::try_from(addr) {
+                self.set_twap(nft_asset, twap);
+            }
+        }
+    }
+
+    pub fn insert_position(&mut self, position: LoanPosition) {
+        self.positions.insert(position.loan_id, position);
+    }
+
+    pub fn remove_position(&mut self, loan_id: U256) -> Option<LoanPosition> {
+        self.positions.remove(&loan_id)
+    }
+
+    pub fn position(&self, loan_id: U256) -> Option<&LoanPosition> {
+        self.positions.get(&loan_id)
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &LoanPosition> {
+        self.positions.values()
+    }
+
+    /// Debt of `position` in reserve units at `timestamp`.
+    /// `None` if the reserve hasn't been loaded.
+    pub fn total_debt(&self, position: &LoanPosition, timestamp: U256) -> Option<U256> {
+        let reserve = self.reserves.get(&position.reserve_asset)?;
+
+        ray_mul(position.scaled_debt, reserve.normalized_debt(timestamp))
+    }
+
+    /// Health factor of `position` at `timestamp`, in wad.
+    /// Twaps in `twaps` take precedence over the stored ones.
+    pub fn health_factor(
+        &self,
+        position: &LoanPosition,
+        timestamp: U256,
+        twaps: &[(Address, U256)],
+    ) -> Option<U256> {
+        let total_debt = self.total_debt(position, timestamp)?;
+
+        if total_debt.is_zero() {
+            return Some(U256::MAX);
+        }
+
+        let nft_asset = Address::from(position.nft_asset);
+        let twap = twaps
+            .iter()
+            .find(|(addr, _)| *addr == nft_asset)
+            .map(|&(_, twap)| twap)
+            .or_else(|| self.twaps.get(&position.nft_asset).copied())?;
+
+        let reserve = self.reserves.get(&position.reserve_asset)?;
+        let liquidation_threshold = self.liquidation_thresholds.get(&position.nft_asset)?;
+
+        let collateral =
+            twap * U256::exp10(position.reserve_asset.decimals() as usize) / reserve.price_in_eth;
+
+        Some(wad_div(
+            percent_mul(collateral, *liquidation_threshold),
+            total_debt,
+        ))
+    }
+
+    /// `Loan` as `getNftDebtData` would report it at `timestamp` with `twaps` posted
+    pub fn loan(&self, loan_id: U256, timestamp: U256, twaps: &[(Address, U256)]) -> Option<Loan> {
+        let position = self.positions.get(&loan_id)?;
+
+        Some(Loan {
+            loan_id: position.loan_id,
+            status: position.status,
+            nft_token_id: position.nft_token_id,
+            health_factor: self.health_factor(position, timestamp, twaps)?,
+            total_debt: self.total_debt(position, timestamp)?,
+            reserve_asset: position.reserve_asset,
+            nft_asset: position.nft_asset,
+        })
+    }
+
+    /// Every loan that can be valued, sorted by `health_factor` in ascending order
+    pub fn loans(&self, timestamp: U256, twaps: &[(Address, U256)]) -> Vec<Loan> {
+        let mut loans: Vec<Loan> = self
+            .positions
+            .keys()
+            .filter_map(|&loan_id| self.loan(loan_id, timestamp, twaps))
+            .collect();
+
+        loans.sort_by_key(|loan| loan.health_factor);
+
+        loans
+    }
+
+    /// Reloads reserve indexes, and the liquidation thresholds and twaps of
+    /// every collection we hold a position in
+    pub async fn refresh_market_data(&mut self, global_provider: &GlobalProvider) -> Result<()> {
+        for reserve_asset in [ReserveAsset::Weth, ReserveAsset::Usdt] {
+            let reserve_data = global_provider
+                .lend_pool
+                .get_reserve_data(reserve_asset.into())
+                .await?;
+
+            let price_in_eth = match reserve_asset {
+                ReserveAsset::Weth => U256::exp10(18),
+                ReserveAsset::Usdt => {
+                    global_provider
+                        .reserve_oracle
+                        .get_asset_price(reserve_asset.into())
+                        .await?
+                }
+            };
+
+            self.set_reserve(
+                reserve_asset,
+                ReserveState {
+                    variable_borrow_index: reserve_data.variable_borrow_index.into(),
+                    variable_borrow_rate: reserve_data.current_variable_borrow_rate.into(),
+                    last_update_timestamp: reserve_data.last_update_timestamp.into(),
+                    price_in_eth,
+                },
+            );
+        }
+
+        let nft_assets: HashSet<NftAsset> = self.positions().map(|p| p.nft_asset).collect();
+
+        let market_data = try_join_all(nft_assets.into_iter().map(|nft_asset| async move {
+            let config_call = global_provider
+                .lend_pool
+                .get_nft_configuration(nft_asset.into());
+            let twap_call = global_provider.nft_oracle.get_asset_price(nft_asset.into());
+            let (config, twap) = try_join!(config_call.call(), twap_call.call())?;
+            anyhow::Ok((nft_asset, NftConfiguration::from(config.data), twap))
+        }))
+        .await?;
+
+        for (nft_asset, config, twap) in market_data {
+            self.set_nft_configuration(nft_asset, config);
+            self.set_twap(nft_asset, twap);
+        }
+
+        Ok(())
+    }
+}
+
+/// bits 16-31 of `NftConfigurationMap.data`
+pub fn liquidation_threshold(config: U256) -> U256 {
+    NftConfiguration::from(config).liquidation_threshold
+}
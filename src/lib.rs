@@ -1,25 +1,53 @@
 pub mod benddao;
+pub mod blur;
+pub mod builders;
+pub mod bundle_tracker;
 pub mod coinmarketcap;
 pub mod constants;
+pub mod executor_batch;
+pub mod gas_strategy;
 pub mod global_provider;
+pub mod health_factor;
+pub mod loan_index;
 pub mod math;
+pub mod nonce_manager;
+pub mod opensea;
+pub mod price_guard;
+pub mod price_source;
 pub mod prices_client;
+pub mod profit;
 pub mod reservoir;
+pub mod resilient_ws;
+pub mod rpc_pool;
+pub mod shared_db;
 pub mod simulator;
 pub mod spoofer;
+pub mod state_cache;
+pub mod store;
+pub mod treasury;
 pub mod types;
 pub mod utils;
+pub mod valuation;
 
-use ethers::contract::abigen;
+use bundle_tracker::BundlePolicy;
+use ethers::{contract::abigen, types::Address};
+use log::warn;
+use price_source::AggregationPolicy;
 use serde::Deserialize;
+use simulator::{BundleSimulatorKind, SimulatorKind};
 
 abigen!(LendPool, "abi/LendPool.json");
 abigen!(LendPoolLoan, "abi/LendPoolLoan.json");
+abigen!(
+    LendPoolAddressesProvider,
+    "abi/LendPoolAddressesProvider.json"
+);
 abigen!(NFTOracle, "abi/NFTOracle.json");
 abigen!(ReserveOracle, "abi/ReserveOracle.json");
 abigen!(Weth, "abi/Weth.json");
 abigen!(Erc721, "abi/ERC721.json");
 abigen!(Erc20, "abi/ERC20.json");
+abigen!(Executor, "abi/Executor.json");
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
@@ -30,4 +58,199 @@ pub struct Config {
     pub coinmarketcap_api_key: String,
     pub slack_url: String,
     pub env: Option<String>,
+    #[serde(default)]
+    pub simulator: SimulatorKind,
+    /// keep a local copy of the BendDAO contracts' state, always on with the local simulator
+    #[serde(default)]
+    pub state_cache: bool,
+    /// where bundles are simulated before they're sent
+    #[serde(default)]
+    pub bundle_simulator: BundleSimulatorKind,
+    /// comma separated nodes pooled with `mainnet_rpc_url_ws`
+    #[serde(default)]
+    pub fallback_rpc_urls_ws: Vec<String>,
+    /// blocks a `LendPool` log can still be reorged out in
+    #[serde(default = "default_reorg_confirmation_depth")]
+    pub reorg_confirmation_depth: u64,
+    /// how collection prices of several marketplaces are combined
+    #[serde(default)]
+    pub price_aggregation: AggregationPolicy,
+    /// marketplaces that must price a collection for us to bid on it
+    #[serde(default = "default_min_price_sources")]
+    pub min_price_sources: usize,
+    /// comma separated `source=weight`, e.g. `reservoir=2,opensea=1`, unlisted marketplaces
+    /// weigh 1 and the `nft_oracle` twap 0
+    #[serde(default)]
+    pub price_source_weights: Vec<String>,
+    /// prices with OpenSea offers if set
+    pub opensea_api_key: Option<String>,
+    /// prices with Blur bids if set
+    pub blur_api_url: Option<String>,
+    pub blur_api_key: Option<String>,
+    /// seconds after its refresh a price is too old to bid with
+    #[serde(default = "default_max_price_age")]
+    pub max_price_age: u64,
+    /// max distance of a collection price to the `NftOracle` twap, two decimals
+    #[serde(default = "default_max_twap_deviation")]
+    pub max_twap_deviation: u64,
+    /// most a token is valued at from its own and trait bids, as a multiple of the collection
+    /// price with two decimals
+    #[serde(default = "default_max_token_value_multiple")]
+    pub max_token_value_multiple: u64,
+    /// max move of a price between two refreshes, two decimals
+    #[serde(default = "default_max_price_change")]
+    pub max_price_change: u64,
+    /// max distance of the `ReserveOracle` USDT price to coinmarketcap's, two decimals
+    #[serde(default = "default_max_eth_usd_deviation")]
+    pub max_eth_usd_deviation: u64,
+    /// marketplace fee and royalties paid on resale, two decimals
+    #[serde(default = "default_resale_fee")]
+    pub resale_fee: u64,
+    /// yearly cost of capital locked in bids, two decimals
+    #[serde(default = "default_annual_capital_cost")]
+    pub annual_capital_cost: u64,
+    /// chance a borrower redeems an auctioned loan, two decimals
+    #[serde(default = "default_redemption_probability")]
+    pub redemption_probability: u64,
+    /// expected profit needed to bid, as a share of the bid with two decimals
+    #[serde(default = "default_min_profit_margin")]
+    pub min_profit_margin: u64,
+    /// paid to the builder per bundle by the executor, in wei. Unused without one
+    #[serde(default)]
+    pub bundle_tip: u64,
+    /// share of a bid's expected profit paid to the builder as priority fee, two decimals
+    #[serde(default = "default_gas_profit_share")]
+    pub gas_profit_share: u64,
+    /// ceiling of the max fee per gas of bids, in gwei
+    #[serde(default = "default_max_gas_price")]
+    pub max_gas_price: u64,
+    /// comma separated `url` or `name=url` of the builders bundles are sent to,
+    /// `DEFAULT_BUILDERS` if empty
+    #[serde(default)]
+    pub builders: Vec<String>,
+    /// comma separated names or urls of builders that aren't sent bundles
+    #[serde(default)]
+    pub disabled_builders: Vec<String>,
+    /// private key bundles are signed with, kept in `BUNDLE_SIGNER_PATH` if unset
+    pub bundle_signer_key: Option<String>,
+    /// contract bids are routed through, see `contracts/Executor.sol`
+    pub executor_address: Option<String>,
+    /// whether bids on several loans share a bundle or get one each
+    #[serde(default)]
+    pub bundle_policy: BundlePolicy,
+    /// blocks a bundle is resubmitted for until it lands
+    #[serde(default = "default_bundle_target_blocks")]
+    pub bundle_target_blocks: u64,
+    /// WETH topped up to when bids need ETH wrapped, in gwei
+    #[serde(default)]
+    pub weth_target: u64,
+    /// USDT we warn about being under, it can't be wrapped, in whole USDT
+    #[serde(default)]
+    pub usdt_target: u64,
+    /// ETH never wrapped for bids, left for gas, in gwei
+    #[serde(default = "default_gas_reserve")]
+    pub gas_reserve: u64,
+}
+
+fn default_reorg_confirmation_depth() -> u64 {
+    constants::DEFAULT_REORG_CONFIRMATION_DEPTH
+}
+
+fn default_min_price_sources() -> usize {
+    1
+}
+
+fn default_max_price_age() -> u64 {
+    constants::DEFAULT_MAX_PRICE_AGE
+}
+
+fn default_max_twap_deviation() -> u64 {
+    constants::DEFAULT_MAX_TWAP_DEVIATION
+}
+
+fn default_max_token_value_multiple() -> u64 {
+    constants::DEFAULT_MAX_TOKEN_VALUE_MULTIPLE
+}
+
+fn default_max_price_change() -> u64 {
+    constants::DEFAULT_MAX_PRICE_CHANGE
+}
+
+fn default_gas_profit_share() -> u64 {
+    constants::DEFAULT_GAS_PROFIT_SHARE
+}
+
+fn default_max_gas_price() -> u64 {
+    constants::DEFAULT_MAX_GAS_PRICE
+}
+
+fn default_bundle_target_blocks() -> u64 {
+    constants::DEFAULT_BUNDLE_TARGET_BLOCKS
+}
+
+fn default_gas_reserve() -> u64 {
+    constants::DEFAULT_GAS_RESERVE
+}
+
+fn default_max_eth_usd_deviation() -> u64 {
+    constants::DEFAULT_MAX_ETH_USD_DEVIATION
+}
+
+fn default_resale_fee() -> u64 {
+    constants::DEFAULT_RESALE_FEE
+}
+
+fn default_annual_capital_cost() -> u64 {
+    constants::DEFAULT_ANNUAL_CAPITAL_COST
+}
+
+fn default_redemption_probability() -> u64 {
+    constants::DEFAULT_REDEMPTION_PROBABILITY
+}
+
+fn default_min_profit_margin() -> u64 {
+    constants::DEFAULT_MIN_PROFIT_MARGIN
+}
+
+impl Config {
+    /// `mainnet_rpc_url_ws` followed by the fallback nodes
+    pub fn rpc_urls_ws(&self) -> Vec<String> {
+        std::iter::once(self.mainnet_rpc_url_ws.clone())
+            .chain(self.fallback_rpc_urls_ws.iter().cloned())
+            .collect()
+    }
+
+    /// `executor_address` if it's set
+    pub fn executor_address(&self) -> anyhow::Result<Option<Address>> {
+        match self.executor_address.as_deref().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(address) => Ok(Some(address.parse()?)),
+        }
+    }
+
+    /// Weight of a `PriceSource` in `price_source_weights`, 1 if it isn't listed
+    pub fn price_source_weight(&self, source: &str) -> u64 {
+        self.price_source_weight_or(source, 1)
+    }
+
+    /// Weight of a `PriceSource` in `price_source_weights`, `default` if it isn't listed
+    pub fn price_source_weight_or(&self, source: &str, default: u64) -> u64 {
+        for entry in &self.price_source_weights {
+            let Some((name, weight)) = entry.split_once('=') else {
+                warn!("ignoring price source weight {entry:?}, expected `source=weight`");
+                continue;
+            };
+
+            if name.trim() != source {
+                continue;
+            }
+
+            match weight.trim().parse() {
+                Ok(weight) => return weight,
+                Err(e) => warn!("ignoring price source weight {entry:?}: {e}"),
+            }
+        }
+
+        default
+    }
 }
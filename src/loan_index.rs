@@ -0,0 +1,442 @@
+use crate::{
+    benddao::{
+        loan::{NftAsset, ReserveAsset},
+        status::Status,
+    },
+    constants::*,
+    health_factor::LoanPosition,
+    math::ray_div,
+    types::Auction,
+    LendPoolLoan, LendPoolLoanEvents,
+};
+use anyhow::Result;
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, H256, U256},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+const LOAN_INDEX_PATH: &str = "data/loan-index.json";
+
+/// `LendPoolLoan`'s `LoanState` as far as the index cares
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum IndexedLoanState {
+    Active,
+    Auction,
+    Repaid,
+    Defaulted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct IndexedLoan {
+    pub loan_id: U256,
+    pub state: IndexedLoanState,
+    pub nft_asset: Address,
+    pub nft_token_id: U256,
+    pub reserve_asset: Address,
+    pub scaled_amount: U256,
+}
+
+impl IndexedLoan {
+    pub fn is_open(&self) -> bool {
+        matches!(
+            self.state,
+            IndexedLoanState::Active | IndexedLoanState::Auction
+        )
+    }
+
+    /// `None` for auctions since their status depends on the auction end time,
+    /// and for assets we don't support
+    pub fn to_position(&self) -> Option<LoanPosition> {
+        if self.state != IndexedLoanState::Active {
+            return None;
+        }
+
+        Some(LoanPosition {
+            loan_id: self.loan_id,
+            status: Status::Active,
+            nft_asset: NftAsset::try_from(self.nft_asset).ok()?,
+            nft_token_id: self.nft_token_id,
+            reserve_asset: ReserveAsset::try_from(self.reserve_asset).ok()?,
+            scaled_debt: self.scaled_amount,
+        })
+    }
+
+    /// Position of a loan under `auction`, the scaled debt doesn't change while it runs
+    pub fn to_auction_position(&self, auction: &Auction) -> Option<LoanPosition> {
+        if self.state != IndexedLoanState::Auction {
+            return None;
+        }
+
+        Some(LoanPosition {
+            loan_id: self.loan_id,
+            status: Status::Auction(*auction),
+            nft_asset: NftAsset::try_from(self.nft_asset).ok()?,
+            nft_token_id: self.nft_token_id,
+            reserve_asset: ReserveAsset::try_from(self.reserve_asset).ok()?,
+            scaled_debt: self.scaled_amount,
+        })
+    }
+}
+
+/// What a `LendPoolLoan` log changed in the index, kept until the log can't be reorged out
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LoanChange {
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub loan_id: U256,
+    /// the loan before the log was applied, `None` if it wasn't indexed
+    pub previous: Option<IndexedLoan>,
+    /// `last_applied` before the log was applied
+    pub previous_last_applied: Option<(u64, U256)>,
+}
+
+/// Every BendDAO loan, rebuilt from `LendPoolLoan` events.
+///
+/// Backfills from `BEND_INCEPTION_BLOCK` and then follows the live subscription.
+/// `synced_block` and `last_applied` are persisted with the loans so a restart only
+/// queries the blocks it missed and never applies the same event twice.
+/// So is the journal of the blocks that can still be reorged out.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LoanIndex {
+    loans: BTreeMap<U256, IndexedLoan>,
+    /// every log up to and including this block has been applied
+    synced_block: Option<u64>,
+    /// `(block_number, log_index)` of the last applied log
+    last_applied: Option<(u64, U256)>,
+    /// changes of the unconfirmed blocks in the order they were applied
+    #[serde(default)]
+    journal: Vec<LoanChange>,
+}
+
+impl LoanIndex {
+    /// Loads the last checkpoint, or an empty index if there is none
+    pub async fn load() -> Result<LoanIndex> {
+        let mut file = match File::open(LOAN_INDEX_PATH).await {
+            Ok(file) => file,
+            Err(_) => return Ok(LoanIndex::default()),
+        };
+        let mut json_string = String::new();
+
+        file.read_to_string(&mut json_string).await?;
+
+        Ok(serde_json::from_str(&json_string)?)
+    }
+
+    /// Writes the index to a temporary file first so a crash never leaves it truncated
+    pub async fn save(&self) -> Result<()> {
+        let data = serde_json::to_string(self)?;
+
+        let tmp_path = format!("{LOAN_INDEX_PATH}.tmp");
+        let mut tmp = File::create(&tmp_path).await?;
+        tmp.write_all(data.as_bytes()).await?;
+        tmp.sync_all().await?;
+        fs::rename(&tmp_path, LOAN_INDEX_PATH).await?;
+
+        Ok(())
+    }
+
+    pub fn synced_block(&self) -> Option<u64> {
+        self.synced_block
+    }
+
+    pub fn get(&self, loan_id: U256) -> Option<&IndexedLoan> {
+        self.loans.get(&loan_id)
+    }
+
+    pub fn loans(&self) -> impl Iterator<Item = &IndexedLoan> {
+        self.loans.values()
+    }
+
+    /// Loans that are neither repaid nor defaulted
+    pub fn open_loans(&self) -> impl Iterator<Item = &IndexedLoan> {
+        self.loans().filter(|loan| loan.is_open())
+    }
+
+    /// Open loan of an NFT, there's at most one
+    pub fn open_loan_of(&self, nft_asset: Address, nft_token_id: U256) -> Option<&IndexedLoan> {
+        self.open_loans()
+            .find(|loan| loan.nft_asset == nft_asset && loan.nft_token_id == nft_token_id)
+    }
+
+    /// Queries and applies every `LendPoolLoan` log after the checkpoint up to `to_block`.
+    /// Journaled blocks that are no longer on the chain are reverted first.
+    pub async fn sync<M: Middleware + 'static>(
+        &mut self,
+        lend_pool_loan: &LendPoolLoan<M>,
+        to_block: u64,
+        confirmation_depth: u64,
+    ) -> Result<()> {
+        self.revert_reorged_blocks(lend_pool_loan.client_ref())
+            .await?;
+
+        let mut from_block = self
+            .synced_block
+            .map_or(BEND_INCEPTION_BLOCK, |block| block + 1);
+
+        if from_block > to_block {
+            return Ok(());
+        }
+
+        info!("syncing loan index from block {from_block} to {to_block}");
+
+        while from_block <= to_block {
+            let chunk_end = (from_block + LOG_QUERY_BLOCK_RANGE - 1).min(to_block);
+
+            let logs = lend_pool_loan
+                .events()
+                .from_block(from_block)
+                .to_block(chunk_end)
+                .query_with_meta()
+                .await?;
+
+            for (event, meta) in logs {
+                self.apply(
+                    &event,
+                    meta.block_number.as_u64(),
+                    meta.block_hash,
+                    meta.log_index,
+                );
+            }
+
+            self.synced_block = Some(chunk_end);
+            self.prune_journal(chunk_end, confirmation_depth);
+            from_block = chunk_end + 1;
+        }
+
+        info!(
+            "loan index synced: {} loans, {} open",
+            self.loans.len(),
+            self.open_loans().count()
+        );
+
+        Ok(())
+    }
+
+    /// Reverts from the lowest journaled block whose hash the chain no longer has.
+    /// Reorgs replace the tip, so blocks are checked from the latest down to the first
+    /// one that's still there.
+    async fn revert_reorged_blocks<M: Middleware + 'static>(&mut self, provider: &M) -> Result<()> {
+        let mut journaled: Vec<(u64, H256)> = self
+            .journal
+            .iter()
+            .map(|c| (c.block_number, c.block_hash))
+            .collect();
+        journaled.dedup();
+
+        let mut reorged_block = None;
+
+        for &(block_number, block_hash) in journaled.iter().rev() {
+            let block = provider
+                .get_block(BlockNumber::Number(block_number.into()))
+                .await?;
+
+            if block.and_then(|block| block.hash) == Some(block_hash) {
+                break;
+            }
+            reorged_block = Some(block_number);
+        }
+
+        let Some(block_number) = reorged_block else {
+            return Ok(());
+        };
+
+        let reverted = self.revert_from(block_number);
+        warn!(
+            "loan index: reorg from block {block_number}, reverted {} loan(s)",
+            reverted.len()
+        );
+
+        Ok(())
+    }
+
+    /// Applies a log from the live subscription. It's only taken if every block before it
+    /// is already in the index, otherwise it's left for the next `sync` to pick up.
+    pub fn apply_live(
+        &mut self,
+        event: &LendPoolLoanEvents,
+        block_number: u64,
+        block_hash: H256,
+        log_index: U256,
+    ) -> bool {
+        let Some(synced_block) = self.synced_block else {
+            return false;
+        };
+
+        // a live log from the first unsynced block means the subscription has
+        // been delivering since then, so the blocks in between had no logs
+        let is_contiguous = block_number <= synced_block + 1
+            || self
+                .last_applied
+                .is_some_and(|(last_block, _)| last_block == synced_block + 1);
+
+        if !is_contiguous {
+            return false;
+        }
+
+        self.synced_block = Some(synced_block.max(block_number - 1));
+
+        self.apply(event, block_number, block_hash, log_index)
+    }
+
+    /// Applies a log the same way `LendPoolLoan` updates its storage and journals it.
+    /// Logs at or before the last applied one are ignored, returns whether it was applied.
+    pub fn apply(
+        &mut self,
+        event: &LendPoolLoanEvents,
+        block_number: u64,
+        block_hash: H256,
+        log_index: U256,
+    ) -> bool {
+        if self
+            .last_applied
+            .is_some_and(|last| (block_number, log_index) <= last)
+        {
+            return false;
+        }
+
+        let loan_id = match event {
+            LendPoolLoanEvents::LoanCreatedFilter(evt) => Some(evt.loan_id),
+            LendPoolLoanEvents::LoanUpdatedFilter(evt) => Some(evt.loan_id),
+            LendPoolLoanEvents::LoanAuctionedFilter(evt) => Some(evt.loan_id),
+            LendPoolLoanEvents::LoanRedeemedFilter(evt) => Some(evt.loan_id),
+            LendPoolLoanEvents::LoanRepaidFilter(evt) => Some(evt.loan_id),
+            LendPoolLoanEvents::LoanLiquidatedFilter(evt) => Some(evt.loan_id),
+            _ => None,
+        };
+
+        if let Some(loan_id) = loan_id {
+            self.journal.push(LoanChange {
+                block_number,
+                block_hash,
+                loan_id,
+                previous: self.loans.get(&loan_id).copied(),
+                previous_last_applied: self.last_applied,
+            });
+        }
+
+        match event {
+            LendPoolLoanEvents::LoanCreatedFilter(evt) => {
+                self.loans.insert(
+                    evt.loan_id,
+                    IndexedLoan {
+                        loan_id: evt.loan_id,
+                        state: IndexedLoanState::Active,
+                        nft_asset: evt.nft_asset,
+                        nft_token_id: evt.nft_token_id,
+                        reserve_asset: evt.reserve_asset,
+                        scaled_amount: ray_div(evt.amount, evt.borrow_index),
+                    },
+                );
+            }
+            LendPoolLoanEvents::LoanUpdatedFilter(evt) => {
+                if let Some(loan) = self.loans.get_mut(&evt.loan_id) {
+                    if !evt.amount_added.is_zero() {
+                        loan.scaled_amount += ray_div(evt.amount_added, evt.borrow_index);
+                    }
+                    if !evt.amount_taken.is_zero() {
+                        let amount_scaled = ray_div(evt.amount_taken, evt.borrow_index);
+                        loan.scaled_amount = loan.scaled_amount.saturating_sub(amount_scaled);
+                    }
+                }
+            }
+            LendPoolLoanEvents::LoanAuctionedFilter(evt) => {
+                if let Some(loan) = self.loans.get_mut(&evt.loan_id) {
+                    loan.state = IndexedLoanState::Auction;
+                }
+            }
+            LendPoolLoanEvents::LoanRedeemedFilter(evt) => {
+                if let Some(loan) = self.loans.get_mut(&evt.loan_id) {
+                    let amount_scaled = ray_div(evt.amount_taken, evt.borrow_index);
+                    loan.scaled_amount = loan.scaled_amount.saturating_sub(amount_scaled);
+                    loan.state = IndexedLoanState::Active;
+                }
+            }
+            LendPoolLoanEvents::LoanRepaidFilter(evt) => {
+                if let Some(loan) = self.loans.get_mut(&evt.loan_id) {
+                    loan.scaled_amount = U256::zero();
+                    loan.state = IndexedLoanState::Repaid;
+                }
+            }
+            LendPoolLoanEvents::LoanLiquidatedFilter(evt) => {
+                if let Some(loan) = self.loans.get_mut(&evt.loan_id) {
+                    loan.scaled_amount = U256::zero();
+                    loan.state = IndexedLoanState::Defaulted;
+                }
+            }
+            _ => {}
+        }
+
+        self.last_applied = Some((block_number, log_index));
+
+        true
+    }
+
+    /// First block that has to be reverted for a live log at `block_number` in `block_hash`
+    /// to be applied, `None` if the journal is consistent with it.
+    pub fn reorged_block(&self, block_number: u64, block_hash: H256) -> Option<u64> {
+        self.journal
+            .iter()
+            .filter(|c| {
+                c.block_number > block_number
+                    || (c.block_number == block_number && c.block_hash != block_hash)
+            })
+            .map(|c| c.block_number)
+            .min()
+    }
+
+    /// Block of a journaled change made in `block_hash`
+    pub fn journaled_block(&self, block_hash: H256) -> Option<u64> {
+        self.journal
+            .iter()
+            .find(|c| c.block_hash == block_hash)
+            .map(|c| c.block_number)
+    }
+
+    /// Undoes every change from `block_number` on, the latest first, and rewinds the
+    /// checkpoint so the next `sync` queries those blocks again.
+    /// Returns the ids of the reverted loans.
+    pub fn revert_from(&mut self, block_number: u64) -> Vec<U256> {
+        let mut reverted = vec![];
+
+        while self
+            .journal
+            .last()
+            .is_some_and(|c| c.block_number >= block_number)
+        {
+            let change = self.journal.pop().unwrap();
+
+            match change.previous {
+                Some(loan) => {
+                    self.loans.insert(change.loan_id, loan);
+                }
+                None => {
+                    self.loans.remove(&change.loan_id);
+                }
+            }
+            self.last_applied = change.previous_last_applied;
+
+            if !reverted.contains(&change.loan_id) {
+                reverted.push(change.loan_id);
+            }
+        }
+
+        self.synced_block = self
+            .synced_block
+            .map(|synced_block| synced_block.min(block_number.saturating_sub(1)));
+
+        reverted
+    }
+
+    /// Forgets the changes that are `confirmation_depth` blocks or more below `head`
+    pub fn prune_journal(&mut self, head: u64, confirmation_depth: u64) {
+        self.journal
+            .retain(|c| c.block_number + confirmation_depth > head);
+    }
+}
@@ -1,19 +1,19 @@
 use anyhow::Result;
 use bend_dao_collector::benddao::loan::NftAsset;
 use bend_dao_collector::benddao::BendDao;
+use bend_dao_collector::bundle_tracker::BundleOutcome;
 use bend_dao_collector::constants::*;
 use bend_dao_collector::global_provider::GlobalProvider;
-use bend_dao_collector::lend_pool::LendPool;
+use bend_dao_collector::price_source::{OnChainSource, PriceSource};
 use bend_dao_collector::prices_client::PricesClient;
-use bend_dao_collector::simulator::Simulator;
-use bend_dao_collector::spoofer::get_new_state_with_twaps_modded;
-use bend_dao_collector::{Config, LendPoolEvents};
-use ethers::providers::Middleware;
+use bend_dao_collector::resilient_ws::ResilientWs;
+use bend_dao_collector::rpc_pool::{self, RpcPool};
+use bend_dao_collector::simulator::{AlchemySimulator, LocalSimulator, Simulator, SimulatorKind};
+use bend_dao_collector::state_cache::StateCache;
+use bend_dao_collector::types::Auction;
+use bend_dao_collector::{Config, LendPoolEvents, LendPoolLoanEvents};
 use ethers::utils::format_ether;
-use ethers::{
-    providers::{Provider, StreamExt, Ws},
-    types::*,
-};
+use ethers::{providers::Provider, types::*};
 use futures::future::try_join_all;
 use log::{error, info};
 use messenger_rs::slack_hook::SlackClient;
@@ -37,36 +37,84 @@ async fn main() -> Result<()> {
     let mut bend_dao =
         BendDao::try_new(config.clone(), prices_client.clone(), slack_bot.clone()).await?;
 
-    let provider = bend_dao.get_provider();
+    let ws = ResilientWs::connect(&config.mainnet_rpc_url_ws, Some(slack_bot.clone())).await?;
+    let ws = Arc::new(ws);
+
+    // every node's mempool is followed so the NftOracle tx is seen as early as possible
+    let mut mempool_nodes = vec![ws.clone()];
+    for url in &config.fallback_rpc_urls_ws {
+        match ResilientWs::connect(url, Some(slack_bot.clone())).await {
+            Ok(node) => mempool_nodes.push(Arc::new(node)),
+            Err(e) => error!("failed to connect to {url} for mempool updates: {e}"),
+        }
+    }
 
     let global_provider = Arc::new(bend_dao.get_global_provider());
 
+    let nft_oracle = OnChainSource::new(global_provider.nft_oracle.clone());
+    let weight = config.price_source_weight_or(nft_oracle.name(), DEFAULT_NFT_ORACLE_WEIGHT);
+    {
+        let mut prices_client = prices_client.write().await;
+        prices_client.add_source(nft_oracle, weight);
+        prices_client.set_reserve_oracle(global_provider.reserve_oracle.clone());
+    }
+
     let slack = bend_dao.slack_bot.clone();
 
     bend_dao.refresh_monitored_loans().await?;
 
     let bend_dao = Arc::new(Mutex::new(bend_dao));
 
-    let simulator = Simulator::new(config);
-
-    let task_one_handle = bend_dao_event_task(provider.clone(), bend_dao.clone());
-    let task_two_handle = nft_oracle_mempool_task(
-        provider.clone(),
+    if let Err(e) = BendDao::refresh_token_values(&bend_dao).await {
+        error!("could not refresh token values: {e}");
+    }
+
+    let task_one_handle = bend_dao_event_task(ws.clone(), bend_dao.clone());
+    let task_two_handle = match config.simulator {
+        SimulatorKind::Alchemy => nft_oracle_mempool_task(
+            mempool_nodes.clone(),
+            bend_dao.clone(),
+            global_provider.clone(),
+            AlchemySimulator::new(config),
+        ),
+        SimulatorKind::Local => nft_oracle_mempool_task(
+            mempool_nodes.clone(),
+            bend_dao.clone(),
+            global_provider.clone(),
+            LocalSimulator::from_shared(
+                global_provider
+                    .state_cache
+                    .as_ref()
+                    .expect("state cache is always built for the local simulator")
+                    .shared_db(),
+            ),
+        ),
+    };
+    let task_three_handle = last_minute_bid_task(
+        ws.clone(),
         bend_dao.clone(),
         global_provider.clone(),
-        simulator,
+        Arc::new(slack),
     );
-    let task_three_handle =
-        last_minute_bid_task(bend_dao.clone(), global_provider, Arc::new(slack));
     let task_four_handle = refresh_nft_prices_task(prices_client, slack_bot);
+    let task_five_handle =
+        scheduled_auctions_task(ws.clone(), bend_dao.clone(), global_provider.clone());
+    let task_six_handle = loan_index_task(ws.clone(), bend_dao.clone());
 
-    try_join_all([
+    let mut handles = vec![
         task_one_handle,
         task_two_handle,
         task_three_handle,
         task_four_handle,
-    ])
-    .await?;
+        task_five_handle,
+        task_six_handle,
+    ];
+
+    if let Some(state_cache) = global_provider.state_cache.clone() {
+        handles.push(state_cache_task(ws.clone(), state_cache));
+    }
+
+    try_join_all(handles).await?;
 
     info!("bot is shutting down");
 
@@ -75,218 +123,324 @@ async fn main() -> Result<()> {
 
 /// listens to benddao events and modifies state
 fn bend_dao_event_task(
-    provider: Arc<Provider<Ws>>,
+    ws: Arc<ResilientWs>,
     bend_dao_state: Arc<Mutex<BendDao>>,
 ) -> JoinHandle<Result<()>> {
     tokio::spawn(async move {
         info!("starting event listener task for lend pool events");
 
-        let lend_pool: Address = LEND_POOL.into();
-        let lend_pool = LendPool::new(lend_pool, provider);
-
-        let events = lend_pool.events();
-        let mut stream = events.subscribe().await?;
-
-        while let Some(Ok(evt)) = stream.next().await {
-            let mut bd_lock = bend_dao_state.lock().await;
-            match evt {
-                LendPoolEvents::AuctionFilter(evt) => {
-                    if let Ok(nft_asset) = NftAsset::try_from(evt.nft_asset) {
-                        if nft_asset.is_allowed_in_production() {
-                            bd_lock.react_to_auction(evt).await;
-                        }
-                    }
-                }
-                LendPoolEvents::RedeemFilter(evt) => {
-                    if let Ok(nft_asset) = NftAsset::try_from(evt.nft_asset) {
-                        if nft_asset.is_allowed_in_production() {
-                            bd_lock.react_to_redeem(evt).await;
-                        }
-                    }
-                }
-                LendPoolEvents::LiquidateFilter(evt) => {
-                    if let Ok(nft_asset) = NftAsset::try_from(evt.nft_asset) {
-                        if nft_asset.is_allowed_in_production() {
-                            bd_lock.react_to_liquidation(evt).await;
-                        }
-                    }
+        ws.follow_logs(
+            Address::from(LEND_POOL),
+            |evt: LendPoolEvents, meta, removed| {
+                let bend_dao_state = bend_dao_state.clone();
+                async move {
+                    bend_dao_state
+                        .lock()
+                        .await
+                        .react_to_lend_pool_event(evt, meta, removed)
+                        .await;
+                    Ok(())
                 }
-                _ => {}
-            }
-        }
-
-        info!("returning event listener task for lend pool");
+            },
+        )
+        .await
+    })
+}
 
-        Ok(())
+/// keeps the loan index current with `LendPoolLoan` events
+fn loan_index_task(
+    ws: Arc<ResilientWs>,
+    bend_dao_state: Arc<Mutex<BendDao>>,
+) -> JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        info!("starting event listener task for lend pool loan events");
+
+        ws.follow_logs(
+            Address::from(LEND_POOL_LOAN),
+            |evt: LendPoolLoanEvents, meta, removed| {
+                let bend_dao_state = bend_dao_state.clone();
+                async move {
+                    bend_dao_state
+                        .lock()
+                        .await
+                        .react_to_loan_event(evt, meta, removed);
+                    Ok(())
+                }
+            },
+        )
+        .await
     })
 }
 
 // listen to mempool for oracle updates
-fn nft_oracle_mempool_task(
-    provider: Arc<Provider<Ws>>,
+fn nft_oracle_mempool_task<S: Simulator + Send + Sync + 'static>(
+    nodes: Vec<Arc<ResilientWs>>,
     bend_dao_state: Arc<Mutex<BendDao>>,
     global_provider: Arc<GlobalProvider>,
-    simulator: Simulator,
+    simulator: S,
 ) -> JoinHandle<Result<()>> {
     tokio::spawn(async move {
         info!("starting task for mempool updates");
 
-        let mut stream = provider.subscribe_full_pending_txs().await?;
+        let simulator = &simulator;
 
-        while let Some(tx) = stream.next().await {
-            if tx.to.is_none() // contract creation
-                || tx.to.unwrap().0 != NFT_ORACLE
-                || tx.from.0 != NFT_ORACLE_CONTROLLER_EOA
-            {
-                continue;
-            }
+        rpc_pool::follow_pending_txs(&nodes, |tx| {
+            let bend_dao_state = bend_dao_state.clone();
+            let global_provider = global_provider.clone();
+            async move {
+                if tx.to.is_none() // contract creation
+                    || tx.to.unwrap().0 != NFT_ORACLE
+                    || tx.from.0 != NFT_ORACLE_CONTROLLER_EOA
+                {
+                    return Ok(());
+                }
 
-            info!("NftOracle posted prices");
+                info!("NftOracle posted prices");
 
-            let twaps = simulator.simulate_twap_changes(&tx).await?;
+                let twaps = simulator.simulate_twap_changes(&tx).await?;
 
-            for &(addr, price) in twaps.iter() {
-                if let Ok(nft_asset) = NftAsset::try_from(addr) {
-                    info!("{:?}: {}", nft_asset, format_ether(price));
+                for &(addr, price) in twaps.iter() {
+                    if let Ok(nft_asset) = NftAsset::try_from(addr) {
+                        info!("{:?}: {}", nft_asset, format_ether(price));
+                    }
                 }
-            }
-
-            let modded_state = get_new_state_with_twaps_modded(twaps);
 
-            {
-                if let Some(bundle) = bend_dao_state
-                    .lock()
-                    .await
-                    .initiate_auctions_if_any(tx, Some(modded_state))
-                    .await?
                 {
-                    match global_provider.send_and_handle_bundle(bundle).await {
-                        Ok(_) => {
-                            info!("bundle sent successfully");
-                        }
-                        Err(e) => {
-                            error!("error sending bundle: {}", e);
+                    let bundles = bend_dao_state
+                        .lock()
+                        .await
+                        .initiate_auctions_if_any(tx, &twaps)
+                        .await?;
+
+                    for result in global_provider.send_and_handle_bundles(bundles).await {
+                        match result {
+                            Ok(outcome) => {
+                                info!("auction bundle {outcome}");
+                            }
+                            Err(e) => {
+                                error!("error sending bundle: {}", e);
+                            }
                         }
                     }
                 }
+
+                // sleep and wait for two blocks to be mined so that
+                // the refresh includes the latest update
+                sleep(Duration::from_secs(24)).await;
+
+                {
+                    bend_dao_state
+                        .lock()
+                        .await
+                        .refresh_monitored_loans()
+                        .await?;
+                }
+
+                if let Err(e) = BendDao::refresh_token_values(&bend_dao_state).await {
+                    error!("could not refresh token values: {e}");
+                }
+
+                Ok(())
             }
+        })
+        .await
+    })
+}
 
-            // sleep and wait for two blocks to be mined so that
-            // the refresh includes the latest update
-            sleep(Duration::from_secs(24)).await;
+/// Auctions loans whose health factor is projected to cross 1 from interest
+/// alone, targeting the first block after the crossing
+fn scheduled_auctions_task(
+    ws: Arc<ResilientWs>,
+    bend_dao_state: Arc<Mutex<BendDao>>,
+    global_provider: Arc<GlobalProvider>,
+) -> JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        info!("starting task for scheduled auctions");
+
+        ws.follow_blocks(|block| {
+            let bend_dao_state = bend_dao_state.clone();
+            let global_provider = global_provider.clone();
+            async move {
+                let Some(number) = block.number else {
+                    return Ok(());
+                };
+
+                let next_block_timestamp = block.timestamp + BLOCK_TIME;
+
+                let bundles = {
+                    let mut bd_lock = bend_dao_state.lock().await;
+                    let due = bd_lock.liquidation_schedule.pop_due(next_block_timestamp);
+                    if due.is_empty() {
+                        return Ok(());
+                    }
+                    info!("{} loans projected to become auctionable", due.len());
+                    bd_lock
+                        .initiate_scheduled_auctions(&due, number + 1, next_block_timestamp)
+                        .await?
+                };
+
+                for result in global_provider.send_and_handle_bundles(bundles).await {
+                    match result {
+                        Ok(outcome) => info!("scheduled auction bundle {outcome}"),
+                        Err(e) => error!("error sending scheduled auction bundle: {}", e),
+                    }
+                }
 
-            {
-                bend_dao_state
-                    .lock()
-                    .await
-                    .refresh_monitored_loans()
-                    .await?;
+                Ok(())
             }
-        }
+        })
+        .await
+    })
+}
 
-        Ok(())
+/// keeps the state cache on the latest block
+fn state_cache_task(
+    ws: Arc<ResilientWs>,
+    state_cache: Arc<StateCache<Provider<RpcPool>>>,
+) -> JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        info!("starting state cache sync task");
+
+        ws.follow_blocks(|block| {
+            let state_cache = state_cache.clone();
+            async move {
+                let Some(number) = block.number else {
+                    return Ok(());
+                };
+                if let Err(e) = state_cache.sync(number.as_u64()).await {
+                    error!("failed to sync state cache to block {number}: {e}");
+                }
+                Ok(())
+            }
+        })
+        .await
     })
 }
 
 /// Task that monitors all ongoing auctions
 fn last_minute_bid_task(
+    ws: Arc<ResilientWs>,
     bend_dao_state: Arc<Mutex<BendDao>>,
     global_provider: Arc<GlobalProvider>,
     slack: Arc<SlackClient>,
 ) -> JoinHandle<Result<()>> {
     tokio::spawn(async move {
-        let provider = global_provider.provider.clone();
-        let mut stream = provider.subscribe_blocks().await?;
-
-        while let Some(block) = stream.next().await {
-            let (ours, not_ours) = {
-                bend_dao_state
-                    .lock()
-                    .await
-                    .pending_auctions
-                    .pop_auctions_due(block.timestamp)
-            };
-
-            for auctions_due in ours.iter().chain(not_ours.iter()) {
-                let msg = format!(
-                    "Auction due to outbid: {:?} #{}\n",
-                    auctions_due.nft_asset, auctions_due.nft_token_id
-                );
-                let msg_ = format!("Bid ends: {}\n", auctions_due.bid_end_timestamp);
-                let msg__ = format!("Current timestamp: {}", block.timestamp);
-                info!("{}{}{}", msg, msg_, msg__);
-            }
+        ws.follow_blocks(|block| {
+            let bend_dao_state = bend_dao_state.clone();
+            let global_provider = global_provider.clone();
+            let slack = slack.clone();
+            async move {
+                let (ours, not_ours) = {
+                    bend_dao_state
+                        .lock()
+                        .await
+                        .pending_auctions
+                        .pop_auctions_due(block.timestamp)
+                };
+
+                for auctions_due in ours.iter().chain(not_ours.iter()) {
+                    let msg = format!(
+                        "Auction due to outbid: {:?} #{}\n",
+                        auctions_due.nft_asset, auctions_due.nft_token_id
+                    );
+                    let msg_ = format!("Bid ends: {}\n", auctions_due.bid_end_timestamp);
+                    let msg__ = format!("Current timestamp: {}", block.timestamp);
+                    info!("{}{}{}", msg, msg_, msg__);
+                }
 
-            let bundles = {
-                bend_dao_state
-                    .lock()
-                    .await
-                    .verify_and_package_outbids(&not_ours)
-                    .await?
-            };
+                let bundles = {
+                    bend_dao_state
+                        .lock()
+                        .await
+                        .verify_and_package_outbids(&not_ours)
+                        .await?
+                };
 
-            for (i, bundle) in bundles.into_iter().enumerate() {
+                // one after another so an outbid that doesn't land can't hold the next back
                 let global_provider_clone = global_provider.clone();
                 let slack_clone = slack.clone();
-                let auction = not_ours[i];
                 tokio::spawn(async move {
-                    match global_provider_clone.send_and_handle_bundle(bundle).await {
+                    for (auction, bundle) in bundles {
+                        match global_provider_clone.send_and_handle_bundle(bundle).await {
+                            Ok(BundleOutcome::Included) => {
+                                tokio::spawn(liquidate_won_auction(
+                                    global_provider_clone.clone(),
+                                    slack_clone.clone(),
+                                    auction,
+                                ));
+                            }
+                            Ok(outcome) => info!(
+                                "bid for {:?} #{} {outcome}",
+                                auction.nft_asset, auction.nft_token_id
+                            ),
+                            Err(e) => {
+                                error!("error sending bundle: {}", e);
+                            }
+                        }
+                    }
+                });
+
+                if let Err(e) = bend_dao_state.lock().await.log_liquidations(&ours).await {
+                    error!("failed to estimate liquidations: {e}");
+                }
+
+                for auction in ours {
+                    match global_provider.liquidate_loan(&auction).await {
                         Ok(_) => {
                             let message = format!(
-                                "bid for {:?} #{:?}sent successfully, waiting 2 block to liquidate",
+                                "liquidated https://www.benddao.xyz/en/auctions/bid/{:?}/{} successfully",
                                 auction.nft_asset, auction.nft_token_id
                             );
                             info!("{}", message);
-
-                            if let Err(e) = slack_clone.send_message(message).await {
+                            if let Err(e) = slack.send_message(message).await {
                                 error!("failed to send slack message {e}");
                             }
-
-                            sleep(Duration::from_secs(24)).await;
-                            match global_provider_clone.liquidate_loan(&auction).await {
-                                Ok(_) => {
-                                    let message = format!(
-                                        "liquidated https://www.benddao.xyz/en/auctions/bid/{:?}/{:?} successfully",
-                                        auction.nft_asset, auction.nft_token_id
-                                    );
-                                    info!("{}", message);
-                                    if let Err(e) = slack_clone.send_message(message).await {
-                                        error!("failed to send slack message {e}");
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("error sending bundle: {}", e);
-                                }
-                            }
                         }
                         Err(e) => {
                             error!("error sending bundle: {}", e);
                         }
                     }
-                });
+                }
+
+                Ok(())
             }
+        })
+        .await
+    })
+}
 
-            for auction in ours {
-                match global_provider.liquidate_loan(&auction).await {
-                    Ok(_) => {
-                        let message = format!(
-                            "liquidated https://www.benddao.xyz/en/auctions/bid/{:?}/{} successfully",
-                            auction.nft_asset, auction.nft_token_id
-                        );
-                        info!("{}", message);
-                        if let Err(e) = slack.send_message(message).await {
-                            error!("failed to send slack message {e}");
-                        }
-                    }
-                    Err(e) => {
-                        error!("error sending bundle: {}", e);
-                    }
-                }
+/// Liquidates an auction our outbid won once it has ended
+async fn liquidate_won_auction(
+    global_provider: Arc<GlobalProvider>,
+    slack: Arc<SlackClient>,
+    auction: Auction,
+) {
+    let message = format!(
+        "bid for {:?} #{:?}sent successfully, waiting 2 block to liquidate",
+        auction.nft_asset, auction.nft_token_id
+    );
+    info!("{}", message);
+
+    if let Err(e) = slack.send_message(message).await {
+        error!("failed to send slack message {e}");
+    }
+
+    sleep(Duration::from_secs(24)).await;
+    match global_provider.liquidate_loan(&auction).await {
+        Ok(_) => {
+            let message = format!(
+                "liquidated https://www.benddao.xyz/en/auctions/bid/{:?}/{:?} successfully",
+                auction.nft_asset, auction.nft_token_id
+            );
+            info!("{}", message);
+            if let Err(e) = slack.send_message(message).await {
+                error!("failed to send slack message {e}");
             }
         }
-
-        Ok(())
-    })
+        Err(e) => {
+            error!("error sending bundle: {}", e);
+        }
+    }
 }
 
 fn refresh_nft_prices_task(
@@ -299,8 +453,8 @@ fn refresh_nft_prices_task(
                 Ok(()) => {
                     info!("refreshed NFT prices successfully");
                 }
-                Err(_) => {
-                    error!("failed to refresh NFT prices");
+                Err(e) => {
+                    error!("failed to refresh NFT prices: {e}");
                     slack_bot
                         .send_message("Error: Failed to refresh NFT prices.")
                         .await
@@ -64,3 +64,43 @@ pub fn calculate_compounded_interest(
 
     U256::exp10(27) + (rate_per_second * (exp)) + (second_term) + (third_term)
 }
+
+/**
+ * @dev Executes a percentage multiplication, rounding half up
+ * @param value The value of which the percentage needs to be calculated
+ * @param percentage The percentage of the value to be calculated, with two decimals (`10_000` = 100%)
+ * @return The percentage of value
+ **/
+pub fn percent_mul(value: U256, percentage: U256) -> U256 {
+    if value.is_zero() || percentage.is_zero() {
+        return U256::zero();
+    }
+
+    let percentage_factor = U256::from(PERCENTAGE_FACTOR);
+
+    (value * percentage + percentage_factor / 2) / percentage_factor
+}
+
+/**
+ * @dev Divides two wad, rounding half up to the nearest wad
+ * @param a Wad
+ * @param b Wad
+ * @return The result of a/b, in wad
+ **/
+pub fn wad_div(a: U256, b: U256) -> U256 {
+    let wad = U256::exp10(18);
+
+    (a * wad + b / 2) / b
+}
+
+/**
+ * @dev Divides two ray, rounding half up to the nearest ray
+ * @param a Ray
+ * @param b Ray
+ * @return The result of a/b, in ray
+ **/
+pub fn ray_div(a: U256, b: U256) -> U256 {
+    let ray = U256::from_dec_str(RAY).unwrap();
+
+    (a * ray + b / 2) / b
+}
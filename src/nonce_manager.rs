@@ -0,0 +1,146 @@
+use crate::constants::*;
+use ethers::types::U256;
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// What a reserved nonce is used for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NonceUse {
+    /// only valid for the block it targets, the nonce is free again if it doesn't land
+    Bundle,
+    /// sent to the mempool, stuck behind any lower nonce that's free
+    Transaction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Reservation {
+    nonce_use: NonceUse,
+    reserved_at: u64,
+    /// bundles signed with the nonce, only one of them can land
+    holders: usize,
+}
+
+/// Hands out the wallet's nonces so txs never share one and bundles never wait on each other
+#[derive(Debug, Default)]
+pub struct NonceManager {
+    reserved: Mutex<BTreeMap<U256, Reservation>>,
+}
+
+impl NonceManager {
+    pub fn new() -> NonceManager {
+        NonceManager::default()
+    }
+
+    /// Reserves a nonce given the `chain_nonce` of the latest block, see `reserve_bundle`
+    /// for `NonceUse::Bundle`. A tx gets the lowest nonce nothing holds.
+    pub fn reserve(&self, chain_nonce: U256, nonce_use: NonceUse, now: u64) -> U256 {
+        if nonce_use == NonceUse::Bundle {
+            return self.reserve_bundle(chain_nonce, 1, now);
+        }
+
+        let mut reserved = self.reserved.lock().unwrap();
+        prune(&mut reserved, chain_nonce, now);
+
+        let mut nonce = chain_nonce;
+        while reserved.contains_key(&nonce) {
+            nonce += U256::one();
+        }
+
+        reserved.insert(
+            nonce,
+            Reservation {
+                nonce_use,
+                reserved_at: now,
+                holders: 1,
+            },
+        );
+
+        nonce
+    }
+
+    /// Reserves `count` consecutive nonces for a bundle and returns the first.
+    ///
+    /// Bundles are alternatives to each other, they all start at the lowest nonce pending
+    /// txs leave free and share the nonces they overlap on. One that doesn't land never
+    /// holds another back, the others are signed again from the next chain nonce.
+    ///
+    /// Nonces that landed are forgotten, which also resyncs with txs sent outside the bot,
+    /// and so are bundle reservations older than `NONCE_RESERVATION_TTL` that leaked
+    pub fn reserve_bundle(&self, chain_nonce: U256, count: usize, now: u64) -> U256 {
+        let mut reserved = self.reserved.lock().unwrap();
+        prune(&mut reserved, chain_nonce, now);
+
+        let held_by_tx = |nonce: U256| {
+            reserved
+                .get(&nonce)
+                .is_some_and(|reservation| reservation.nonce_use == NonceUse::Transaction)
+        };
+
+        let mut base = chain_nonce;
+        while (0..count).any(|i| held_by_tx(base + i)) {
+            base += U256::one();
+        }
+
+        for i in 0..count {
+            reserved
+                .entry(base + i)
+                .and_modify(|reservation| {
+                    reservation.holders += 1;
+                    reservation.reserved_at = now;
+                })
+                .or_insert(Reservation {
+                    nonce_use: NonceUse::Bundle,
+                    reserved_at: now,
+                    holders: 1,
+                });
+        }
+
+        base
+    }
+
+    /// Frees a nonce whose tx won't land once no other bundle holds it,
+    /// the next reservation reuses it
+    pub fn release(&self, nonce: U256) {
+        let mut reserved = self.reserved.lock().unwrap();
+
+        if let Some(reservation) = reserved.get_mut(&nonce) {
+            reservation.holders = reservation.holders.saturating_sub(1);
+            if reservation.holders == 0 {
+                reserved.remove(&nonce);
+            }
+        }
+    }
+
+    /// Free nonces below a `NonceUse::Transaction` reservation. Nothing reuses them in time,
+    /// they have to be cancelled for that tx to land
+    pub fn gaps(&self, chain_nonce: U256) -> Vec<U256> {
+        let reserved = self.reserved.lock().unwrap();
+        let Some(&highest) = reserved
+            .iter()
+            .rev()
+            .find(|(_, reservation)| reservation.nonce_use == NonceUse::Transaction)
+            .map(|(nonce, _)| nonce)
+        else {
+            return vec![];
+        };
+
+        let mut gaps = vec![];
+        let mut nonce = chain_nonce;
+        while nonce < highest {
+            if !reserved.contains_key(&nonce) {
+                gaps.push(nonce);
+            }
+            nonce += U256::one();
+        }
+
+        gaps
+    }
+}
+
+/// Drops reservations of nonces that landed and bundle reservations that leaked
+fn prune(reserved: &mut BTreeMap<U256, Reservation>, chain_nonce: U256, now: u64) {
+    reserved.retain(|&nonce, reservation| {
+        let expired = reservation.nonce_use == NonceUse::Bundle
+            && now >= reservation.reserved_at + NONCE_RESERVATION_TTL;
+        nonce >= chain_nonce && !expired
+    });
+}
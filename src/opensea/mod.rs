@@ -0,0 +1 @@
+pub mod offers_response;
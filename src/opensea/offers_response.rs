@@ -0,0 +1,66 @@
+use anyhow::{anyhow, Result};
+use ethers::types::U256;
+use serde::Deserialize;
+
+/// `api/v2/offers/collection/{slug}`
+#[derive(Debug, Deserialize)]
+pub struct CollectionOffersResponse {
+    offers: Vec<Offer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Offer {
+    price: Price,
+    protocol_data: ProtocolData,
+}
+
+#[derive(Debug, Deserialize)]
+struct Price {
+    currency: String,
+    /// for every item of the offer
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProtocolData {
+    parameters: Parameters,
+}
+
+#[derive(Debug, Deserialize)]
+struct Parameters {
+    consideration: Vec<ConsiderationItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsiderationItem {
+    #[serde(rename = "startAmount")]
+    start_amount: String,
+}
+
+impl CollectionOffersResponse {
+    /// Best WETH offer per item
+    pub fn get_best_offer(&self) -> Result<U256> {
+        let mut best_offer = None;
+
+        for offer in &self.offers {
+            if offer.price.currency != "WETH" {
+                continue;
+            }
+
+            // the first consideration item is the nfts wanted
+            let quantity = match offer.protocol_data.parameters.consideration.first() {
+                Some(item) => U256::from_dec_str(&item.start_amount)?,
+                None => continue,
+            };
+
+            if quantity.is_zero() {
+                continue;
+            }
+
+            let price = U256::from_dec_str(&offer.price.value)? / quantity;
+            best_offer = best_offer.max(Some(price));
+        }
+
+        best_offer.ok_or_else(|| anyhow!("no offers found"))
+    }
+}
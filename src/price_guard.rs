@@ -0,0 +1,163 @@
+use crate::math::percent_mul;
+use ethers::{types::U256, utils::format_ether};
+use std::fmt::{self, Display};
+
+/// A collection price as last refreshed
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceEntry {
+    /// in ETH (1e18)
+    pub price: U256,
+    /// price sources that quoted it
+    pub sources: Vec<&'static str>,
+    /// unix timestamp of the refresh
+    pub updated_at: u64,
+    /// price before the refresh, `None` for the first one
+    pub previous: Option<U256>,
+}
+
+/// Why a price can't be bid with
+#[derive(Debug, Clone, PartialEq)]
+pub enum PriceRejection {
+    /// the collection was never priced
+    Missing,
+    Zero,
+    /// the last refresh was `age` seconds ago, more than `max_age`
+    Stale {
+        age: u64,
+        max_age: u64,
+    },
+    /// more than `max_deviation` (two decimals) away from the `NftOracle` twap
+    TwapDeviation {
+        price: U256,
+        twap: U256,
+        max_deviation: U256,
+    },
+    /// moved more than `max_change` (two decimals) since the refresh before
+    PriceJump {
+        price: U256,
+        previous: U256,
+        max_change: U256,
+    },
+    /// more than `max_deviation` (two decimals) away from what `source` reports
+    CrossCheck {
+        price: U256,
+        source: &'static str,
+        cross_check: U256,
+        max_deviation: U256,
+    },
+}
+
+impl Display for PriceRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceRejection::Missing => write!(f, "no price"),
+            PriceRejection::Zero => write!(f, "price is zero"),
+            PriceRejection::Stale { age, max_age } => {
+                write!(f, "price is {age}s old, max age is {max_age}s")
+            }
+            PriceRejection::TwapDeviation {
+                price,
+                twap,
+                max_deviation,
+            } => write!(
+                f,
+                "price {} ETH is more than {}% away from twap {} ETH",
+                format_ether(*price),
+                max_deviation / 100,
+                format_ether(*twap)
+            ),
+            PriceRejection::PriceJump {
+                price,
+                previous,
+                max_change,
+            } => write!(
+                f,
+                "price {} ETH moved more than {}% from {} ETH",
+                format_ether(*price),
+                max_change / 100,
+                format_ether(*previous)
+            ),
+            PriceRejection::CrossCheck {
+                price,
+                source,
+                cross_check,
+                max_deviation,
+            } => write!(
+                f,
+                "price {} ETH is more than {}% away from {} ETH reported by {}",
+                format_ether(*price),
+                max_deviation / 100,
+                format_ether(*cross_check),
+                source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PriceRejection {}
+
+/// Limits a price must be within to be bid with, percentages have two decimals
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceGuard {
+    /// seconds
+    pub max_age: u64,
+    pub max_twap_deviation: U256,
+    pub max_change: U256,
+}
+
+impl PriceGuard {
+    /// `twap` is `None` if the `NftOracle` price isn't known, the check is skipped then
+    pub fn check(
+        &self,
+        entry: Option<&PriceEntry>,
+        twap: Option<U256>,
+        now: u64,
+    ) -> Result<U256, PriceRejection> {
+        let entry = entry.ok_or(PriceRejection::Missing)?;
+
+        if entry.price.is_zero() {
+            return Err(PriceRejection::Zero);
+        }
+
+        let age = now.saturating_sub(entry.updated_at);
+        if age > self.max_age {
+            return Err(PriceRejection::Stale {
+                age,
+                max_age: self.max_age,
+            });
+        }
+
+        if let Some(twap) = twap {
+            if deviates(entry.price, twap, self.max_twap_deviation) {
+                return Err(PriceRejection::TwapDeviation {
+                    price: entry.price,
+                    twap,
+                    max_deviation: self.max_twap_deviation,
+                });
+            }
+        }
+
+        if let Some(previous) = entry.previous {
+            if deviates(entry.price, previous, self.max_change) {
+                return Err(PriceRejection::PriceJump {
+                    price: entry.price,
+                    previous,
+                    max_change: self.max_change,
+                });
+            }
+        }
+
+        Ok(entry.price)
+    }
+}
+
+/// Whether `price` is more than `max_deviation` of `reference` away from it
+pub fn deviates(price: U256, reference: U256, max_deviation: U256) -> bool {
+    let difference = if price > reference {
+        price - reference
+    } else {
+        reference - price
+    };
+
+    difference > percent_mul(reference, max_deviation)
+}
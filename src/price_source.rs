@@ -0,0 +1,162 @@
+pub mod blur;
+pub mod on_chain;
+pub mod opensea;
+pub mod reservoir;
+
+pub use blur::BlurSource;
+pub use on_chain::OnChainSource;
+pub use opensea::OpenSeaSource;
+pub use reservoir::ReservoirSource;
+
+use crate::benddao::loan::NftAsset;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use ethers::{types::U256, utils::format_ether};
+use futures::future::join_all;
+use log::{info, warn};
+use serde::Deserialize;
+
+/// Somewhere a collection can be sold into
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Best collection wide price in ETH (1e18)
+    async fn collection_price(&self, nft_asset: NftAsset) -> Result<U256>;
+}
+
+/// How `PriceAggregator` combines the prices of its sources
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AggregationPolicy {
+    /// weighted median, a single source can't move it unless it outweighs the others
+    #[default]
+    Median,
+    /// lowest price, weights only decide whether a source is used
+    Min,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quote {
+    pub source: &'static str,
+    pub price: U256,
+    pub weight: u64,
+}
+
+/// Prices a collection from several `PriceSource`s so one bad API response
+/// cannot make us overbid
+pub struct PriceAggregator {
+    sources: Vec<(Box<dyn PriceSource>, u64)>,
+    policy: AggregationPolicy,
+    /// quotes needed for a price, fewer is an error
+    min_sources: usize,
+}
+
+impl PriceAggregator {
+    pub fn new(policy: AggregationPolicy, min_sources: usize) -> PriceAggregator {
+        PriceAggregator {
+            sources: vec![],
+            policy,
+            min_sources,
+        }
+    }
+
+    /// A source weighted 0 is never asked
+    pub fn add_source(&mut self, source: impl PriceSource + 'static, weight: u64) {
+        if weight == 0 {
+            info!("price source {} is disabled", source.name());
+            return;
+        }
+
+        self.sources.push((Box::new(source), weight));
+    }
+
+    pub fn sources(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Price in ETH (1e18), sources that fail are left out
+    pub async fn collection_price(&self, nft_asset: NftAsset) -> Result<U256> {
+        let quotes = self.collection_quotes(nft_asset).await;
+
+        self.aggregate(&quotes)
+    }
+
+    /// Combines quotes with the aggregator's policy
+    pub fn aggregate(&self, quotes: &[Quote]) -> Result<U256> {
+        aggregate(quotes, self.policy, self.min_sources)
+    }
+
+    /// Quotes of every source that priced the collection
+    pub async fn collection_quotes(&self, nft_asset: NftAsset) -> Vec<Quote> {
+        let prices = join_all(
+            self.sources
+                .iter()
+                .map(|(source, _)| source.collection_price(nft_asset)),
+        )
+        .await;
+
+        let mut quotes = vec![];
+
+        for ((source, weight), price) in self.sources.iter().zip(prices) {
+            match price {
+                Ok(price) => {
+                    info!(
+                        "{} price of {:?} = {} ETH",
+                        source.name(),
+                        nft_asset,
+                        format_ether(price)
+                    );
+                    quotes.push(Quote {
+                        source: source.name(),
+                        price,
+                        weight: *weight,
+                    });
+                }
+                Err(e) => warn!("{} could not price {:?}: {}", source.name(), nft_asset, e),
+            }
+        }
+
+        quotes
+    }
+}
+
+/// Combines quotes with `policy`, failing with fewer than `min_sources` of them
+pub fn aggregate(quotes: &[Quote], policy: AggregationPolicy, min_sources: usize) -> Result<U256> {
+    if quotes.is_empty() || quotes.len() < min_sources {
+        bail!(
+            "{} price quotes, at least {} needed",
+            quotes.len(),
+            min_sources.max(1)
+        );
+    }
+
+    match policy {
+        AggregationPolicy::Min => Ok(quotes.iter().map(|quote| quote.price).min().unwrap()),
+        AggregationPolicy::Median => {
+            let mut quotes = quotes.to_vec();
+            quotes.sort_by_key(|quote| quote.price);
+
+            let total_weight: u64 = quotes.iter().map(|quote| quote.weight).sum();
+            let mut cumulative_weight = 0;
+
+            // lower weighted median, the price at least half the weight is at or below
+            for quote in &quotes {
+                cumulative_weight += quote.weight;
+                if cumulative_weight * 2 >= total_weight {
+                    return Ok(quote.price);
+                }
+            }
+
+            unreachable!("the cumulative weight ends at the total weight")
+        }
+    }
+}
+
+/// stBAYC has no market of its own, it is priced as BAYC
+pub fn market_asset(nft_asset: NftAsset) -> NftAsset {
+    match nft_asset {
+        NftAsset::StBayc => NftAsset::Bayc,
+        nft_asset => nft_asset,
+    }
+}
@@ -0,0 +1,50 @@
+use super::{market_asset, PriceSource};
+use crate::benddao::loan::NftAsset;
+use crate::blur::bids_response::ExecutableBidsResponse;
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::types::{Address, U256};
+use reqwest::{header::HeaderValue, Client};
+use url::Url;
+
+/// Blur has no public API, `api_url` is whichever gateway to its bids we have access to
+#[derive(Debug, Clone)]
+pub struct BlurSource {
+    http_client: Client,
+    api_url: String,
+    api_key: Option<String>,
+}
+
+impl BlurSource {
+    pub fn new(http_client: Client, api_url: String, api_key: Option<String>) -> BlurSource {
+        BlurSource {
+            http_client,
+            api_url,
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for BlurSource {
+    fn name(&self) -> &'static str {
+        "blur"
+    }
+
+    async fn collection_price(&self, nft_asset: NftAsset) -> Result<U256> {
+        let mut url: Url = self.api_url.parse()?;
+        url.set_path(&format!(
+            "v1/collections/{:?}/executable-bids",
+            Address::from(market_asset(nft_asset))
+        ));
+
+        let mut req = self.http_client.get(url);
+        if let Some(api_key) = &self.api_key {
+            req = req.header("x-api-key", HeaderValue::from_str(api_key)?);
+        }
+
+        let res: ExecutableBidsResponse = req.send().await?.error_for_status()?.json().await?;
+
+        res.get_best_bid()
+    }
+}
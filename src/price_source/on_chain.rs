@@ -0,0 +1,36 @@
+use super::PriceSource;
+use crate::{benddao::loan::NftAsset, rpc_pool::RpcPool, NFTOracle};
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::{providers::Provider, types::U256};
+
+/// The twap BendDAO's `NFTOracle` prices loans with.
+///
+/// It lags the market so it is a sanity check rather than a price we can sell at.
+/// Left out of the aggregate unless weighted in `price_source_weights`, the `PriceGuard`
+/// checks prices against it either way.
+#[derive(Debug, Clone)]
+pub struct OnChainSource {
+    nft_oracle: NFTOracle<Provider<RpcPool>>,
+}
+
+impl OnChainSource {
+    pub fn new(nft_oracle: NFTOracle<Provider<RpcPool>>) -> OnChainSource {
+        OnChainSource { nft_oracle }
+    }
+}
+
+#[async_trait]
+impl PriceSource for OnChainSource {
+    fn name(&self) -> &'static str {
+        "nft_oracle"
+    }
+
+    async fn collection_price(&self, nft_asset: NftAsset) -> Result<U256> {
+        Ok(self
+            .nft_oracle
+            .get_asset_price(nft_asset.into())
+            .call()
+            .await?)
+    }
+}
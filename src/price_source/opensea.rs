@@ -0,0 +1,62 @@
+use super::{market_asset, PriceSource};
+use crate::benddao::loan::NftAsset;
+use crate::opensea::offers_response::CollectionOffersResponse;
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::types::U256;
+use reqwest::{header::HeaderValue, Client};
+use url::Url;
+
+const OPENSEA_BASE_URL: &str = "https://api.opensea.io";
+
+#[derive(Debug, Clone)]
+pub struct OpenSeaSource {
+    http_client: Client,
+    api_key: String,
+}
+
+impl OpenSeaSource {
+    pub fn new(http_client: Client, api_key: String) -> OpenSeaSource {
+        OpenSeaSource {
+            http_client,
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for OpenSeaSource {
+    fn name(&self) -> &'static str {
+        "opensea"
+    }
+
+    async fn collection_price(&self, nft_asset: NftAsset) -> Result<U256> {
+        let mut url: Url = OPENSEA_BASE_URL.parse()?;
+        url.set_path(&format!("api/v2/offers/collection/{}", slug(nft_asset)));
+
+        let res: CollectionOffersResponse = self
+            .http_client
+            .get(url)
+            .header("x-api-key", HeaderValue::from_str(&self.api_key)?)
+            .header("Accept", "application/json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        res.get_best_offer()
+    }
+}
+
+/// OpenSea identifies collections by slug
+fn slug(nft_asset: NftAsset) -> &'static str {
+    match market_asset(nft_asset) {
+        NftAsset::Azuki => "azuki",
+        NftAsset::Bayc | NftAsset::StBayc => "boredapeyachtclub",
+        NftAsset::CryptoPunks => "cryptopunks",
+        NftAsset::Mayc => "mutant-ape-yacht-club",
+        NftAsset::CloneX => "clonex",
+        NftAsset::PudgyPenguins => "pudgypenguins",
+    }
+}
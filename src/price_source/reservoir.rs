@@ -0,0 +1,138 @@
+use super::{market_asset, PriceSource};
+use crate::benddao::loan::NftAsset;
+use crate::reservoir::floor_response::CollectionBidsResponse;
+use crate::reservoir::{sales_response::SalesResponse, tokens_response::TokensResponse};
+use crate::valuation::{
+    TokenValuation, COMPARABLE_SALES_LIMIT, MIN_COMPARABLE_SALES, RARE_ATTRIBUTES_TO_PRICE,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::types::{Address, U256};
+use reqwest::{header::HeaderValue, Client};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+const RESERVOIR_BASE_URL: &str = "https://api.reservoir.tools";
+
+#[derive(Debug, Clone)]
+pub struct ReservoirSource {
+    http_client: Client,
+    api_key: String,
+}
+
+impl ReservoirSource {
+    pub fn new(http_client: Client, api_key: String) -> ReservoirSource {
+        ReservoirSource {
+            http_client,
+            api_key,
+        }
+    }
+
+    /// Bids on the token and on its rarest attributes, and recent sales of tokens sharing
+    /// its rarest attribute that sold often enough to compare against
+    pub async fn token_valuation(
+        &self,
+        nft_asset: NftAsset,
+        nft_token_id: U256,
+    ) -> Result<TokenValuation> {
+        let collection = format!("{:?}", Address::from(market_asset(nft_asset)));
+        let token = format!("{collection}:{nft_token_id}");
+
+        let token_bids: CollectionBidsResponse = self
+            .get(
+                "orders/bids/v6",
+                &[("token", &token), ("sortBy", "price"), ("limit", "1")],
+            )
+            .await?;
+
+        let tokens: TokensResponse = self
+            .get(
+                "tokens/v7",
+                &[("tokens", &token), ("includeAttributes", "true")],
+            )
+            .await?;
+
+        let mut valuation = TokenValuation {
+            best_token_bid: token_bids.get_best_bid().ok(),
+            ..Default::default()
+        };
+
+        for attribute in tokens
+            .rarest_attributes()
+            .into_iter()
+            .take(RARE_ATTRIBUTES_TO_PRICE)
+        {
+            let bid_filter = format!("attribute[{}]", attribute.key);
+            let attribute_bids: CollectionBidsResponse = self
+                .get(
+                    "orders/bids/v6",
+                    &[
+                        ("collection", &collection),
+                        (&bid_filter, &attribute.value),
+                        ("sortBy", "price"),
+                        ("limit", "1"),
+                    ],
+                )
+                .await?;
+
+            if let Ok(bid) = attribute_bids.get_best_bid() {
+                valuation.best_attribute_bid = valuation.best_attribute_bid.max(Some(bid));
+            }
+
+            if valuation.comparable_sales.len() >= MIN_COMPARABLE_SALES {
+                continue;
+            }
+
+            let sales_filter = format!("attributes[{}]", attribute.key);
+            let sales: SalesResponse = self
+                .get(
+                    "sales/v6",
+                    &[
+                        ("collection", &collection),
+                        (&sales_filter, &attribute.value),
+                        ("limit", &COMPARABLE_SALES_LIMIT.to_string()),
+                    ],
+                )
+                .await?;
+
+            valuation.comparable_sales = sales.get_net_prices();
+        }
+
+        Ok(valuation)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> Result<T> {
+        let mut url: Url = RESERVOIR_BASE_URL.parse()?;
+        url.set_path(path);
+        url.query_pairs_mut().extend_pairs(query);
+
+        let res = self
+            .http_client
+            .get(url)
+            .header("x-api-key", HeaderValue::from_str(&self.api_key)?)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(res.json().await?)
+    }
+}
+
+#[async_trait]
+impl PriceSource for ReservoirSource {
+    fn name(&self) -> &'static str {
+        "reservoir"
+    }
+
+    async fn collection_price(&self, nft_asset: NftAsset) -> Result<U256> {
+        let path = format!(
+            "collections/{:?}/bids/v1",
+            Address::from(market_asset(nft_asset))
+        );
+
+        // collection wide bids
+        let res: CollectionBidsResponse = self.get(&path, &[("type", "collection")]).await?;
+
+        res.get_best_bid()
+    }
+}
@@ -1,130 +1,365 @@
 use crate::benddao::loan::ALL_ALLOWED_NFT_ASSETS;
-use crate::reservoir::floor_response::CollectionBidsResponse;
-use crate::Config;
-use crate::{benddao::loan::NftAsset, coinmarketcap::price_response::PriceResponse};
-use anyhow::Result;
+use crate::price_guard::{deviates, PriceEntry, PriceGuard, PriceRejection};
+use crate::price_source::{
+    market_asset, BlurSource, OpenSeaSource, PriceAggregator, PriceSource, ReservoirSource,
+};
+use crate::valuation::{bounded_token_value, TokenValuation, TOKEN_VALUATION_CONCURRENCY};
+use crate::{
+    benddao::loan::{NftAsset, ReserveAsset},
+    coinmarketcap::price_response::PriceResponse,
+};
+use crate::{rpc_pool::RpcPool, Config, ReserveOracle};
+use anyhow::{anyhow, bail, Result};
+use ethers::providers::Provider;
 use ethers::types::{Address, U256};
 use ethers::utils::format_ether;
-use futures::future::try_join_all;
+use futures::future::join_all;
+use futures::{stream, StreamExt};
 use log::{info, warn};
-use reqwest::{header::HeaderValue, Client};
+use reqwest::Client;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
 use url::Url;
 
-const RESERVOIR_BASE_URL: &str = "https://api.reservoir.tools";
 const COINMARKETCAP_BASE_URL: &str = "https://pro-api.coinmarketcap.com";
 
 pub struct PricesClient {
     http_client: Client,
-    pub eth_usd_price: U256,
-    pub prices: HashMap<NftAsset, U256>,
-    reservoir_api_key: String,
+    /// ETH per USDT as BendDAO's `ReserveOracle` prices it
+    eth_usd: Option<PriceEntry>,
+    /// ETH per USD from coinmarketcap at the same refresh, `None` if it failed
+    eth_usd_cross_check: Option<U256>,
+    /// how far `eth_usd_cross_check` may be from the `ReserveOracle` price, two decimals
+    max_eth_usd_deviation: U256,
+    /// set with `set_reserve_oracle` once connected
+    reserve_oracle: Option<ReserveOracle<Provider<RpcPool>>>,
+    pub prices: HashMap<NftAsset, PriceEntry>,
+    /// keyed by the asset as priced by the markets, stBAYC is BAYC.
+    /// with their value as last refreshed, for the `PriceGuard`
+    token_values: HashMap<(NftAsset, U256), (TokenValuation, PriceEntry)>,
+    guard: PriceGuard,
+    /// most a token is valued at as a multiple of its collection price, two decimals
+    max_token_value_multiple: U256,
+    /// collection prices
+    aggregator: PriceAggregator,
+    /// token valuations, only reservoir prices attributes and sales
+    reservoir: ReservoirSource,
     coinmarketcap_api_key: String,
 }
 
 impl PricesClient {
+    /// Prices with every marketplace configured, see `add_source` for the on-chain one
     pub fn new(config: Config) -> PricesClient {
+        let http_client = Client::new();
+        let reservoir = ReservoirSource::new(http_client.clone(), config.reservoir_api_key.clone());
+
+        let mut aggregator =
+            PriceAggregator::new(config.price_aggregation, config.min_price_sources);
+
+        aggregator.add_source(
+            reservoir.clone(),
+            config.price_source_weight(reservoir.name()),
+        );
+
+        if let Some(api_key) = config.opensea_api_key.clone().filter(|key| !key.is_empty()) {
+            let opensea = OpenSeaSource::new(http_client.clone(), api_key);
+            let weight = config.price_source_weight(opensea.name());
+            aggregator.add_source(opensea, weight);
+        }
+
+        if let Some(api_url) = config.blur_api_url.clone().filter(|url| !url.is_empty()) {
+            let blur = BlurSource::new(
+                http_client.clone(),
+                api_url,
+                config.blur_api_key.clone().filter(|key| !key.is_empty()),
+            );
+            let weight = config.price_source_weight(blur.name());
+            aggregator.add_source(blur, weight);
+        }
+
         PricesClient {
             prices: HashMap::new(),
-            eth_usd_price: U256::zero(),
-            reservoir_api_key: config.reservoir_api_key,
+            token_values: HashMap::new(),
+            eth_usd: None,
+            eth_usd_cross_check: None,
+            max_eth_usd_deviation: config.max_eth_usd_deviation.into(),
+            reserve_oracle: None,
+            guard: PriceGuard {
+                max_age: config.max_price_age,
+                max_twap_deviation: config.max_twap_deviation.into(),
+                max_change: config.max_price_change.into(),
+            },
+            max_token_value_multiple: config.max_token_value_multiple.into(),
+            aggregator,
+            reservoir,
             coinmarketcap_api_key: config.coinmarketcap_api_key,
-            http_client: Client::new(),
+            http_client,
         }
     }
 
-    /// Prices in ETH (1e18)
-    /// // TODO RETURN HASHMAP
+    /// USDT is priced with the `ReserveOracle` so our USDT math matches the lend pool's
+    pub fn set_reserve_oracle(&mut self, reserve_oracle: ReserveOracle<Provider<RpcPool>>) {
+        self.reserve_oracle = Some(reserve_oracle);
+    }
+
+    /// Adds a collection price source, weighted 0 it is left out
+    pub fn add_source(&mut self, source: impl PriceSource + 'static, weight: u64) {
+        self.aggregator.add_source(source, weight);
+    }
+
+    /// Prices in ETH (1e18), unchecked
     pub fn get_nft_price(&self, nft_asset: NftAsset) -> U256 {
-        let nft_asset = match nft_asset {
-            NftAsset::StBayc => NftAsset::Bayc,
-            _ => nft_asset,
+        self.prices
+            .get(&market_asset(nft_asset))
+            .map(|entry| entry.price)
+            .unwrap_or_default()
+    }
+
+    /// Value of a specific token in ETH (1e18), never below the collection wide best bid.
+    /// Tokens whose valuation was never refreshed are valued at the collection price
+    pub fn value(&self, nft_asset: NftAsset, nft_token_id: U256) -> U256 {
+        let token_value = self
+            .get_token_valuation(nft_asset, nft_token_id)
+            .map(TokenValuation::value)
+            .unwrap_or_default();
+
+        self.get_nft_price(nft_asset).max(token_value)
+    }
+
+    /// `value` if the collection price passes the `PriceGuard`, what bids are built with.
+    /// Token values go through the guard too, those it rejects are left out and the rest are
+    /// bounded by `max_token_value_multiple` of the guarded collection price.
+    /// `twap` is the collection's `NftOracle` price if known
+    pub fn checked_value(
+        &self,
+        nft_asset: NftAsset,
+        nft_token_id: U256,
+        twap: Option<U256>,
+        now: u64,
+    ) -> Result<U256, PriceRejection> {
+        let collection_price =
+            self.guard
+                .check(self.prices.get(&market_asset(nft_asset)), twap, now)?;
+
+        // the twap is collection wide, a rare token is rightly far from it
+        let token_value = match self
+            .token_values
+            .get(&(market_asset(nft_asset), nft_token_id))
+            .map(|(_, entry)| self.guard.check(Some(entry), None, now))
+        {
+            Some(Ok(token_value)) => token_value,
+            // nobody bids on the token
+            None | Some(Err(PriceRejection::Zero)) => U256::zero(),
+            Some(Err(reason)) => {
+                warn!(
+                    "valuing {:?} #{} at its collection price: {}",
+                    nft_asset, nft_token_id, reason
+                );
+                U256::zero()
+            }
         };
-        *self.prices.get(&nft_asset).unwrap_or(&U256::zero())
+
+        Ok(bounded_token_value(
+            collection_price,
+            token_value,
+            self.max_token_value_multiple,
+        ))
     }
 
-    /// Prices in ETH (1e18)
+    pub fn get_token_valuation(
+        &self,
+        nft_asset: NftAsset,
+        nft_token_id: U256,
+    ) -> Option<&TokenValuation> {
+        self.token_values
+            .get(&(market_asset(nft_asset), nft_token_id))
+            .map(|(valuation, _)| valuation)
+    }
+
+    /// ETH per USDT (1e18), unchecked
     pub fn get_eth_usd_price(&self) -> U256 {
-        self.eth_usd_price
+        self.eth_usd
+            .as_ref()
+            .map(|entry| entry.price)
+            .unwrap_or_default()
     }
 
-    pub async fn refresh_prices(&mut self) -> Result<()> {
-        self.refresh_eth_usd_price().await?;
-        self.refresh_nft_prices().await?;
-        Ok(())
+    /// `get_eth_usd_price` if it passes the `PriceGuard` and agrees with coinmarketcap.
+    /// Without a coinmarketcap price there is nothing to cross-check
+    pub fn checked_eth_usd_price(&self, now: u64) -> Result<U256, PriceRejection> {
+        let price = self.guard.check(self.eth_usd.as_ref(), None, now)?;
+
+        if let Some(cross_check) = self.eth_usd_cross_check {
+            if deviates(price, cross_check, self.max_eth_usd_deviation) {
+                return Err(PriceRejection::CrossCheck {
+                    price,
+                    source: "coinmarketcap",
+                    cross_check,
+                    max_deviation: self.max_eth_usd_deviation,
+                });
+            }
+        }
+
+        Ok(price)
     }
 
-    async fn refresh_nft_prices(&mut self) -> Result<()> {
-        let mut handles = Vec::new();
-
-        let reservoir_api_key = Arc::new(self.reservoir_api_key.clone());
-        let client = Arc::new(self.http_client.clone());
-
-        for nft_asset in ALL_ALLOWED_NFT_ASSETS {
-            let client = Arc::clone(&client);
-            let reservoir_api_key = Arc::clone(&reservoir_api_key);
-            let future = tokio::spawn(async move {
-                let nft_asset = nft_asset;
-                let price =
-                    PricesClient::get_best_nft_bid(client, nft_asset, &reservoir_api_key).await?;
-                anyhow::Ok((nft_asset, price))
-            });
-            handles.push(future);
+    /// A failed USDT price is only logged, it goes stale and only USDT loans are refused
+    pub async fn refresh_prices(&mut self) -> Result<()> {
+        if let Err(e) = self.refresh_eth_usd_price().await {
+            warn!("could not refresh the USDT price: {e}");
         }
+        self.refresh_nft_prices().await
+    }
 
-        let result = try_join_all(handles).await?;
+    /// Collections that can't be priced keep their last entry, which goes stale
+    async fn refresh_nft_prices(&mut self) -> Result<()> {
+        let quotes = join_all(
+            ALL_ALLOWED_NFT_ASSETS
+                .iter()
+                .map(|nft_asset| self.aggregator.collection_quotes(*nft_asset)),
+        )
+        .await;
+
+        let now = unix_timestamp()?;
+        let mut failed = vec![];
+
+        for (addr, quotes) in ALL_ALLOWED_NFT_ASSETS.into_iter().zip(quotes) {
+            let price = match self.aggregator.aggregate(&quotes) {
+                Ok(price) => price,
+                Err(e) => {
+                    warn!("could not price {addr:?}: {e}");
+                    failed.push(addr);
+                    continue;
+                }
+            };
+
+            info!("Price of {:?} = {} ETH", addr, format_ether(price));
+
+            let entry = PriceEntry {
+                price,
+                sources: quotes.iter().map(|quote| quote.source).collect(),
+                updated_at: now,
+                previous: self.prices.get(&addr).map(|entry| entry.price),
+            };
 
-        for res in result {
-            let (addr, price) = res?;
-            info!(
-                "Reservoir price of {:?} = {} ETH",
-                addr,
-                format_ether(price)
-            );
             if addr == NftAsset::Bayc {
-                self.prices.insert(NftAsset::StBayc, price);
+                self.prices.insert(NftAsset::StBayc, entry.clone());
             }
-            self.prices.insert(addr, price);
+            self.prices.insert(addr, entry);
+        }
+
+        if !failed.is_empty() {
+            bail!("could not price {failed:?}");
         }
 
         Ok(())
     }
 
-    async fn refresh_eth_usd_price(&mut self) -> Result<()> {
-        let usd_eth_price = self.get_usd_eth_price().await?;
-
-        let eth_usd_price = (1e18_f64 / usd_eth_price.floor()) as u64;
-        let eth_usd_price = U256::from(eth_usd_price);
-
-        self.eth_usd_price = eth_usd_price;
+    /// Refreshes the valuation of every token, tokens that fail keep their last valuation.
+    /// The requests are made without holding `prices_client`, it's only locked to read
+    /// the collection prices and store the results
+    pub async fn refresh_token_values(
+        prices_client: &RwLock<PricesClient>,
+        tokens: &[(NftAsset, U256)],
+    ) -> Result<()> {
+        let mut tokens: Vec<(NftAsset, U256)> = tokens
+            .iter()
+            .map(|(nft_asset, nft_token_id)| (market_asset(*nft_asset), *nft_token_id))
+            .collect();
+        tokens.sort_by_key(|(nft_asset, nft_token_id)| (Address::from(*nft_asset), *nft_token_id));
+        tokens.dedup();
+
+        let reservoir = prices_client.read().await.reservoir.clone();
+
+        let requests: Vec<_> = tokens
+            .iter()
+            .map(|(nft_asset, nft_token_id)| reservoir.token_valuation(*nft_asset, *nft_token_id))
+            .collect();
+        let valuations: Vec<_> = stream::iter(requests)
+            .buffered(TOKEN_VALUATION_CONCURRENCY)
+            .collect()
+            .await;
+
+        let now = unix_timestamp()?;
+        let mut prices_client = prices_client.write().await;
+
+        for ((nft_asset, nft_token_id), valuation) in tokens.into_iter().zip(valuations) {
+            match valuation {
+                Ok(valuation) => {
+                    prices_client.set_token_value(nft_asset, nft_token_id, valuation, now)
+                }
+                Err(e) => warn!("could not value {nft_asset:?} #{nft_token_id}: {e}"),
+            }
+        }
 
         Ok(())
     }
 
-    /// Price in ETH (1e18)
-    async fn get_best_nft_bid(
-        client: Arc<Client>,
+    fn set_token_value(
+        &mut self,
         nft_asset: NftAsset,
-        reservoir_api_key: &str,
-    ) -> Result<U256> {
-        let nft_asset = match nft_asset {
-            NftAsset::StBayc => NftAsset::Bayc,
-            nft_asset => nft_asset,
+        nft_token_id: U256,
+        valuation: TokenValuation,
+        now: u64,
+    ) {
+        let collection_price = self.get_nft_price(nft_asset);
+        if valuation.value() > collection_price {
+            info!(
+                "{:?} #{} valued at {} ETH, collection bid {} ETH",
+                nft_asset,
+                nft_token_id,
+                format_ether(valuation.value()),
+                format_ether(collection_price)
+            );
+        }
+        let entry = PriceEntry {
+            price: valuation.value(),
+            sources: vec![self.reservoir.name()],
+            updated_at: now,
+            previous: self
+                .token_values
+                .get(&(nft_asset, nft_token_id))
+                .map(|(_, entry)| entry.price),
         };
-        let mut url: Url = RESERVOIR_BASE_URL.parse()?;
-        let path = format!("collections/{:?}/bids/v1", Address::from(nft_asset));
-        url.set_path(&path);
-        url.set_query(Some("type=collection")); // collection wide bids
+        self.token_values
+            .insert((nft_asset, nft_token_id), (valuation, entry));
+    }
 
-        let res = client
-            .get(url)
-            .header("x-api-key", HeaderValue::from_str(reservoir_api_key)?)
-            .send()
+    async fn refresh_eth_usd_price(&mut self) -> Result<()> {
+        let reserve_oracle = self
+            .reserve_oracle
+            .as_ref()
+            .ok_or_else(|| anyhow!("no reserve oracle to price USDT with"))?;
+
+        let eth_usd_price = reserve_oracle
+            .get_asset_price(ReserveAsset::Usdt.into())
+            .call()
             .await?;
-        let res: CollectionBidsResponse = res.json().await?;
 
-        res.get_best_bid()
+        self.eth_usd_cross_check = match self.get_coinmarketcap_eth_usd_price().await {
+            Ok(price) => Some(price),
+            Err(e) => {
+                warn!("could not cross-check the USDT price with coinmarketcap: {e}");
+                None
+            }
+        };
+
+        self.eth_usd = Some(PriceEntry {
+            price: eth_usd_price,
+            sources: vec!["reserve_oracle"],
+            updated_at: unix_timestamp()?,
+            previous: self.eth_usd.as_ref().map(|entry| entry.price),
+        });
+
+        Ok(())
+    }
+
+    /// ETH per USD (1e18)
+    async fn get_coinmarketcap_eth_usd_price(&self) -> Result<U256> {
+        let usd_eth_price = self.get_usd_eth_price().await?;
+
+        Ok(U256::from((1e18_f64 / usd_eth_price) as u128))
     }
 
     async fn get_usd_eth_price(&self) -> Result<f64> {
@@ -146,6 +381,10 @@ impl PricesClient {
     }
 }
 
+fn unix_timestamp() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -156,11 +395,9 @@ mod tests {
         dotenv::dotenv().ok();
         let config_vars: Config = envy::from_env()?;
 
-        let mut client = PricesClient::new(config_vars);
-
-        client.refresh_eth_usd_price().await?;
+        let client = PricesClient::new(config_vars);
 
-        let eth_usd_price = client.get_eth_usd_price();
+        let eth_usd_price = client.get_coinmarketcap_eth_usd_price().await?;
 
         println!("eth_usd_price: {}", eth_usd_price);
 
@@ -177,7 +414,7 @@ mod tests {
 
         let mut client = PricesClient::new(config_vars);
 
-        client.refresh_prices().await?;
+        client.refresh_nft_prices().await?;
 
         let bayc_eth_price = client.get_nft_price(NftAsset::Bayc);
 
@@ -186,7 +423,7 @@ mod tests {
         // unlikely 1 BAYC < 1 ETH
         assert!(bayc_eth_price > parse_ether("1").unwrap());
 
-        let eth_usd = client.get_eth_usd_price();
+        let eth_usd = client.get_coinmarketcap_eth_usd_price().await?;
         let bayc_usd = bayc_eth_price * U256::exp10(6) / eth_usd;
 
         println!("bayc_usd_price: {}", bayc_usd);
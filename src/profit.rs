@@ -0,0 +1,194 @@
+use crate::{
+    benddao::loan::ReserveAsset,
+    constants::{LIQUIDATE_GAS, ONE_YEAR, PERCENTAGE_FACTOR},
+    math::percent_mul,
+    Config,
+};
+use ethers::{
+    types::{I256, U256},
+    utils::format_ether,
+};
+use std::fmt::{self, Display};
+
+/// Costs and risks every auction, outbid and liquidation is weighed against.
+/// Percentages have two decimals
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProfitModel {
+    /// marketplace fee and royalties paid when reselling, on top of what the price
+    /// sources already net out
+    pub resale_fee: U256,
+    /// yearly cost of the capital locked in a bid
+    pub annual_capital_cost: U256,
+    /// chance the borrower redeems before the auction ends
+    pub redemption_probability: U256,
+    /// expected profit needed, as a share of the bid
+    pub min_margin: U256,
+    /// paid to the builder per bundle by the executor, in ETH (1e18).
+    /// Zero without one, bids sent from the wallet only pay priority fees
+    pub bundle_tip: U256,
+    /// share of the expected profit the `GasStrategy` tips the builder on a bid tx
+    pub gas_profit_share: U256,
+}
+
+impl ProfitModel {
+    pub fn from_config(config: &Config) -> ProfitModel {
+        ProfitModel {
+            resale_fee: config.resale_fee.into(),
+            annual_capital_cost: config.annual_capital_cost.into(),
+            redemption_probability: config.redemption_probability.into(),
+            min_margin: config.min_profit_margin.into(),
+            bundle_tip: match config.executor_address() {
+                Ok(Some(_)) => config.bundle_tip.into(),
+                _ => U256::zero(),
+            },
+            gas_profit_share: config.gas_profit_share.into(),
+        }
+    }
+
+    /// Expected PnL of bidding `bid` on a token worth `value`.
+    ///
+    /// Redeemed, the bid comes back with the redeem fine and only the bid tx was paid.
+    /// Otherwise we liquidate after the auction and resell at `value` minus fees.
+    /// Either way the bid is locked for `opportunity.holding` seconds.
+    ///
+    /// The bid tx's priority fee is at least `gas_profit_share` of what it's expected to
+    /// make, that share is taken out on top of the gas at `opportunity.gas_price`.
+    pub fn estimate(&self, opportunity: &Opportunity) -> ProfitEstimate {
+        let percentage_factor = U256::from(PERCENTAGE_FACTOR);
+        let not_redeemed = percentage_factor - self.redemption_probability.min(percentage_factor);
+
+        let resale = opportunity.value - percent_mul(opportunity.value, self.resale_fee);
+        let resale_profit = to_signed(resale) - to_signed(opportunity.bid);
+
+        let bid_gas = opportunity.gas_price * opportunity.bid_gas;
+        let liquidate_gas = opportunity.gas_price * opportunity.liquidate_gas;
+        let gas = bid_gas + percent_mul(liquidate_gas, not_redeemed);
+
+        let capital_cost =
+            percent_mul(opportunity.bid, self.annual_capital_cost) * opportunity.holding / ONE_YEAR;
+
+        let expected_resale_profit =
+            resale_profit * to_signed(not_redeemed) / to_signed(percentage_factor);
+        let expected_fine = percent_mul(opportunity.redeem_fine, self.redemption_probability);
+
+        let pnl_before_profit_share = expected_resale_profit + to_signed(expected_fine)
+            - to_signed(gas)
+            - to_signed(self.bundle_tip)
+            - to_signed(capital_cost);
+        let profit_share = percent_mul(
+            pnl_before_profit_share.max(I256::zero()).into_raw(),
+            self.gas_profit_share,
+        );
+        let expected_pnl = pnl_before_profit_share - to_signed(profit_share);
+
+        ProfitEstimate {
+            expected_resale_profit,
+            expected_fine,
+            gas,
+            tip: self.bundle_tip,
+            profit_share,
+            capital_cost,
+            expected_pnl,
+            min_pnl: percent_mul(opportunity.bid, self.min_margin),
+        }
+    }
+
+    /// PnL of liquidating an auction won with `bid`, it can't be redeemed anymore
+    /// and the liquidate tx is sent directly so there is no tip nor profit share
+    pub fn estimate_liquidation(&self, bid: U256, value: U256, gas_price: U256) -> ProfitEstimate {
+        let model = ProfitModel {
+            redemption_probability: U256::zero(),
+            bundle_tip: U256::zero(),
+            gas_profit_share: U256::zero(),
+            ..*self
+        };
+
+        model.estimate(&Opportunity {
+            bid,
+            value,
+            gas_price,
+            bid_gas: U256::zero(),
+            liquidate_gas: LIQUIDATE_GAS.into(),
+            holding: U256::zero(),
+            redeem_fine: U256::zero(),
+        })
+    }
+}
+
+/// A bid we could make, amounts in ETH (1e18)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Opportunity {
+    pub bid: U256,
+    /// what the token resells for
+    pub value: U256,
+    /// wei per gas
+    pub gas_price: U256,
+    /// gas of the auction tx
+    pub bid_gas: U256,
+    /// gas of the liquidate tx once the auction is won
+    pub liquidate_gas: U256,
+    /// seconds the bid stays locked
+    pub holding: U256,
+    /// paid to the first bidder if the borrower redeems, zero if that isn't us
+    pub redeem_fine: U256,
+}
+
+/// Breakdown of `ProfitModel::estimate`, in ETH (1e18)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProfitEstimate {
+    /// resale proceeds minus the bid, weighted by the chance of no redemption
+    pub expected_resale_profit: I256,
+    /// redeem fine weighted by the chance of a redemption
+    pub expected_fine: U256,
+    pub gas: U256,
+    pub tip: U256,
+    /// priority fee above `gas` the gas strategy pays for the expected profit
+    pub profit_share: U256,
+    pub capital_cost: U256,
+    pub expected_pnl: I256,
+    /// expected PnL needed to bid
+    pub min_pnl: U256,
+}
+
+impl ProfitEstimate {
+    pub fn is_profitable(&self) -> bool {
+        self.expected_pnl >= to_signed(self.min_pnl)
+    }
+
+    /// `expected_pnl`, zero if it's a loss
+    pub fn expected_profit(&self) -> U256 {
+        self.expected_pnl.max(I256::zero()).into_raw()
+    }
+}
+
+impl Display for ProfitEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected pnl {} ETH (min {}) = resale {} + fine {} - gas {} - tip {} - profit share {} - capital {}",
+            format_signed_ether(self.expected_pnl),
+            format_ether(self.min_pnl),
+            format_signed_ether(self.expected_resale_profit),
+            format_ether(self.expected_fine),
+            format_ether(self.gas),
+            format_ether(self.tip),
+            format_ether(self.profit_share),
+            format_ether(self.capital_cost),
+        )
+    }
+}
+
+/// `amount` of `reserve_asset` in ETH (1e18), `reserve_price` is ETH per whole unit
+pub fn to_eth(amount: U256, reserve_asset: ReserveAsset, reserve_price: U256) -> U256 {
+    amount * reserve_price / U256::exp10(reserve_asset.decimals() as usize)
+}
+
+fn to_signed(value: U256) -> I256 {
+    I256::from_raw(value)
+}
+
+fn format_signed_ether(value: I256) -> String {
+    let sign = if value.is_negative() { "-" } else { "" };
+
+    format!("{sign}{}", format_ether(value.unsigned_abs()))
+}
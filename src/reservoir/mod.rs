@@ -1 +1,3 @@
 pub mod floor_response;
+pub mod sales_response;
+pub mod tokens_response;
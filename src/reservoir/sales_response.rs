@@ -0,0 +1,35 @@
+use ethers::types::U256;
+use serde::Deserialize;
+
+/// `sales/v6`
+#[derive(Debug, Deserialize)]
+pub struct SalesResponse {
+    sales: Vec<Sale>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Sale {
+    price: Price,
+}
+
+#[derive(Debug, Deserialize)]
+struct Price {
+    #[serde(rename = "netAmount")]
+    net_amount: NetAmount,
+}
+
+#[derive(Debug, Deserialize)]
+struct NetAmount {
+    /// in ETH, whatever the sale currency was
+    native: f64,
+}
+
+impl SalesResponse {
+    /// What the sellers received in ETH (1e18)
+    pub fn get_net_prices(&self) -> Vec<U256> {
+        self.sales
+            .iter()
+            .map(|sale| U256::from((sale.price.net_amount.native * 1e18) as u128))
+            .collect()
+    }
+}
@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+/// `tokens/v7` with `includeAttributes=true`
+#[derive(Debug, Deserialize)]
+pub struct TokensResponse {
+    tokens: Vec<TokenEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenEntry {
+    token: Token,
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    #[serde(default)]
+    attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Attribute {
+    pub key: String,
+    pub value: String,
+    /// tokens of the collection that share this attribute
+    #[serde(rename = "tokenCount")]
+    pub token_count: u64,
+}
+
+impl TokensResponse {
+    /// Attributes of the first token, the rarest first
+    pub fn rarest_attributes(&self) -> Vec<Attribute> {
+        let mut attributes = self
+            .tokens
+            .first()
+            .map(|entry| entry.token.attributes.clone())
+            .unwrap_or_default();
+
+        attributes.sort_by_key(|attribute| attribute.token_count);
+
+        attributes
+    }
+}
@@ -0,0 +1,308 @@
+use crate::constants::*;
+use anyhow::Result;
+use ethers::{
+    contract::{EthLogDecode, LogMeta},
+    providers::{Middleware, Provider, StreamExt, Ws},
+    types::{Address, Block, Filter, Log, Transaction, TxHash, U256, U64},
+};
+use log::{debug, error, info, warn};
+use messenger_rs::slack_hook::SlackClient;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{future::Future, sync::Arc};
+use tokio::{
+    sync::RwLock,
+    time::{sleep, Duration},
+};
+
+/// first wait before reconnecting, doubled on every failed attempt
+const RECONNECT_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+struct Connection {
+    /// bumped on every reconnect so concurrent subscribers only reconnect once
+    generation: u64,
+    provider: Arc<Provider<Ws>>,
+}
+
+/// Websocket provider for the long-running subscriptions.
+///
+/// The underlying `Ws` has its own reconnects disabled, so a dropped connection ends
+/// every subscription stream instead of silently resubscribing with a gap. The `follow_*`
+/// methods then reconnect with exponential backoff, resubscribe and, for logs, backfill
+/// the blocks they missed by range. Every reconnect is reported to slack.
+pub struct ResilientWs {
+    url: String,
+    slack: Option<SlackClient>,
+    connection: RwLock<Connection>,
+}
+
+impl ResilientWs {
+    pub async fn connect(url: &str, slack: Option<SlackClient>) -> Result<ResilientWs> {
+        let provider = Self::connect_provider(url).await?;
+
+        Ok(ResilientWs {
+            url: url.to_string(),
+            slack,
+            connection: RwLock::new(Connection {
+                generation: 0,
+                provider: Arc::new(provider),
+            }),
+        })
+    }
+
+    async fn connect_provider(url: &str) -> Result<Provider<Ws>> {
+        let ws = Ws::connect_with_reconnects(url, 0).await?;
+
+        Ok(Provider::new(ws))
+    }
+
+    /// Provider of the current connection, only valid until the next reconnect
+    pub async fn provider(&self) -> Arc<Provider<Ws>> {
+        self.connection.read().await.provider.clone()
+    }
+
+    async fn current(&self) -> (u64, Arc<Provider<Ws>>) {
+        let connection = self.connection.read().await;
+
+        (connection.generation, connection.provider.clone())
+    }
+
+    /// Replaces the connection of `generation`, retrying until it succeeds.
+    /// Does nothing if another subscriber already replaced it.
+    async fn reconnect(&self, generation: u64, reason: &str) {
+        let mut connection = self.connection.write().await;
+
+        if connection.generation != generation {
+            return;
+        }
+
+        warn!("websocket connection lost: {reason}");
+
+        let mut backoff = RECONNECT_BACKOFF_BASE;
+        let mut attempts = 1;
+
+        let provider = loop {
+            match Self::connect_provider(&self.url).await {
+                Ok(provider) => break provider,
+                Err(e) => {
+                    error!(
+                        "failed to reconnect (attempt {attempts}): {e}, retrying in {backoff:?}"
+                    );
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                    attempts += 1;
+                }
+            }
+        };
+
+        connection.provider = Arc::new(provider);
+        connection.generation += 1;
+
+        let message = format!(
+            "Websocket reconnected after {attempts} attempt(s), resubscribing. Reason: {reason}"
+        );
+        info!("{message}");
+
+        if let Some(slack) = &self.slack {
+            if let Err(e) = slack.send_message(message).await {
+                error!("failed to send slack message {e}");
+            }
+        }
+    }
+
+    /// Calls `handle` with every new block, forever
+    pub async fn follow_blocks<F, Fut>(&self, handle: F) -> Result<()>
+    where
+        F: FnMut(Block<TxHash>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        self.follow(json!(["newHeads"]), handle).await
+    }
+
+    /// Calls `handle` with every pending transaction, forever
+    pub async fn follow_pending_txs<F, Fut>(&self, handle: F) -> Result<()>
+    where
+        F: FnMut(Transaction) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        self.follow(json!(["newPendingTransactions", true]), handle)
+            .await
+    }
+
+    /// Resubscribes with `params` every time the subscription ends.
+    /// Only returns if `handle` fails.
+    async fn follow<R, F, Fut>(&self, params: Value, mut handle: F) -> Result<()>
+    where
+        R: DeserializeOwned + Send + Sync,
+        F: FnMut(R) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        loop {
+            let (generation, provider) = self.current().await;
+
+            let reason = match provider.subscribe::<_, R>(params.clone()).await {
+                Ok(mut stream) => {
+                    while let Some(item) = stream.next().await {
+                        handle(item).await?;
+                    }
+                    format!("subscription to {params} ended")
+                }
+                Err(e) => format!("failed to subscribe to {params}: {e}"),
+            };
+
+            self.reconnect(generation, &reason).await;
+        }
+    }
+
+    /// Calls `handle` with every log of `address` decoded as `D`, forever. The last argument
+    /// is `true` for a log that was reorged out after being handled.
+    ///
+    /// After a reconnect every block since the last handled log, or since the first
+    /// subscription if there was none, is queried so nothing is missed in between.
+    /// Logs that were already handled are skipped. Only returns if `handle` fails.
+    pub async fn follow_logs<D, F, Fut>(&self, address: Address, mut handle: F) -> Result<()>
+    where
+        D: EthLogDecode,
+        F: FnMut(D, LogMeta, bool) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut cursor = None;
+
+        loop {
+            let (generation, provider) = self.current().await;
+
+            let reason = Self::stream_logs(&provider, address, &mut cursor, &mut handle).await?;
+
+            self.reconnect(generation, &reason).await;
+        }
+    }
+
+    /// Backfills and then follows the subscription until the connection drops.
+    /// Returns why it dropped, errors are `handle`'s.
+    async fn stream_logs<D, F, Fut>(
+        provider: &Provider<Ws>,
+        address: Address,
+        cursor: &mut Option<LogCursor>,
+        handle: &mut F,
+    ) -> Result<String>
+    where
+        D: EthLogDecode,
+        F: FnMut(D, LogMeta, bool) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let filter = Filter::new().address(address);
+
+        let resuming = cursor.is_some();
+
+        // the first subscription starts at the current block, if it drops before any
+        // log arrives the next one still backfills from here
+        if !resuming {
+            let block = match provider.get_block_number().await {
+                Ok(block) => block,
+                Err(e) => return Ok(format!("failed to get block number: {e}")),
+            };
+            *cursor = Some(LogCursor {
+                block,
+                log_index: None,
+            });
+        }
+
+        let cursor = cursor.as_mut().expect("set above");
+
+        // subscribe before backfilling so no block falls in between
+        let mut stream = match provider.subscribe_logs(&filter).await {
+            Ok(stream) => stream,
+            Err(e) => return Ok(format!("failed to subscribe to logs of {address:?}: {e}")),
+        };
+
+        if resuming {
+            let latest_block = match provider.get_block_number().await {
+                Ok(block) => block.as_u64(),
+                Err(e) => return Ok(format!("failed to get block number: {e}")),
+            };
+
+            info!(
+                "backfilling logs of {address:?} from block {} to {latest_block}",
+                cursor.block
+            );
+
+            // the cursor's block is queried again in case it wasn't fully delivered
+            let mut from_block = cursor.block.as_u64();
+
+            while from_block <= latest_block {
+                let chunk_end = (from_block + LOG_QUERY_BLOCK_RANGE - 1).min(latest_block);
+
+                let range = filter.clone().from_block(from_block).to_block(chunk_end);
+                let logs = match provider.get_logs(&range).await {
+                    Ok(logs) => logs,
+                    Err(e) => return Ok(format!("failed to backfill logs of {address:?}: {e}")),
+                };
+
+                for log in logs {
+                    cursor.handle_log(log, handle).await?;
+                }
+
+                from_block = chunk_end + 1;
+            }
+        }
+
+        while let Some(log) = stream.next().await {
+            cursor.handle_log(log, handle).await?;
+        }
+
+        Ok(format!("log subscription of {address:?} ended"))
+    }
+}
+
+/// Where a log subscription resumes from after a reconnect
+struct LogCursor {
+    block: U64,
+    /// `None` until a log of `block` is handled
+    log_index: Option<U256>,
+}
+
+impl LogCursor {
+    /// Passes `log` to `handle` unless it is pending or was already handled. Removed logs
+    /// are always passed and move the cursor back so the log is handled again if it's
+    /// re-included.
+    async fn handle_log<D, F, Fut>(&mut self, log: Log, handle: &mut F) -> Result<()>
+    where
+        D: EthLogDecode,
+        F: FnMut(D, LogMeta, bool) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        if log.block_number.is_none() || log.log_index.is_none() {
+            return Ok(());
+        }
+
+        let removed = log.removed == Some(true);
+        let meta = LogMeta::from(&log);
+
+        let already_handled = match self.log_index {
+            Some(log_index) => (meta.block_number, meta.log_index) <= (self.block, log_index),
+            None => meta.block_number < self.block,
+        };
+
+        if removed {
+            if meta.block_number <= self.block {
+                self.block = meta.block_number;
+                self.log_index = None;
+            }
+        } else {
+            if already_handled {
+                return Ok(());
+            }
+            self.block = meta.block_number;
+            self.log_index = Some(meta.log_index);
+        }
+
+        match D::decode_log(&log.into()) {
+            Ok(event) => handle(event, meta, removed).await,
+            Err(e) => {
+                debug!("skipping log that doesn't decode: {e}");
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::resilient_ws::ResilientWs;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use ethers::{
+    providers::{JsonRpcClient, JsonRpcError, ProviderError, RpcError, Ws, WsClientError},
+    types::{Transaction, TxHash, U64},
+};
+use futures::future::join_all;
+use log::{error, info, warn};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::{self, Debug, Display},
+    future::Future,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::Instant,
+};
+use tokio::{
+    sync::mpsc,
+    time::{sleep, timeout, Duration},
+};
+
+/// how often every node is probed, ejected nodes are reconnected
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// a node that takes longer than this is ejected and the request goes to the next one
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// pending tx hashes remembered to de-duplicate the merged subscriptions
+const SEEN_PENDING_TXS: usize = 10_000;
+
+#[derive(Debug, Default)]
+struct NodeStats {
+    /// moving average of the response time
+    latency: Option<Duration>,
+    ejected: bool,
+}
+
+#[derive(Debug)]
+struct Node {
+    url: String,
+    /// `None` if the node has never connected
+    client: RwLock<Option<Ws>>,
+    stats: Mutex<NodeStats>,
+}
+
+impl Node {
+    fn client(&self) -> Option<Ws> {
+        self.client.read().unwrap().clone()
+    }
+
+    fn is_ejected(&self) -> bool {
+        self.stats.lock().unwrap().ejected
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        self.stats.lock().unwrap().latency
+    }
+
+    fn record_latency(&self, elapsed: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+
+        stats.latency = Some(match stats.latency {
+            Some(latency) => (latency * 4 + elapsed) / 5,
+            None => elapsed,
+        });
+    }
+
+    fn eject(&self, reason: impl Display) {
+        let mut stats = self.stats.lock().unwrap();
+
+        if !stats.ejected {
+            warn!("ejecting rpc node {}: {reason}", self.url);
+        }
+
+        stats.ejected = true;
+        stats.latency = None;
+    }
+
+    /// Times an `eth_blockNumber`, reconnecting first if the node is ejected.
+    /// Ejects the node if it fails and restores it if it succeeds.
+    async fn probe(&self) {
+        let client = match self.client() {
+            Some(client) if !self.is_ejected() => client,
+            _ => match Ws::connect(self.url.as_str()).await {
+                Ok(client) => {
+                    *self.client.write().unwrap() = Some(client.clone());
+                    client
+                }
+                Err(e) => return self.eject(e),
+            },
+        };
+
+        let start = Instant::now();
+
+        match timeout(
+            REQUEST_TIMEOUT,
+            client.request::<_, U64>("eth_blockNumber", ()),
+        )
+        .await
+        {
+            Ok(Ok(_)) => {
+                self.record_latency(start.elapsed());
+
+                let mut stats = self.stats.lock().unwrap();
+                if stats.ejected {
+                    info!("rpc node {} is back", self.url);
+                    stats.ejected = false;
+                }
+            }
+            Ok(Err(e)) => self.eject(e),
+            Err(_) => self.eject("probe timed out"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RpcPoolError {
+    /// the answer of a node, or the last failure if none answered
+    Node(WsClientError),
+    /// the last node tried didn't answer within `REQUEST_TIMEOUT`
+    Timeout,
+    /// no node has ever connected
+    NoNodes,
+    SerdeJson(serde_json::Error),
+}
+
+impl Display for RpcPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcPoolError::Node(e) => write!(f, "{e}"),
+            RpcPoolError::Timeout => write!(f, "rpc request timed out on every node"),
+            RpcPoolError::NoNodes => write!(f, "no rpc node is connected"),
+            RpcPoolError::SerdeJson(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RpcPoolError {}
+
+impl RpcError for RpcPoolError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            RpcPoolError::Node(e) => e.as_error_response(),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            RpcPoolError::Node(e) => e.as_serde_error(),
+            RpcPoolError::SerdeJson(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<RpcPoolError> for ProviderError {
+    fn from(src: RpcPoolError) -> Self {
+        match src {
+            RpcPoolError::Node(e) => e.into(),
+            RpcPoolError::SerdeJson(e) => e.into(),
+            _ => ProviderError::JsonRpcClientError(Box::new(src)),
+        }
+    }
+}
+
+/// `JsonRpcClient` over several websocket nodes.
+///
+/// Every request goes to the healthy node with the lowest average latency and fails
+/// over to the next one if the node doesn't answer. Nodes that fail are ejected and
+/// only tried once every healthy node failed too. All nodes are probed every
+/// `PROBE_INTERVAL`, which keeps the latencies current and brings ejected nodes back.
+#[derive(Debug, Clone)]
+pub struct RpcPool {
+    nodes: Arc<Vec<Node>>,
+}
+
+impl RpcPool {
+    /// Connects to every url, fails only if none of them connects
+    pub async fn connect(urls: &[String]) -> Result<RpcPool> {
+        let nodes: Vec<Node> = urls
+            .iter()
+            .map(|url| Node {
+                url: url.clone(),
+                client: RwLock::new(None),
+                stats: Mutex::new(NodeStats::default()),
+            })
+            .collect();
+
+        join_all(nodes.iter().map(|node| node.probe())).await;
+
+        if nodes.iter().all(|node| node.is_ejected()) {
+            bail!("none of the {} rpc nodes could be reached", nodes.len());
+        }
+
+        let pool = RpcPool {
+            nodes: Arc::new(nodes),
+        };
+
+        for node in pool.nodes.iter() {
+            match node.latency() {
+                Some(latency) => info!("rpc node {} responded in {latency:?}", node.url),
+                None => error!("rpc node {} is down", node.url),
+            }
+        }
+
+        pool.spawn_prober();
+
+        Ok(pool)
+    }
+
+    pub fn healthy_nodes(&self) -> usize {
+        self.nodes.iter().filter(|node| !node.is_ejected()).count()
+    }
+
+    /// Healthy nodes from the fastest to the slowest, then the ejected ones
+    fn candidates(&self) -> Vec<&Node> {
+        let mut candidates: Vec<&Node> = self.nodes.iter().collect();
+
+        candidates.sort_by_key(|node| (node.is_ejected(), node.latency().unwrap_or(Duration::MAX)));
+
+        candidates
+    }
+
+    /// Probes every node until the pool is dropped
+    fn spawn_prober(&self) {
+        let nodes: Weak<Vec<Node>> = Arc::downgrade(&self.nodes);
+
+        tokio::spawn(async move {
+            loop {
+                sleep(PROBE_INTERVAL).await;
+
+                let Some(nodes) = nodes.upgrade() else {
+                    return;
+                };
+
+                join_all(nodes.iter().map(|node| node.probe())).await;
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for RpcPool {
+    type Error = RpcPoolError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params).map_err(RpcPoolError::SerdeJson)?;
+
+        let mut last_error = RpcPoolError::NoNodes;
+
+        for node in self.candidates() {
+            let Some(client) = node.client() else {
+                continue;
+            };
+
+            let start = Instant::now();
+
+            match timeout(REQUEST_TIMEOUT, client.request::<_, R>(method, &params)).await {
+                Ok(Ok(res)) => {
+                    node.record_latency(start.elapsed());
+                    return Ok(res);
+                }
+                // the node answered, reverts and the like are the same on every node
+                Ok(Err(e @ (WsClientError::JsonRpcError(_) | WsClientError::JsonError(_)))) => {
+                    node.record_latency(start.elapsed());
+                    return Err(RpcPoolError::Node(e));
+                }
+                Ok(Err(e)) => {
+                    node.eject(&e);
+                    last_error = RpcPoolError::Node(e);
+                }
+                Err(_) => {
+                    node.eject(format!("{method} timed out"));
+                    last_error = RpcPoolError::Timeout;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+/// Calls `handle` once with every pending transaction seen by any of `nodes`, as soon as
+/// the first node delivers it. Only returns if `handle` fails.
+pub async fn follow_pending_txs<F, Fut>(nodes: &[Arc<ResilientWs>], mut handle: F) -> Result<()>
+where
+    F: FnMut(Transaction) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    let forwarders: Vec<_> = nodes
+        .iter()
+        .map(|ws| {
+            let ws = ws.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                ws.follow_pending_txs(|tx| {
+                    let sent = sender.send(tx);
+                    async move { sent.map_err(|_| anyhow!("pending tx receiver dropped")) }
+                })
+                .await
+            })
+        })
+        .collect();
+
+    drop(sender);
+
+    let mut seen = HashSet::new();
+    let mut seen_order: VecDeque<TxHash> = VecDeque::new();
+
+    let result = loop {
+        let Some(tx) = receiver.recv().await else {
+            break Ok(());
+        };
+
+        if !seen.insert(tx.hash) {
+            continue;
+        }
+
+        seen_order.push_back(tx.hash);
+        if seen_order.len() > SEEN_PENDING_TXS {
+            if let Some(oldest) = seen_order.pop_front() {
+                seen.remove(&oldest);
+            }
+        }
+
+        if let Err(e) = handle(tx).await {
+            break Err(e);
+        }
+    };
+
+    for forwarder in forwarders {
+        forwarder.abort();
+    }
+
+    result
+}
@@ -0,0 +1,248 @@
+use revm::{
+    db::{AccountState, CacheDB, DbAccount},
+    primitives::{AccountInfo, Address, Bytecode, B256, U256},
+    DatabaseRef,
+};
+use std::{
+    cell::RefCell,
+    sync::{Mutex, MutexGuard},
+};
+
+/// A `CacheDB` shared between tasks whose lock is never held across a remote read.
+///
+/// Executions run on an overlay that looks into the cache under the lock and goes to the
+/// underlying db without it. What they fetched is committed afterwards, unless the cache
+/// moved to another block in the meantime.
+pub struct SharedDb<DB> {
+    inner: Mutex<Versioned<DB>>,
+}
+
+struct Versioned<DB> {
+    db: CacheDB<DB>,
+    /// bumped on every update so reads of an older state aren't committed
+    version: u64,
+}
+
+/// Accounts, slots, code and block hashes an execution read from the underlying db
+struct Fetched {
+    accounts: Vec<(Address, Option<AccountInfo>)>,
+    storage: Vec<(Address, U256, U256)>,
+    contracts: Vec<(B256, Bytecode)>,
+    block_hashes: Vec<(U256, B256)>,
+}
+
+/// Reads the shared cache and falls back to a copy of its underlying db outside the lock
+pub struct ReadThrough<'a, DB> {
+    shared: &'a SharedDb<DB>,
+    remote: DB,
+    fetched: RefCell<Fetched>,
+}
+
+impl<DB: DatabaseRef + Clone> SharedDb<DB> {
+    pub fn new(db: CacheDB<DB>) -> Self {
+        Self {
+            inner: Mutex::new(Versioned { db, version: 0 }),
+        }
+    }
+
+    /// Reads the cached state, `f` must not go to the underlying db
+    pub fn inspect<T>(&self, f: impl FnOnce(&CacheDB<DB>) -> T) -> T {
+        f(&self.lock().db)
+    }
+
+    /// Changes the cached state, `f` must not go to the underlying db
+    pub fn update<T>(&self, f: impl FnOnce(&mut CacheDB<DB>) -> T) -> T {
+        let mut inner = self.lock();
+        inner.version += 1;
+        f(&mut inner.db)
+    }
+
+    /// Runs `f` on a throwaway overlay of the cached state. Writes stay in the overlay,
+    /// reads it had to fetch are kept in the cache
+    pub fn execute<T>(&self, f: impl FnOnce(&mut CacheDB<ReadThrough<'_, DB>>) -> T) -> T {
+        let (remote, version) = {
+            let inner = self.lock();
+            (inner.db.db.clone(), inner.version)
+        };
+
+        let mut overlay = CacheDB::new(ReadThrough {
+            shared: self,
+            remote,
+            fetched: RefCell::new(Fetched {
+                accounts: vec![],
+                storage: vec![],
+                contracts: vec![],
+                block_hashes: vec![],
+            }),
+        });
+
+        let result = f(&mut overlay);
+
+        self.commit(version, overlay.db.fetched.into_inner());
+
+        result
+    }
+
+    /// Adds what was fetched at `version` and isn't cached yet
+    fn commit(&self, version: u64, fetched: Fetched) {
+        let mut inner = self.lock();
+        if inner.version != version {
+            return;
+        }
+
+        let db = &mut inner.db;
+        for (address, info) in fetched.accounts {
+            if db.accounts.contains_key(&address) {
+                continue;
+            }
+            match info {
+                Some(info) => db.insert_account_info(address, info),
+                None => {
+                    db.accounts.insert(address, DbAccount::new_not_existing());
+                }
+            }
+        }
+        for (address, slot, value) in fetched.storage {
+            // inserting a slot of an uncached account would fetch the account
+            if let Some(account) = db.accounts.get_mut(&address) {
+                account.storage.entry(slot).or_insert(value);
+            }
+        }
+        for (code_hash, bytecode) in fetched.contracts {
+            db.contracts.entry(code_hash).or_insert(bytecode);
+        }
+        for (number, hash) in fetched.block_hashes {
+            db.block_hashes.entry(number).or_insert(hash);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Versioned<DB>> {
+        self.inner.lock().expect("shared db is poisoned")
+    }
+}
+
+impl<DB: DatabaseRef + Clone> DatabaseRef for ReadThrough<'_, DB> {
+    type Error = DB::Error;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let cached = self
+            .shared
+            .inspect(|db| db.accounts.get(&address).map(|account| account.info()));
+        if let Some(info) = cached {
+            return Ok(info);
+        }
+
+        let info = self.remote.basic_ref(address)?;
+        self.fetched
+            .borrow_mut()
+            .accounts
+            .push((address, info.clone()));
+        Ok(info)
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if let Some(bytecode) = self
+            .shared
+            .inspect(|db| db.contracts.get(&code_hash).cloned())
+        {
+            return Ok(bytecode);
+        }
+
+        let bytecode = self.remote.code_by_hash_ref(code_hash)?;
+        self.fetched
+            .borrow_mut()
+            .contracts
+            .push((code_hash, bytecode.clone()));
+        Ok(bytecode)
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        // same as `CacheDB`, cleared storage isn't fetched
+        let cached = self.shared.inspect(|db| {
+            let account = db.accounts.get(&address)?;
+            match account.storage.get(&index) {
+                Some(&value) => Some(value),
+                None if matches!(
+                    account.account_state,
+                    AccountState::StorageCleared | AccountState::NotExisting
+                ) =>
+                {
+                    Some(U256::ZERO)
+                }
+                None => None,
+            }
+        });
+        if let Some(value) = cached {
+            return Ok(value);
+        }
+
+        let value = self.remote.storage_ref(address, index)?;
+        self.fetched
+            .borrow_mut()
+            .storage
+            .push((address, index, value));
+        Ok(value)
+    }
+
+    fn block_hash_ref(&self, number: U256) -> Result<B256, Self::Error> {
+        if let Some(hash) = self
+            .shared
+            .inspect(|db| db.block_hashes.get(&number).copied())
+        {
+            return Ok(hash);
+        }
+
+        let hash = self.remote.block_hash_ref(number)?;
+        self.fetched.borrow_mut().block_hashes.push((number, hash));
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use revm::{db::EmptyDB, Database};
+
+    fn remote() -> CacheDB<EmptyDB> {
+        let mut remote = CacheDB::new(EmptyDB::default());
+        remote.insert_account_info(Address::ZERO, AccountInfo::from_balance(U256::from(5)));
+        remote
+            .insert_account_storage(Address::ZERO, U256::from(1), U256::from(7))
+            .unwrap();
+        remote
+    }
+
+    #[test]
+    fn keeps_what_an_execution_fetched() {
+        let shared = SharedDb::new(CacheDB::new(remote()));
+
+        let value = shared.execute(|overlay| {
+            let value = overlay.storage(Address::ZERO, U256::from(1)).unwrap();
+            // writes only live in the overlay
+            overlay
+                .insert_account_storage(Address::ZERO, U256::from(1), U256::from(9))
+                .unwrap();
+            value
+        });
+        assert_eq!(value, U256::from(7));
+
+        shared.inspect(|db| {
+            let account = &db.accounts[&Address::ZERO];
+            assert_eq!(account.info.balance, U256::from(5));
+            assert_eq!(account.storage[&U256::from(1)], U256::from(7));
+        });
+    }
+
+    #[test]
+    fn drops_reads_of_a_replaced_state() {
+        let shared = SharedDb::new(CacheDB::new(remote()));
+
+        shared.execute(|overlay| {
+            overlay.storage(Address::ZERO, U256::from(1)).unwrap();
+            // the cache moves to the next block while the execution runs
+            shared.update(|_| ());
+        });
+
+        shared.inspect(|db| assert!(db.accounts.is_empty()));
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use ethers::{
+    abi::{decode, ParamType},
+    types::{Bytes, Transaction, H256, U256},
+    utils::{format_units, rlp::Rlp},
+};
+use ethers_flashbots::{BundleRequest, BundleTransaction, SimulatedBundle};
+use serde::Deserialize;
+use std::fmt::{self, Display};
+
+/// selector of `Error(string)`
+pub const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Where `GlobalProvider` simulates bundles before sending them
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleSimulatorKind {
+    /// `eth_callBundle` on the flashbots relay
+    #[default]
+    Relay,
+    /// revm on top of the state cache
+    Local,
+}
+
+/// Block a bundle is simulated in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulationBlock {
+    pub number: u64,
+    pub timestamp: u64,
+    pub base_fee: U256,
+}
+
+/// How a bundle executed in simulation
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleSimulation {
+    pub gas_used: U256,
+    /// what the builder earns per gas, tips and coinbase transfers included
+    pub effective_gas_price: U256,
+    pub txs: Vec<TxSimulation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxSimulation {
+    pub hash: H256,
+    pub gas_used: U256,
+    /// why the tx reverted or halted, `None` if it succeeded
+    pub error: Option<String>,
+}
+
+impl BundleSimulation {
+    pub fn reverted(&self) -> impl Iterator<Item = &TxSimulation> {
+        self.txs.iter().filter(|tx| tx.error.is_some())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.reverted().next().is_none()
+    }
+
+    /// Only txs of `reverting_tx_hashes` reverted, and not all of them
+    pub fn is_success_with(&self, reverting_tx_hashes: &[H256]) -> bool {
+        let allowed = |tx: &&TxSimulation| reverting_tx_hashes.contains(&tx.hash);
+        let reverted_allowed = self.reverted().filter(allowed).count();
+        let allowed_txs = self.txs.iter().filter(allowed).count();
+
+        self.reverted().all(|tx| allowed(&tx))
+            && (allowed_txs == 0 || reverted_allowed < allowed_txs)
+    }
+}
+
+impl From<SimulatedBundle> for BundleSimulation {
+    fn from(bundle: SimulatedBundle) -> BundleSimulation {
+        BundleSimulation {
+            gas_used: bundle.gas_used,
+            effective_gas_price: bundle.effective_gas_price(),
+            txs: bundle
+                .transactions
+                .into_iter()
+                .map(|tx| TxSimulation {
+                    hash: tx.hash,
+                    gas_used: tx.gas_used,
+                    error: tx.revert.or(tx.error),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Display for BundleSimulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gwei = format_units(self.effective_gas_price, "gwei").map_err(|_| fmt::Error)?;
+        write!(
+            f,
+            "gas used {}, effective gas price {gwei} gwei",
+            self.gas_used
+        )?;
+
+        for tx in &self.txs {
+            match &tx.error {
+                None => write!(f, "\n  {:?} ok, gas used {}", tx.hash, tx.gas_used)?,
+                Some(error) => write!(f, "\n  {:?} reverted: {error}", tx.hash)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Signed transactions of a bundle, with their sender recovered
+pub fn bundle_transactions(txs: &[BundleTransaction]) -> Result<Vec<Transaction>> {
+    txs.iter()
+        .map(|tx| match tx {
+            BundleTransaction::Signed(tx) => Ok(*tx.clone()),
+            BundleTransaction::Raw(raw) => {
+                let mut tx: Transaction = Rlp::new(raw).as_val()?;
+                tx.recover_from_mut()?;
+                Ok(tx)
+            }
+        })
+        .collect()
+}
+
+/// `revertingTxHashes` of `bundle`, the txs builders may include even if they revert
+pub fn reverting_tx_hashes(bundle: &BundleRequest) -> Vec<H256> {
+    serde_json::to_value(bundle)
+        .ok()
+        .and_then(|bundle| serde_json::from_value(bundle["revertingTxHashes"].clone()).ok())
+        .unwrap_or_default()
+}
+
+/// The message of an `Error(string)` revert, otherwise the raw output
+pub fn revert_reason(output: &Bytes) -> String {
+    if output.len() > 4 && output[..4] == ERROR_SELECTOR {
+        if let Some(reason) = decode(&[ParamType::String], &output[4..])
+            .ok()
+            .and_then(|tokens| tokens.into_iter().next())
+            .and_then(|token| token.into_string())
+        {
+            return reason;
+        }
+    }
+
+    output.to_string()
+}
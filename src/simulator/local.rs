@@ -0,0 +1,359 @@
+use super::{
+    bundle::{revert_reason, BundleSimulation, SimulationBlock, TxSimulation},
+    Simulator,
+};
+use crate::{constants::*, shared_db::SharedDb, SetAssetTwapPriceFilter};
+use anyhow::{anyhow, bail, Result};
+use ethers::{
+    abi::RawLog,
+    contract::EthLogDecode,
+    types::{Transaction, H160, H256, U256},
+};
+use revm::{
+    db::CacheDB,
+    primitives::{Address, Bytes, ExecutionResult, Log, TransactTo, U256 as rU256},
+    Database, DatabaseRef, Evm,
+};
+use std::{
+    fmt::Display,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Executes pending `NftOracle` txs with revm against a cached copy of mainnet state.
+///
+/// Every account and slot read during a simulation stays in the cache, so after the
+/// first run the oracle tx executes without touching the network. The db is usually
+/// shared with the `StateCache`, which keeps it in sync with new blocks.
+pub struct LocalSimulator<DB> {
+    db: Arc<SharedDb<DB>>,
+}
+
+impl<DB> LocalSimulator<DB>
+where
+    DB: DatabaseRef + Clone,
+    DB::Error: Display,
+{
+    pub fn new(db: CacheDB<DB>) -> Self {
+        Self::from_shared(Arc::new(SharedDb::new(db)))
+    }
+
+    pub fn from_shared(db: Arc<SharedDb<DB>>) -> Self {
+        Self { db }
+    }
+
+    /// Simulates a transaction without committing it and returns the updated twaps
+    pub fn simulate(&self, tx: &Transaction) -> Result<Vec<(H160, U256)>> {
+        let to = tx
+            .to
+            .ok_or_else(|| anyhow!("contract creation can't update twaps"))?;
+        let gas_price = tx.gas_price.or(tx.max_fee_per_gas).unwrap_or_default();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        let result = self
+            .db
+            .execute(|db| {
+                let mut evm = Evm::builder()
+                    .with_db(db)
+                    .modify_tx_env(|env_tx| {
+                        env_tx.caller = Address::from(tx.from.0);
+                        env_tx.transact_to = TransactTo::Call(Address::from(to.0));
+                        env_tx.data = Bytes::from(tx.input.to_vec());
+                        env_tx.value = rU256::from_limbs(tx.value.0);
+                        env_tx.gas_limit = tx.gas.as_u64();
+                        env_tx.gas_price = rU256::from_limbs(gas_price.0);
+                        // the tx is still pending so its nonce may be ahead of the cached state
+                        env_tx.nonce = None;
+                    })
+                    .modify_block_env(|block_env| {
+                        // the oracle checks update intervals against `block.timestamp`
+                        block_env.timestamp = rU256::from(timestamp);
+                    })
+                    .build();
+
+                evm.transact().map_err(|e| anyhow!("{e}"))
+            })?
+            .result;
+
+        match result {
+            ExecutionResult::Success { logs, .. } => Ok(decode_twaps(&logs)),
+            ExecutionResult::Revert { output, .. } => bail!("oracle tx reverted: {output}"),
+            ExecutionResult::Halt { reason, .. } => bail!("oracle tx halted: {reason:?}"),
+        }
+    }
+
+    /// Executes `txs` in order on top of the cached state, as if they were `block`.
+    /// Changes only live for the simulation, the cached state is left as is
+    pub fn simulate_bundle(
+        &self,
+        txs: &[Transaction],
+        block: SimulationBlock,
+    ) -> Result<BundleSimulation> {
+        self.db.execute(|overlay| simulate_on(overlay, txs, block))
+    }
+}
+
+/// Runs the bundle on `overlay` and reads back what it paid the builder
+fn simulate_on<DB>(
+    overlay: &mut CacheDB<DB>,
+    txs: &[Transaction],
+    block: SimulationBlock,
+) -> Result<BundleSimulation>
+where
+    DB: DatabaseRef,
+    DB::Error: Display,
+{
+    let mut simulation = BundleSimulation {
+        gas_used: U256::zero(),
+        effective_gas_price: U256::zero(),
+        txs: Vec::with_capacity(txs.len()),
+    };
+    let coinbase_before = coinbase_balance(overlay)?;
+
+    for tx in txs {
+        let mut evm = Evm::builder()
+            .with_db(&mut *overlay)
+            .modify_tx_env(|env_tx| {
+                env_tx.caller = Address::from(tx.from.0);
+                env_tx.transact_to = match tx.to {
+                    Some(to) => TransactTo::Call(Address::from(to.0)),
+                    None => TransactTo::create(),
+                };
+                env_tx.data = Bytes::from(tx.input.to_vec());
+                env_tx.value = rU256::from_limbs(tx.value.0);
+                env_tx.gas_limit = tx.gas.as_u64();
+                env_tx.gas_price =
+                    rU256::from_limbs(tx.max_fee_per_gas.or(tx.gas_price).unwrap_or_default().0);
+                env_tx.gas_priority_fee = tx
+                    .max_priority_fee_per_gas
+                    .map(|fee| rU256::from_limbs(fee.0));
+                // pending txs may be ahead of the cached nonces
+                env_tx.nonce = None;
+            })
+            .modify_block_env(|block_env| {
+                block_env.number = rU256::from(block.number);
+                block_env.timestamp = rU256::from(block.timestamp);
+                block_env.basefee = rU256::from_limbs(block.base_fee.0);
+                block_env.coinbase = SIMULATION_COINBASE;
+            })
+            .build();
+
+        let result = evm.transact_commit().map_err(|e| anyhow!("{e}"))?;
+        let gas_used = U256::from(result.gas_used());
+
+        let error = match result {
+            ExecutionResult::Success { .. } => None,
+            ExecutionResult::Revert { output, .. } => Some(revert_reason(&output.to_vec().into())),
+            ExecutionResult::Halt { reason, .. } => Some(format!("halted: {reason:?}")),
+        };
+
+        simulation.gas_used += gas_used;
+        simulation.txs.push(TxSimulation {
+            hash: tx.hash,
+            gas_used,
+            error,
+        });
+    }
+
+    let coinbase_diff = coinbase_balance(overlay)?.saturating_sub(coinbase_before);
+    if !simulation.gas_used.is_zero() {
+        simulation.effective_gas_price = coinbase_diff / simulation.gas_used;
+    }
+
+    Ok(simulation)
+}
+
+impl<DB> Simulator for LocalSimulator<DB>
+where
+    DB: DatabaseRef + Clone + Send + 'static,
+    DB::Error: Display,
+{
+    async fn simulate_twap_changes(&self, tx: &Transaction) -> Result<Vec<(H160, U256)>> {
+        self.simulate(tx)
+    }
+}
+
+/// stands in for the builder so what the bundle pays it can be read back
+const SIMULATION_COINBASE: Address = Address::new([0xc0; 20]);
+
+fn coinbase_balance<DB>(db: &mut CacheDB<DB>) -> Result<U256>
+where
+    DB: DatabaseRef,
+    DB::Error: Display,
+{
+    let balance = db
+        .basic(SIMULATION_COINBASE)
+        .map_err(|e| anyhow!("{e}"))?
+        .map(|account| account.balance)
+        .unwrap_or_default();
+
+    Ok(U256(balance.into_limbs()))
+}
+
+/// Picks the `SetAssetTwapPrice` events emitted by `NftOracle`
+fn decode_twaps(logs: &[Log]) -> Vec<(H160, U256)> {
+    logs.iter()
+        .filter(|log| log.address == Address::from(NFT_ORACLE))
+        .filter_map(|log| {
+            let raw_log = RawLog {
+                topics: log.topics().iter().map(|t| H256::from(t.0)).collect(),
+                data: log.data.data.to_vec(),
+            };
+            SetAssetTwapPriceFilter::decode_log(&raw_log).ok()
+        })
+        .map(|evt| (evt.asset, evt.price))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::bundle::ERROR_SELECTOR, *};
+    use ethers::{
+        abi::{encode, Token},
+        contract::EthEvent,
+        types::Bytes as eBytes,
+    };
+    use revm::{
+        db::EmptyDB,
+        primitives::{AccountInfo, Bytecode},
+    };
+
+    /// Minimal stand-in for `NftOracle` that emits
+    /// `SetAssetTwapPrice(calldata[4..36], calldata[36..68], block.timestamp)`
+    fn fake_oracle_bytecode() -> Vec<u8> {
+        let mut code = vec![
+            0x60, 0x24, 0x35, // PUSH1 0x24 CALLDATALOAD (price)
+            0x60, 0x00, 0x52, // PUSH1 0x00 MSTORE
+            0x42, // TIMESTAMP
+            0x60, 0x20, 0x52, // PUSH1 0x20 MSTORE
+            0x60, 0x04, 0x35, // PUSH1 0x04 CALLDATALOAD (asset)
+            0x7f, // PUSH32 (event signature)
+        ];
+        code.extend_from_slice(SetAssetTwapPriceFilter::signature().as_bytes());
+        code.extend_from_slice(&[
+            0x60, 0x40, // PUSH1 0x40 (size)
+            0x60, 0x00, // PUSH1 0x00 (offset)
+            0xa2, // LOG2
+            0x00, // STOP
+        ]);
+        code
+    }
+
+    fn oracle_tx(asset: H160, price: U256) -> Transaction {
+        let mut input = vec![0u8; 4];
+        input.extend_from_slice(H256::from(asset).as_bytes());
+        let mut price_word = [0u8; 32];
+        price.to_big_endian(&mut price_word);
+        input.extend_from_slice(&price_word);
+
+        Transaction {
+            from: NFT_ORACLE_CONTROLLER_EOA.into(),
+            to: Some(NFT_ORACLE.into()),
+            input: eBytes::from(input),
+            gas: 1_000_000.into(),
+            gas_price: Some(U256::zero()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn simulates_twap_changes_from_in_memory_state() -> Result<()> {
+        let mut db = CacheDB::new(EmptyDB::default());
+        let bytecode = Bytecode::new_raw(fake_oracle_bytecode().into());
+        db.insert_account_info(
+            Address::from(NFT_ORACLE),
+            AccountInfo::new(rU256::ZERO, 1, bytecode.hash_slow(), bytecode),
+        );
+
+        let simulator = LocalSimulator::new(db);
+
+        let price = U256::exp10(18) * 42;
+        let twaps = simulator.simulate(&oracle_tx(CRYPTOPUNKS.into(), price))?;
+
+        assert_eq!(twaps, vec![(H160::from(CRYPTOPUNKS), price)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ignores_txs_without_twap_events() -> Result<()> {
+        // with no code at `NFT_ORACLE` the call succeeds but emits nothing
+        let simulator = LocalSimulator::new(CacheDB::new(EmptyDB::default()));
+
+        let twaps = simulator.simulate(&oracle_tx(CRYPTOPUNKS.into(), U256::one()))?;
+
+        assert!(twaps.is_empty());
+
+        Ok(())
+    }
+
+    /// Reverts every call with `Error("58")`, like `LendPool` rejecting a bid
+    fn reverting_bytecode() -> Vec<u8> {
+        let mut data = ERROR_SELECTOR.to_vec();
+        data.extend(encode(&[Token::String("58".to_string())]));
+
+        let len = data.len() as u8;
+        let mut code = vec![
+            0x60, len, 0x60, 12, 0x60, 0x00, 0x39, // CODECOPY(0, 12, len)
+            0x60, len, 0x60, 0x00, 0xfd, // REVERT(0, len)
+        ];
+        code.extend(data);
+        code
+    }
+
+    #[test]
+    fn simulates_bundles_without_touching_the_cached_state() -> Result<()> {
+        let mut db = CacheDB::new(EmptyDB::default());
+        for (address, code) in [
+            (NFT_ORACLE, fake_oracle_bytecode()),
+            (LEND_POOL, reverting_bytecode()),
+        ] {
+            let bytecode = Bytecode::new_raw(code.into());
+            db.insert_account_info(
+                Address::from(address),
+                AccountInfo::new(rU256::ZERO, 1, bytecode.hash_slow(), bytecode),
+            );
+        }
+        db.insert_account_info(
+            Address::from(NFT_ORACLE_CONTROLLER_EOA),
+            AccountInfo::from_balance(rU256::from(10u64).pow(rU256::from(18))),
+        );
+
+        let simulator = LocalSimulator::new(db);
+        let gwei = U256::exp10(9);
+
+        let mut oracle_tx = oracle_tx(CRYPTOPUNKS.into(), U256::one());
+        oracle_tx.gas_price = Some(gwei * 2);
+        let auction_tx = Transaction {
+            to: Some(LEND_POOL.into()),
+            gas: 100_000.into(),
+            gas_price: Some(gwei * 2),
+            ..oracle_tx.clone()
+        };
+        let block = SimulationBlock {
+            number: 1,
+            timestamp: 1_704_447_839,
+            base_fee: gwei,
+        };
+
+        let simulation = simulator.simulate_bundle(&[oracle_tx.clone(), auction_tx], block)?;
+
+        assert!(!simulation.is_success());
+        assert_eq!(simulation.txs[0].error, None);
+        assert_eq!(simulation.txs[1].error.as_deref(), Some("58"));
+        // the builder keeps what's paid above the base fee
+        assert_eq!(simulation.effective_gas_price, gwei);
+
+        // the sender paid nothing outside the simulation
+        let simulation = simulator.simulate_bundle(&[oracle_tx], block)?;
+        assert!(simulation.is_success());
+        let sender = simulator.db.inspect(|db| {
+            db.accounts[&Address::from(NFT_ORACLE_CONTROLLER_EOA)]
+                .info
+                .balance
+        });
+        assert_eq!(sender, rU256::from(10u64).pow(rU256::from(18)));
+
+        Ok(())
+    }
+}
@@ -7,16 +7,27 @@ use ethers::{
 pub fn get_new_state_with_twaps_modded(twaps: Vec<(Address, U256)>) -> State {
     let mut state = State::default();
 
-    for (nft_address, twap) in twaps {
-        let storage_slot = get_storage_slot(nft_address);
-        state
-            .account(Address::from(NFT_ORACLE))
-            .store(storage_slot, u256_to_h256_be(twap));
+    for (account, slot, value) in get_twap_storage_overrides(&twaps) {
+        state.account(account).store(slot, value);
     }
 
     state
 }
 
+/// `(account, slot, value)` writes that set each twap in `NftOracle`'s `twapPriceMap`
+pub fn get_twap_storage_overrides(twaps: &[(Address, U256)]) -> Vec<(Address, H256, H256)> {
+    twaps
+        .iter()
+        .map(|&(nft_address, twap)| {
+            (
+                Address::from(NFT_ORACLE),
+                get_storage_slot(nft_address),
+                u256_to_h256_be(twap),
+            )
+        })
+        .collect()
+}
+
 fn get_storage_slot(nft_address: Address) -> H256 {
     let nft_address: H256 = nft_address.into();
 
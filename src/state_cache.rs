@@ -0,0 +1,587 @@
+use crate::{
+    benddao::{
+        loan::{Loan, NftAsset, ReserveAsset},
+        status::Status,
+    },
+    constants::*,
+    lend_pool::{
+        GetNftAuctionEndTimeCall, GetNftAuctionEndTimeReturn, GetNftDebtDataCall,
+        GetNftDebtDataReturn,
+    },
+    lend_pool_loan::{GetLoanCall, GetLoanReturn},
+    shared_db::SharedDb,
+    utils::auction_status,
+};
+use anyhow::{anyhow, bail, Result};
+use ethers::{
+    abi::{AbiDecode, AbiEncode},
+    providers::Middleware,
+    types::{
+        Address, BlockId, GethDebugBuiltInTracerConfig, GethDebugBuiltInTracerType,
+        GethDebugTracerConfig, GethDebugTracerType, GethDebugTracingOptions, PreStateConfig, H256,
+        U256, U64,
+    },
+};
+use futures::future::try_join_all;
+use log::{info, warn};
+use revm::{
+    db::{CacheDB, EthersDB},
+    primitives::{
+        AccountInfo, Address as rAddress, Bytecode, Bytes, ExecutionResult, TransactTo,
+        U256 as rU256,
+    },
+    Database, DatabaseRef, Evm,
+};
+use serde_json::Value;
+use std::{
+    fmt::Display,
+    sync::{Arc, RwLock},
+};
+use tokio::try_join;
+
+pub use ethers::types::DiffMode;
+
+/// gas limit for read-only calls against the cache
+const CALL_GAS_LIMIT: u64 = 30_000_000;
+
+/// replaying more blocks than this is slower than refetching the cached slots
+const MAX_BLOCKS_TO_REPLAY: u64 = 5;
+
+/// `(account, slot, value)` storage writes applied only for the duration of a call
+pub type StorageOverrides = [(Address, H256, H256)];
+
+pub type SharedCacheDB<M> = Arc<SharedDb<EthersDB<M>>>;
+
+/// Block the cached state corresponds to
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CachedBlock {
+    pub number: u64,
+    pub timestamp: u64,
+}
+
+/// Long-lived revm state for the BendDAO contracts.
+///
+/// Accounts and slots are fetched from the node the first time they're read and from then
+/// on kept current by replaying each block's `prestateTracer` diff, so reads like
+/// `getNftDebtData` run locally instead of going through the rpc.
+pub struct StateCache<M: Middleware> {
+    provider: Arc<M>,
+    db: SharedCacheDB<M>,
+    head: RwLock<CachedBlock>,
+}
+
+impl<M: Middleware + Clone + 'static> StateCache<M> {
+    /// Pins a new cache to the latest block with `accounts` already loaded
+    pub async fn try_new(provider: Arc<M>, accounts: &[Address]) -> Result<Self> {
+        let head = get_cached_block(&provider, None).await?;
+
+        let ethers_db = EthersDB::new(provider.clone(), Some(BlockId::from(head.number)))
+            .ok_or_else(|| anyhow!("could not create ethers db"))?;
+        let mut db = CacheDB::new(ethers_db);
+
+        let infos = try_join_all(
+            accounts
+                .iter()
+                .map(|&addr| get_account_info(&provider, addr, head.number)),
+        )
+        .await?;
+        for (&addr, info) in accounts.iter().zip(infos) {
+            db.insert_account_info(rAddress::from(addr.0), info);
+        }
+
+        info!(
+            "state cache warmed with {} accounts at block {}",
+            accounts.len(),
+            head.number
+        );
+
+        Ok(Self {
+            provider,
+            db: Arc::new(SharedDb::new(db)),
+            head: RwLock::new(head),
+        })
+    }
+
+    /// Handle to the underlying db so a `LocalSimulator` can execute on the same state
+    pub fn shared_db(&self) -> SharedCacheDB<M> {
+        self.db.clone()
+    }
+
+    pub fn head(&self) -> CachedBlock {
+        *self.head.read().expect("state cache head is poisoned")
+    }
+
+    /// Block env `number` is expected to execute in, `BLOCK_TIME` apart from the head.
+    /// The head itself if `number` isn't ahead of it
+    pub fn block_at(&self, number: u64) -> CachedBlock {
+        let head = self.head();
+        let blocks_ahead = number.saturating_sub(head.number);
+
+        CachedBlock {
+            number: head.number + blocks_ahead,
+            timestamp: head.timestamp + blocks_ahead * BLOCK_TIME,
+        }
+    }
+
+    /// Moves the cache forward to `block_number` by replaying each block's state diff.
+    /// If we're too far behind or the node can't trace, every cached slot is refetched instead.
+    pub async fn sync(&self, block_number: u64) -> Result<()> {
+        let head = self.head();
+
+        if block_number <= head.number {
+            return Ok(());
+        }
+
+        if block_number - head.number > MAX_BLOCKS_TO_REPLAY {
+            return self.rebuild(block_number).await;
+        }
+
+        for number in head.number + 1..=block_number {
+            if let Err(e) = self.apply_block(number).await {
+                warn!("could not replay state diff of block {number}: {e}");
+                return self.rebuild(block_number).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read-only call against the cached state with temporary storage `overrides`,
+    /// executed in the `block` env or the head's if it isn't set
+    pub fn call<C: AbiEncode, R: AbiDecode>(
+        &self,
+        to: Address,
+        call: C,
+        overrides: &StorageOverrides,
+        block: Option<CachedBlock>,
+    ) -> Result<R> {
+        let head = block.unwrap_or_else(|| self.head());
+
+        self.db.execute(|db| {
+            with_storage_overrides(db, overrides, |db| decode_call(db, head, to, call))
+        })
+    }
+
+    /// Local equivalent of `utils::get_loan_data` for a batch of loans, see `call`
+    pub fn get_loans(
+        &self,
+        loan_ids: &[U256],
+        overrides: &StorageOverrides,
+        block: Option<CachedBlock>,
+    ) -> Result<Vec<Loan>> {
+        let head = block.unwrap_or_else(|| self.head());
+
+        self.db.execute(|db| {
+            with_storage_overrides(db, overrides, |db| {
+                let mut loans = Vec::new();
+                for &loan_id in loan_ids {
+                    if let Some(loan) = read_loan(db, head, loan_id)? {
+                        loans.push(loan);
+                    }
+                }
+                Ok(loans)
+            })
+        })
+    }
+
+    async fn apply_block(&self, number: u64) -> Result<()> {
+        let (diffs, head) = try_join!(
+            self.get_state_diffs(number),
+            get_cached_block(&self.provider, Some(number))
+        )?;
+
+        self.db.update(|db| {
+            for diff in &diffs {
+                apply_state_diff(db, diff);
+            }
+            // slots we haven't seen yet must be read at the block we're now at
+            db.db.set_block_number(BlockId::from(number));
+            *self.head.write().expect("state cache head is poisoned") = head;
+        });
+
+        Ok(())
+    }
+
+    async fn get_state_diffs(&self, number: u64) -> Result<Vec<DiffMode>> {
+        let options = GethDebugTracingOptions {
+            tracer: Some(GethDebugTracerType::BuiltInTracer(
+                GethDebugBuiltInTracerType::PreStateTracer,
+            )),
+            tracer_config: Some(GethDebugTracerConfig::BuiltInTracer(
+                GethDebugBuiltInTracerConfig::PreStateTracer(PreStateConfig {
+                    diff_mode: Some(true),
+                }),
+            )),
+            ..Default::default()
+        };
+
+        let traces: Vec<Value> = self
+            .provider
+            .provider()
+            .request("debug_traceBlockByNumber", (U64::from(number), options))
+            .await?;
+
+        traces
+            .into_iter()
+            .map(|trace| {
+                // newer clients wrap every trace as `{ txHash, result }`
+                let trace = match trace.get("result") {
+                    Some(result) => result.clone(),
+                    None => trace,
+                };
+                Ok(serde_json::from_value(trace)?)
+            })
+            .collect()
+    }
+
+    /// Re-pins the cache to `block_number` and refetches every cached account and slot
+    async fn rebuild(&self, block_number: u64) -> Result<()> {
+        let (mut ethers_db, touched) = self.db.inspect(|db| {
+            let touched: Vec<(Address, Vec<rU256>)> = db
+                .accounts
+                .iter()
+                .map(|(addr, account)| {
+                    let slots = account.storage.keys().copied().collect();
+                    (Address::from(addr.0 .0), slots)
+                })
+                .collect();
+            (db.db.clone(), touched)
+        });
+
+        let head = get_cached_block(&self.provider, Some(block_number)).await?;
+
+        let accounts = try_join_all(touched.into_iter().map(|(addr, slots)| async move {
+            let block = Some(BlockId::from(head.number));
+            let info = get_account_info(&self.provider, addr, head.number).await?;
+            let values = try_join_all(slots.iter().map(|slot| {
+                self.provider
+                    .get_storage_at(addr, H256::from(slot.to_be_bytes()), block)
+            }))
+            .await?;
+            anyhow::Ok((addr, info, slots, values))
+        }))
+        .await?;
+
+        ethers_db.set_block_number(BlockId::from(head.number));
+        let mut fresh = CacheDB::new(ethers_db);
+        let accounts_len = accounts.len();
+
+        for (addr, info, slots, values) in accounts {
+            let addr = rAddress::from(addr.0);
+            fresh.insert_account_info(addr, info);
+            for (slot, value) in slots.into_iter().zip(values) {
+                fresh.insert_account_storage(addr, slot, rU256::from_be_bytes(value.0))?;
+            }
+        }
+
+        self.db.update(|db| {
+            *db = fresh;
+            *self.head.write().expect("state cache head is poisoned") = head;
+        });
+
+        info!(
+            "rebuilt state cache with {} accounts at block {}",
+            accounts_len, head.number
+        );
+
+        Ok(())
+    }
+}
+
+/// Writes one tx's `prestateTracer` diff into the accounts we already cache.
+/// Accounts we never read are skipped, they'll be loaded at the new block when needed.
+pub fn apply_state_diff<DB>(db: &mut CacheDB<DB>, diff: &DiffMode) {
+    for (addr, post) in &diff.post {
+        let Some(account) = db.accounts.get_mut(&rAddress::from(addr.0)) else {
+            continue;
+        };
+
+        if let Some(balance) = post.balance {
+            account.info.balance = rU256::from_limbs(balance.0);
+        }
+        if let Some(nonce) = post.nonce {
+            account.info.nonce = nonce.as_u64();
+        }
+        if let Some(code) = post
+            .code
+            .as_ref()
+            .and_then(|c| hex::decode(c.trim_start_matches("0x")).ok())
+        {
+            let bytecode = Bytecode::new_raw(code.into());
+            account.info.code_hash = bytecode.hash_slow();
+            db.contracts
+                .insert(account.info.code_hash, bytecode.clone());
+            account.info.code = Some(bytecode);
+        }
+
+        let post_storage = post.storage.as_ref();
+        for (slot, value) in post_storage.into_iter().flatten() {
+            account
+                .storage
+                .insert(rU256::from_be_bytes(slot.0), rU256::from_be_bytes(value.0));
+        }
+
+        // slots that were cleared only show up in `pre`
+        let pre_storage = diff.pre.get(addr).and_then(|pre| pre.storage.as_ref());
+        for slot in pre_storage.into_iter().flat_map(|storage| storage.keys()) {
+            if !post_storage.is_some_and(|storage| storage.contains_key(slot)) {
+                account
+                    .storage
+                    .insert(rU256::from_be_bytes(slot.0), rU256::ZERO);
+            }
+        }
+    }
+
+    // in `pre` but not in `post` means the account selfdestructed
+    for addr in diff.pre.keys().filter(|addr| !diff.post.contains_key(addr)) {
+        db.accounts.remove(&rAddress::from(addr.0));
+    }
+}
+
+/// Applies `overrides`, runs `f` and puts the original values back
+fn with_storage_overrides<DB, T>(
+    db: &mut CacheDB<DB>,
+    overrides: &StorageOverrides,
+    f: impl FnOnce(&mut CacheDB<DB>) -> Result<T>,
+) -> Result<T>
+where
+    DB: DatabaseRef,
+    DB::Error: Display,
+{
+    let mut originals = Vec::with_capacity(overrides.len());
+
+    for &(addr, slot, value) in overrides {
+        let addr = rAddress::from(addr.0);
+        let slot = rU256::from_be_bytes(slot.0);
+        let original = db.storage(addr, slot).map_err(|e| anyhow!("{e}"))?;
+        originals.push((addr, slot, original));
+        db.insert_account_storage(addr, slot, rU256::from_be_bytes(value.0))
+            .map_err(|e| anyhow!("{e}"))?;
+    }
+
+    let result = f(db);
+
+    for (addr, slot, original) in originals.into_iter().rev() {
+        db.insert_account_storage(addr, slot, original)
+            .map_err(|e| anyhow!("{e}"))?;
+    }
+
+    result
+}
+
+fn read_loan<DB>(db: &mut CacheDB<DB>, head: CachedBlock, loan_id: U256) -> Result<Option<Loan>>
+where
+    DB: DatabaseRef,
+    DB::Error: Display,
+{
+    let GetLoanReturn { loan_data } =
+        decode_call(db, head, LEND_POOL_LOAN.into(), GetLoanCall { loan_id })?;
+
+    let (Ok(reserve_asset), Ok(nft_asset)) = (
+        ReserveAsset::try_from(loan_data.reserve_asset),
+        NftAsset::try_from(loan_data.nft_asset),
+    ) else {
+        return Ok(None);
+    };
+
+    let status = match loan_data.state {
+        0 => return Ok(None),
+        1 => Status::Created,
+        2 => Status::Active,
+        3 => {
+            let end_time: GetNftAuctionEndTimeReturn = decode_call(
+                db,
+                head,
+                LEND_POOL.into(),
+                GetNftAuctionEndTimeCall {
+                    nft_asset: loan_data.nft_asset,
+                    nft_token_id: loan_data.nft_token_id,
+                },
+            )?;
+            auction_status(
+                &loan_data,
+                reserve_asset,
+                nft_asset,
+                head.timestamp.into(),
+                end_time.bid_end_timestamp,
+            )
+        }
+        4 | 5 => Status::RepaidDefaulted,
+        state => bail!("invalid loan state {state}"),
+    };
+
+    let debt_data: GetNftDebtDataReturn = decode_call(
+        db,
+        head,
+        LEND_POOL.into(),
+        GetNftDebtDataCall {
+            nft_asset: loan_data.nft_asset,
+            nft_token_id: loan_data.nft_token_id,
+        },
+    )?;
+
+    Ok(Some(Loan {
+        health_factor: debt_data.health_factor,
+        status,
+        total_debt: debt_data.total_debt,
+        reserve_asset,
+        nft_asset,
+        loan_id: loan_data.loan_id,
+        nft_token_id: loan_data.nft_token_id,
+    }))
+}
+
+fn decode_call<DB, C, R>(db: &mut CacheDB<DB>, head: CachedBlock, to: Address, call: C) -> Result<R>
+where
+    DB: DatabaseRef,
+    DB::Error: Display,
+    C: AbiEncode,
+    R: AbiDecode,
+{
+    let mut evm = Evm::builder()
+        .with_db(db)
+        .modify_tx_env(|tx| {
+            tx.caller = rAddress::ZERO;
+            tx.transact_to = TransactTo::Call(rAddress::from(to.0));
+            tx.data = Bytes::from(call.encode());
+            tx.gas_limit = CALL_GAS_LIMIT;
+            tx.gas_price = rU256::ZERO;
+            tx.nonce = None;
+        })
+        .modify_block_env(|block_env| {
+            block_env.number = rU256::from(head.number);
+            block_env.timestamp = rU256::from(head.timestamp);
+        })
+        .build();
+
+    let output = match evm.transact().map_err(|e| anyhow!("{e}"))?.result {
+        ExecutionResult::Success { output, .. } => output.into_data(),
+        ExecutionResult::Revert { output, .. } => bail!("call reverted: {output}"),
+        ExecutionResult::Halt { reason, .. } => bail!("call halted: {reason:?}"),
+    };
+
+    Ok(R::decode(output)?)
+}
+
+async fn get_cached_block<M: Middleware + 'static>(
+    provider: &M,
+    number: Option<u64>,
+) -> Result<CachedBlock> {
+    let block_id = match number {
+        Some(number) => BlockId::from(number),
+        None => BlockId::from(ethers::types::BlockNumber::Latest),
+    };
+
+    let block = provider
+        .get_block(block_id)
+        .await?
+        .ok_or_else(|| anyhow!("block {block_id:?} not found"))?;
+
+    Ok(CachedBlock {
+        number: block.number.unwrap_or_default().as_u64(),
+        timestamp: block.timestamp.as_u64(),
+    })
+}
+
+async fn get_account_info<M: Middleware + 'static>(
+    provider: &M,
+    addr: Address,
+    block_number: u64,
+) -> Result<AccountInfo> {
+    let block = Some(BlockId::from(block_number));
+
+    let (balance, nonce, code) = try_join!(
+        provider.get_balance(addr, block),
+        provider.get_transaction_count(addr, block),
+        provider.get_code(addr, block)
+    )?;
+
+    let bytecode = Bytecode::new_raw(code.0.into());
+
+    Ok(AccountInfo::new(
+        rU256::from_limbs(balance.0),
+        nonce.as_u64(),
+        bytecode.hash_slow(),
+        bytecode,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::types::AccountState;
+    use revm::db::EmptyDB;
+    use std::collections::BTreeMap;
+
+    fn slot(n: u64) -> H256 {
+        H256::from_low_u64_be(n)
+    }
+
+    #[test]
+    fn applies_diff_only_to_cached_accounts() -> Result<()> {
+        let lend_pool = Address::from(LEND_POOL);
+        let not_cached = Address::from(WETH);
+
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(rAddress::from(lend_pool.0), AccountInfo::default());
+        for n in 1..=3 {
+            db.insert_account_storage(rAddress::from(lend_pool.0), rU256::from(n), rU256::from(7))?;
+        }
+
+        let diff = DiffMode {
+            pre: BTreeMap::from([(
+                lend_pool,
+                AccountState {
+                    storage: Some(BTreeMap::from([(slot(1), slot(7)), (slot(2), slot(7))])),
+                    ..Default::default()
+                },
+            )]),
+            post: BTreeMap::from([
+                (
+                    lend_pool,
+                    AccountState {
+                        balance: Some(U256::from(5)),
+                        // slot 2 was set to zero so it's missing here
+                        storage: Some(BTreeMap::from([(slot(1), slot(9))])),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    not_cached,
+                    AccountState {
+                        storage: Some(BTreeMap::from([(slot(1), slot(1))])),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+        };
+
+        apply_state_diff(&mut db, &diff);
+
+        let account = &db.accounts[&rAddress::from(lend_pool.0)];
+        assert_eq!(account.info.balance, rU256::from(5));
+        assert_eq!(account.storage[&rU256::from(1)], rU256::from(9));
+        assert_eq!(account.storage[&rU256::from(2)], rU256::ZERO);
+        assert_eq!(account.storage[&rU256::from(3)], rU256::from(7));
+        assert!(!db.accounts.contains_key(&rAddress::from(not_cached.0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn removes_selfdestructed_accounts() {
+        let lend_pool = Address::from(LEND_POOL);
+
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(rAddress::from(lend_pool.0), AccountInfo::default());
+
+        let diff = DiffMode {
+            pre: BTreeMap::from([(lend_pool, AccountState::default())]),
+            post: BTreeMap::new(),
+        };
+
+        apply_state_diff(&mut db, &diff);
+
+        assert!(!db.accounts.contains_key(&rAddress::from(lend_pool.0)));
+    }
+}
@@ -0,0 +1,418 @@
+use crate::{
+    benddao::loan::{NftAsset, ReserveAsset},
+    builders::BuilderStats,
+    bundle_tracker::BundleOutcome,
+    types::{Auction, AuctionBid},
+};
+use anyhow::{bail, Result};
+use ethers::types::{Address, H256, U256, U64};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+pub const STORE_PATH: &str = "data/store.jsonl";
+
+/// what the store replaced, imported the first time the store is created
+pub const LEGACY_REPAID_DEFAULTED_PATH: &str = "data/repaid-defaulted.json";
+
+/// the log is rewritten as a snapshot once it grows past this many records
+const COMPACT_AFTER_RECORDS: usize = 10_000;
+
+/// bids, bundles, liquidations and fines older than this are moved to the archive when compacting
+const RETENTION_SECS: u64 = 90 * 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BidRecord {
+    pub nft_asset: Address,
+    pub nft_token_id: U256,
+    pub bid_price: U256,
+    pub timestamp: u64,
+}
+
+impl BidRecord {
+    pub fn new(bid: &AuctionBid) -> Self {
+        Self {
+            nft_asset: bid.nft_asset,
+            nft_token_id: bid.nft_token_id,
+            bid_price: bid.bid_price,
+            timestamp: chrono::Utc::now().timestamp() as u64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BundleRecord {
+    pub block: Option<U64>,
+    pub transactions: Vec<H256>,
+    pub bundle_hash: Option<H256>,
+    pub included: bool,
+    /// `None` for bundles recorded before outcomes were tracked
+    #[serde(default)]
+    pub outcome: Option<BundleOutcome>,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LiquidationRecord {
+    pub nft_asset: NftAsset,
+    pub nft_token_id: U256,
+    pub transaction_hash: H256,
+    pub timestamp: u64,
+}
+
+/// Bid fine paid to us as first bidder when a borrower redeemed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FineRecord {
+    pub nft_asset: NftAsset,
+    pub nft_token_id: U256,
+    pub reserve_asset: ReserveAsset,
+    pub fine: U256,
+    pub timestamp: u64,
+}
+
+/// One line of the store, tagged with the table it belongs to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "table", rename_all = "snake_case")]
+pub enum Record {
+    /// loan that was repaid or defaulted and never needs to be queried again
+    ClosedLoan {
+        loan_id: u64,
+    },
+    /// replaces the previous monitored set
+    MonitoredLoans {
+        loan_ids: Vec<U256>,
+    },
+    /// auction started or outbid
+    Auction(Auction),
+    /// auction redeemed or liquidated
+    AuctionRemoved {
+        nft_asset: NftAsset,
+        nft_token_id: U256,
+    },
+    Bid(BidRecord),
+    Bundle(BundleRecord),
+    Liquidation(LiquidationRecord),
+    Fine(FineRecord),
+    /// replaces the previous stats of the builder
+    BuilderStats {
+        builder: String,
+        stats: BuilderStats,
+    },
+}
+
+/// Current contents of every table, as rebuilt from the log
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoreState {
+    pub closed_loans: BTreeSet<u64>,
+    pub monitored_loans: Vec<U256>,
+    pub auctions: Vec<Auction>,
+    pub bids: Vec<BidRecord>,
+    pub bundles: Vec<BundleRecord>,
+    pub liquidations: Vec<LiquidationRecord>,
+    pub fines: Vec<FineRecord>,
+    /// by builder name
+    pub builder_stats: BTreeMap<String, BuilderStats>,
+}
+
+impl StoreState {
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::ClosedLoan { loan_id } => {
+                self.closed_loans.insert(loan_id);
+            }
+            Record::MonitoredLoans { loan_ids } => self.monitored_loans = loan_ids,
+            Record::Auction(auction) => {
+                self.remove_auction(auction.nft_asset, auction.nft_token_id);
+                self.auctions.push(auction);
+            }
+            Record::AuctionRemoved {
+                nft_asset,
+                nft_token_id,
+            } => self.remove_auction(nft_asset, nft_token_id),
+            Record::Bid(bid) => self.bids.push(bid),
+            Record::Bundle(bundle) => self.bundles.push(bundle),
+            Record::Liquidation(liquidation) => self.liquidations.push(liquidation),
+            Record::Fine(fine) => self.fines.push(fine),
+            Record::BuilderStats { builder, stats } => {
+                self.builder_stats.insert(builder, stats);
+            }
+        }
+    }
+
+    fn remove_auction(&mut self, nft_asset: NftAsset, nft_token_id: U256) {
+        self.auctions
+            .retain(|a| a.nft_asset != nft_asset || a.nft_token_id != nft_token_id);
+    }
+
+    /// Removes the bids, bundles, liquidations and fines from before `cutoff`,
+    /// nothing else depends on them once they're settled
+    fn take_expired(&mut self, cutoff: u64) -> Vec<Record> {
+        let mut expired = vec![];
+
+        let (old, kept) = std::mem::take(&mut self.bids)
+            .into_iter()
+            .partition(|bid| bid.timestamp < cutoff);
+        self.bids = kept;
+        expired.extend(old.into_iter().map(Record::Bid));
+
+        let (old, kept) = std::mem::take(&mut self.bundles)
+            .into_iter()
+            .partition(|bundle| bundle.timestamp < cutoff);
+        self.bundles = kept;
+        expired.extend(old.into_iter().map(Record::Bundle));
+
+        let (old, kept) = std::mem::take(&mut self.liquidations)
+            .into_iter()
+            .partition(|liquidation| liquidation.timestamp < cutoff);
+        self.liquidations = kept;
+        expired.extend(old.into_iter().map(Record::Liquidation));
+
+        let (old, kept) = std::mem::take(&mut self.fines)
+            .into_iter()
+            .partition(|fine| fine.timestamp < cutoff);
+        self.fines = kept;
+        expired.extend(old.into_iter().map(Record::Fine));
+
+        expired
+    }
+
+    /// the fewest records that rebuild this state
+    fn snapshot(&self) -> Vec<Record> {
+        let closed_loans = self
+            .closed_loans
+            .iter()
+            .map(|&loan_id| Record::ClosedLoan { loan_id });
+        let monitored_loans = Record::MonitoredLoans {
+            loan_ids: self.monitored_loans.clone(),
+        };
+
+        closed_loans
+            .chain([monitored_loans])
+            .chain(self.auctions.iter().copied().map(Record::Auction))
+            .chain(self.bids.iter().cloned().map(Record::Bid))
+            .chain(self.bundles.iter().cloned().map(Record::Bundle))
+            .chain(self.liquidations.iter().cloned().map(Record::Liquidation))
+            .chain(self.fines.iter().cloned().map(Record::Fine))
+            .chain(
+                self.builder_stats
+                    .iter()
+                    .map(|(builder, &stats)| Record::BuilderStats {
+                        builder: builder.clone(),
+                        stats,
+                    }),
+            )
+            .collect()
+    }
+}
+
+struct StoreInner {
+    path: PathBuf,
+    file: File,
+    state: StoreState,
+    records: usize,
+    /// records in the log right after it was last compacted
+    compacted_records: usize,
+}
+
+/// Crash-safe, append-only store for everything the bot must remember across restarts.
+///
+/// Every record is one JSON line that is flushed to disk before `append` returns. A line
+/// torn by a crash can only be the last one and is dropped when the store is opened.
+#[derive(Clone)]
+pub struct Store {
+    inner: Arc<Mutex<StoreInner>>,
+}
+
+impl StoreInner {
+    async fn compact(&mut self) -> Result<()> {
+        let cutoff = (chrono::Utc::now().timestamp() as u64).saturating_sub(RETENTION_SECS);
+        let expired = self.state.take_expired(cutoff);
+
+        // archived first, a crash before the rename only archives them twice
+        if !expired.is_empty() {
+            let mut lines = String::new();
+            for record in &expired {
+                lines.push_str(&serde_json::to_string(record)?);
+                lines.push('\n');
+            }
+
+            let mut archive = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(archive_path(&self.path))
+                .await?;
+            archive.write_all(lines.as_bytes()).await?;
+            archive.sync_data().await?;
+        }
+
+        let snapshot = self.state.snapshot();
+        let mut contents = String::new();
+        for record in &snapshot {
+            contents.push_str(&serde_json::to_string(record)?);
+            contents.push('\n');
+        }
+
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        let mut tmp = File::create(&tmp_path).await?;
+        tmp.write_all(contents.as_bytes()).await?;
+        tmp.sync_all().await?;
+        fs::rename(&tmp_path, &self.path).await?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path).await?;
+
+        info!(
+            "compacted store from {} to {} records, archived {}",
+            self.records,
+            snapshot.len(),
+            expired.len()
+        );
+        self.records = snapshot.len();
+        self.compacted_records = snapshot.len();
+
+        Ok(())
+    }
+}
+
+/// Where compaction moves the records that left the retention window
+pub fn archive_path(path: impl AsRef<Path>) -> PathBuf {
+    path.as_ref().with_extension("archive.jsonl")
+}
+
+impl Store {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Store> {
+        let path = path.as_ref().to_path_buf();
+        let mut state = StoreState::default();
+        let mut records = 0;
+
+        match fs::read_to_string(&path).await {
+            Ok(contents) => {
+                let mut valid_len = 0;
+                let mut lines = contents.split_inclusive('\n').peekable();
+
+                while let Some(line) = lines.next() {
+                    match serde_json::from_str::<Record>(line) {
+                        Ok(record) if line.ends_with('\n') => {
+                            state.apply(record);
+                            records += 1;
+                            valid_len += line.len();
+                        }
+                        _ if lines.peek().is_none() => {
+                            warn!("dropping torn record at the end of {}", path.display());
+                        }
+                        Err(e) => bail!("corrupt record in {}: {e}", path.display()),
+                        Ok(_) => unreachable!("only the last line can lack a newline"),
+                    }
+                }
+
+                if valid_len < contents.len() {
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&path)
+                        .await?
+                        .set_len(valid_len as u64)
+                        .await?;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        let store = Store {
+            inner: Arc::new(Mutex::new(StoreInner {
+                path,
+                file,
+                state,
+                records,
+                compacted_records: 0,
+            })),
+        };
+
+        if records > COMPACT_AFTER_RECORDS {
+            store.compact().await?;
+        }
+
+        Ok(store)
+    }
+
+    /// Writes `record` to disk and applies it
+    pub async fn append(&self, record: Record) -> Result<()> {
+        self.append_all(vec![record]).await
+    }
+
+    /// Like `append` with a single flush for all `records`
+    pub async fn append_all(&self, records: Vec<Record>) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let mut inner = self.inner.lock().await;
+
+        let mut lines = String::new();
+        for record in &records {
+            lines.push_str(&serde_json::to_string(record)?);
+            lines.push('\n');
+        }
+
+        inner.file.write_all(lines.as_bytes()).await?;
+        inner.file.sync_data().await?;
+
+        inner.records += records.len();
+        for record in records {
+            inner.state.apply(record);
+        }
+
+        if inner.records > inner.compacted_records + COMPACT_AFTER_RECORDS {
+            inner.compact().await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn state(&self) -> StoreState {
+        self.inner.lock().await.state.clone()
+    }
+
+    /// Rewrites the log as a snapshot of the current state, moving the records older than
+    /// the retention window to the archive. The new file is synced before it replaces the old one.
+    pub async fn compact(&self) -> Result<()> {
+        self.inner.lock().await.compact().await
+    }
+
+    /// Imports the loan ids of the json array the store replaced.
+    /// Does nothing if the file doesn't exist.
+    pub async fn import_repaid_defaulted(&self, path: impl AsRef<Path>) -> Result<()> {
+        let Ok(json_string) = fs::read_to_string(path.as_ref()).await else {
+            return Ok(());
+        };
+
+        let loan_ids: Vec<u64> = serde_json::from_str(&json_string)?;
+
+        info!(
+            "importing {} repaid/defaulted loans from {}",
+            loan_ids.len(),
+            path.as_ref().display()
+        );
+
+        let records = loan_ids
+            .into_iter()
+            .map(|loan_id| Record::ClosedLoan { loan_id })
+            .collect();
+
+        self.append_all(records).await
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{
+    benddao::loan::ReserveAsset,
+    constants::*,
+    types::{AuctionBid, Balances},
+    Config,
+};
+use anyhow::{bail, Result};
+use ethers::types::U256;
+use log::warn;
+
+/// Txs that have to land before bids can, prepended to their bundle
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Funding {
+    /// ETH wrapped into WETH
+    pub wrap: U256,
+    pub approve_weth: bool,
+    pub approve_usdt: bool,
+}
+
+impl Funding {
+    pub fn is_empty(&self) -> bool {
+        self.wrap.is_zero() && !self.approve_weth && !self.approve_usdt
+    }
+}
+
+/// Pays for bids out of the wallet, wrapping its ETH and approving the lend pool
+/// when it has to instead of passing on them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Treasury {
+    /// WETH topped up to whenever ETH is wrapped
+    pub weth_target: U256,
+    /// USDT can't be wrapped, falling under it is only warned about
+    pub usdt_target: U256,
+    /// ETH never wrapped, left for gas
+    pub gas_reserve: U256,
+}
+
+impl Treasury {
+    pub fn from_config(config: &Config) -> Treasury {
+        Treasury {
+            weth_target: U256::from(config.weth_target) * U256::exp10(9),
+            usdt_target: U256::from(config.usdt_target) * U256::exp10(6),
+            gas_reserve: U256::from(config.gas_reserve) * U256::exp10(9),
+        }
+    }
+
+    /// Takes a bid of `amount` out of `balances`, adding what it needs wrapped or approved
+    /// to `funding`. Both are left untouched if the wallet can't pay for it.
+    pub fn fund(
+        &self,
+        balances: &mut Balances,
+        funding: &mut Funding,
+        reserve_asset: ReserveAsset,
+        amount: U256,
+    ) -> Result<()> {
+        let bid_gas = U256::from(BID_GAS_ETH);
+        if balances.eth < bid_gas {
+            bail!("{} ETH doesn't pay for the gas of a bid", balances.eth);
+        }
+
+        match reserve_asset {
+            ReserveAsset::Weth => {
+                if balances.weth < amount {
+                    let spare = (balances.eth - bid_gas).saturating_sub(self.gas_reserve);
+                    let shortfall = amount - balances.weth;
+                    if spare < shortfall {
+                        bail!(
+                            "{} WETH and {} ETH to wrap don't pay for a bid of {}",
+                            balances.weth,
+                            spare,
+                            amount
+                        );
+                    }
+
+                    let wrap = shortfall
+                        .max(self.weth_target.saturating_sub(balances.weth))
+                        .min(spare);
+                    balances.eth -= wrap;
+                    balances.weth += wrap;
+                    funding.wrap += wrap;
+                }
+
+                if !balances.is_weth_lend_pool_approved {
+                    balances.is_weth_lend_pool_approved = true;
+                    funding.approve_weth = true;
+                }
+                balances.weth -= amount;
+            }
+            ReserveAsset::Usdt => {
+                if balances.usdt < amount {
+                    bail!("{} USDT doesn't pay for a bid of {}", balances.usdt, amount);
+                }
+
+                if !balances.is_usdt_lend_pool_approved {
+                    balances.is_usdt_lend_pool_approved = true;
+                    funding.approve_usdt = true;
+                }
+                balances.usdt -= amount;
+            }
+        }
+
+        balances.eth -= bid_gas;
+
+        Ok(())
+    }
+
+    /// The bids of `bids` that `balances` pay for, in order, and the funding they need
+    pub fn fund_bids(
+        &self,
+        mut balances: Balances,
+        bids: Vec<AuctionBid>,
+    ) -> (Vec<AuctionBid>, Funding) {
+        let mut funding = Funding::default();
+        let mut funded = vec![];
+
+        for bid in bids {
+            match self.fund(
+                &mut balances,
+                &mut funding,
+                bid.reserve_asset,
+                bid.bid_price,
+            ) {
+                Ok(()) => funded.push(bid),
+                Err(e) => warn!(
+                    "not bidding on {:?} #{}: {e}",
+                    bid.nft_asset, bid.nft_token_id
+                ),
+            }
+        }
+
+        (funded, funding)
+    }
+
+    /// What `balances` are short of, one line each
+    pub fn shortfalls(&self, balances: &Balances) -> Vec<String> {
+        let mut shortfalls = vec![];
+
+        if balances.eth < self.gas_reserve {
+            shortfalls.push(format!(
+                "ETH {} is below the gas reserve of {}",
+                balances.eth, self.gas_reserve
+            ));
+        }
+        if balances.weth + balances.eth.saturating_sub(self.gas_reserve) < self.weth_target {
+            shortfalls.push(format!(
+                "WETH {} can't be wrapped up to its target of {}",
+                balances.weth, self.weth_target
+            ));
+        }
+        if balances.usdt < self.usdt_target {
+            shortfalls.push(format!(
+                "USDT {} is below its target of {}",
+                balances.usdt, self.usdt_target
+            ));
+        }
+
+        shortfalls
+    }
+}
@@ -1,12 +1,16 @@
 use crate::benddao::loan::{NftAsset, ReserveAsset};
 use ethers::types::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub struct Auction {
     pub nft_asset: NftAsset,
     pub nft_token_id: U256,
     pub current_bid: U256,
     pub current_bidder: Address,
+    /// paid the bid fine if the borrower redeems
+    #[serde(default)]
+    pub first_bidder: Address,
     pub bid_end_timestamp: U256,     // unix timestamp in seconds
     pub reserve_asset: ReserveAsset, // for profit calculation
 }
@@ -1,19 +1,26 @@
 use ethers::types::*;
 
 use super::Auction;
+use crate::benddao::loan::ReserveAsset;
 
+#[derive(Debug, Clone)]
 pub struct AuctionBid {
     pub nft_asset: H160,
     pub nft_token_id: U256,
     pub bid_price: U256,
+    pub reserve_asset: ReserveAsset,
+    /// in ETH (1e18), what the gas paid is weighed against
+    pub expected_profit: U256,
 }
 
 impl AuctionBid {
-    pub fn new(auction: &Auction, bid_price: U256) -> Self {
+    pub fn new(auction: &Auction, bid_price: U256, expected_profit: U256) -> Self {
         Self {
             nft_asset: auction.nft_asset.into(),
             nft_token_id: auction.nft_token_id,
             bid_price,
+            reserve_asset: auction.reserve_asset,
+            expected_profit,
         }
     }
 }
@@ -1,6 +1,6 @@
 use ethers::types::U256;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Balances {
     pub eth: U256,
     pub weth: U256,
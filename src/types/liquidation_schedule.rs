@@ -0,0 +1,45 @@
+use ethers::types::U256;
+use std::collections::BTreeSet;
+
+/// Loans expected to become auctionable from interest alone,
+/// ordered by the timestamp at which that happens
+#[derive(Default)]
+pub struct LiquidationSchedule {
+    queue: BTreeSet<(U256, U256)>, // (timestamp, loan_id)
+}
+
+impl LiquidationSchedule {
+    pub fn schedule(&mut self, timestamp: U256, loan_id: U256) {
+        self.queue.insert((timestamp, loan_id));
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// timestamp of the next loan to cross
+    pub fn peek(&self) -> Option<&(U256, U256)> {
+        self.queue.first()
+    }
+
+    /// removes and returns every loan id due at or before `timestamp`
+    pub fn pop_due(&mut self, timestamp: U256) -> Vec<U256> {
+        let mut due = vec![];
+        while let Some(&(due_timestamp, loan_id)) = self.queue.first() {
+            if due_timestamp > timestamp {
+                break;
+            }
+            self.queue.pop_first();
+            due.push(loan_id);
+        }
+        due
+    }
+}
@@ -6,11 +6,25 @@ use crate::{
 use ethers::types::*;
 use log::info;
 
+/// What a `LendPool` log changed in `PendingAuctions`, kept until the log can't be reorged out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuctionChange {
+    pub block_number: U64,
+    pub block_hash: H256,
+    pub log_index: U256,
+    pub nft_asset: NftAsset,
+    pub nft_token_id: U256,
+    /// the auction before the log was applied, `None` if there was none
+    pub previous: Option<Auction>,
+}
+
 // ideally we dont do this
 // imo makes the code ugly
 #[derive(Default)]
 pub struct PendingAuctions {
     pub pending_auctions: Vec<Auction>,
+    /// changes of the unconfirmed blocks in the order they were applied
+    journal: Vec<AuctionChange>,
 }
 
 impl PendingAuctions {
@@ -65,6 +79,73 @@ impl PendingAuctions {
         }
     }
 
+    pub fn get(&self, nft_asset: NftAsset, nft_token_id: U256) -> Option<&Auction> {
+        self.pending_auctions
+            .iter()
+            .find(|a| a.nft_asset == nft_asset && a.nft_token_id == nft_token_id)
+    }
+
+    /// Remembers `change` so it can be reverted if its block is reorged out
+    pub fn journal(&mut self, change: AuctionChange) {
+        self.journal.push(change);
+    }
+
+    pub fn journal_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// First block that has to be reverted for a log at `block_number` in `block_hash`
+    /// to be applied, `None` if the journal is consistent with it.
+    pub fn reorged_block(&self, block_number: U64, block_hash: H256) -> Option<U64> {
+        self.journal
+            .iter()
+            .filter(|c| {
+                c.block_number > block_number
+                    || (c.block_number == block_number && c.block_hash != block_hash)
+            })
+            .map(|c| c.block_number)
+            .min()
+    }
+
+    /// Block of a journaled change made in `block_hash`
+    pub fn journaled_block(&self, block_hash: H256) -> Option<U64> {
+        self.journal
+            .iter()
+            .find(|c| c.block_hash == block_hash)
+            .map(|c| c.block_number)
+    }
+
+    /// Undoes every change from `block_number` on, the latest first.
+    /// Returns the reverted changes in the order they were undone.
+    pub fn revert_from(&mut self, block_number: U64) -> Vec<AuctionChange> {
+        let mut reverted = vec![];
+
+        while self
+            .journal
+            .last()
+            .is_some_and(|c| c.block_number >= block_number)
+        {
+            let change = self.journal.pop().unwrap();
+
+            match change.previous {
+                Some(auction) => {
+                    self.add_update_auction(auction);
+                }
+                None => self.remove_auction(change.nft_asset, change.nft_token_id),
+            }
+
+            reverted.push(change);
+        }
+
+        reverted
+    }
+
+    /// Forgets the changes that are `confirmation_depth` blocks or more below `head`
+    pub fn prune_journal(&mut self, head: U64, confirmation_depth: u64) {
+        self.journal
+            .retain(|c| c.block_number + confirmation_depth > head);
+    }
+
     pub fn pop_auctions_due(&mut self, current_timestamp: U256) -> (Vec<Auction>, Vec<Auction>) {
         let mut auctions_due = vec![];
         while let Some(auction) = self.peek() {
@@ -4,75 +4,36 @@ use crate::{
         loan::{Loan, NftAsset, ReserveAsset},
         status::Status,
     },
+    health_factor::LoanPosition,
     LendPool, LendPoolLoan, LoanData,
 };
 use anyhow::Result;
 use ethers::providers::Middleware;
-use ethers::signers::Signer;
 use ethers::types::BlockNumber;
 use ethers::{
     providers::{JsonRpcClient, Provider, RawCall},
-    types::{spoof::State, U256},
+    types::{spoof::State, Address, U256},
 };
-use ethers_flashbots::PendingBundleError;
-use log::{debug, error, info};
-use std::collections::BTreeSet;
+use log::debug;
 use std::sync::Arc;
-use tokio::{
-    fs::File,
-    io::{AsyncReadExt, AsyncWriteExt},
-};
-
-pub async fn get_repaid_defaulted_loans() -> Result<BTreeSet<u64>> {
-    // if the file does not exist it will return Err
-    let mut file = File::open("data/repaid-defaulted.json").await?;
-    let mut json_string = String::new();
-
-    file.read_to_string(&mut json_string).await?;
-
-    let data: Vec<u64> = serde_json::from_str(&json_string)?;
-
-    Ok(BTreeSet::from_iter(data))
-}
-
-pub async fn save_repaid_defaulted_loans(loans: &BTreeSet<u64>) -> Result<()> {
-    // will create the file or delete it's contents if it exists already
-    let mut file = File::create("data/repaid-defaulted.json").await?;
-
-    // if BTreeSet is empty it will just write `[]` to the json file
-    let data = serde_json::to_string(loans)?;
-
-    file.write_all(data.as_bytes()).await?;
-
-    Ok(())
-}
-
-// TODO: refine the calculation
-// 40% / 365 days = 0.11% (so we take into account the interest in the next 24 hours until we liquidate)
-// total_debt + 0.11% * total_debt
-pub fn calculate_bidding_amount(total_debt: U256) -> U256 {
-    total_debt + (total_debt * U256::from(11) / U256::from(10_000))
-}
 
-// builds a loan based on the struct `Loan`. does not care if the
-// `NftAsset` is not supported in production
-/// TODO: need to make the two async calls concurrent with `try_join`.
-/// To do that we need to have already stored the token_id and nft_asset
-pub async fn get_loan_data<U>(
+/// builds a `LoanPosition` from `getLoan`. does not care if the
+/// `NftAsset` is not supported in production
+pub async fn get_loan_position<U>(
     loan_id: U256,
     provider: Arc<Provider<U>>,
     lend_pool: LendPool<Provider<U>>,
     lend_pool_loan: LendPoolLoan<Provider<U>>,
-    state: Option<State>,
-) -> Result<Option<Loan>>
+    state: Option<&State>,
+) -> Result<Option<LoanPosition>>
 where
     U: JsonRpcClient + 'static,
 {
-    let loan_data: LoanData = if let Some(state) = state.clone() {
+    let loan_data: LoanData = if let Some(state) = state {
         lend_pool_loan
             .get_loan(loan_id)
             .call_raw()
-            .state(&state)
+            .state(state)
             .await?
     } else {
         lend_pool_loan.get_loan(loan_id).await?
@@ -107,66 +68,99 @@ where
             let (_, _, bid_end_timestamp, _) = lend_pool
                 .get_nft_auction_end_time(loan_data.nft_asset, loan_data.nft_token_id)
                 .await?;
-            if current_timestamp >= bid_end_timestamp {
-                Status::RepaidDefaulted
-            } else {
-                Status::Auction(Auction {
-                    current_bid: loan_data.bid_price,
-                    current_bidder: loan_data.bidder_address,
-                    bid_end_timestamp,
-                    reserve_asset,
-                    nft_asset,
-                    nft_token_id: loan_data.nft_token_id,
-                })
-            }
+            auction_status(
+                &loan_data,
+                reserve_asset,
+                nft_asset,
+                current_timestamp,
+                bid_end_timestamp,
+            )
         }
         4 | 5 => Status::RepaidDefaulted,
         _ => panic!("invalid state"),
     };
 
+    Ok(Some(LoanPosition {
+        loan_id: loan_data.loan_id,
+        status,
+        nft_asset,
+        nft_token_id: loan_data.nft_token_id,
+        reserve_asset,
+        scaled_debt: loan_data.scaled_amount,
+    }))
+}
+
+// builds a loan based on the struct `Loan`. does not care if the
+// `NftAsset` is not supported in production
+pub async fn get_loan_data<U>(
+    loan_id: U256,
+    provider: Arc<Provider<U>>,
+    lend_pool: LendPool<Provider<U>>,
+    lend_pool_loan: LendPoolLoan<Provider<U>>,
+    state: Option<State>,
+) -> Result<Option<Loan>>
+where
+    U: JsonRpcClient + 'static,
+{
+    let Some(position) = get_loan_position(
+        loan_id,
+        provider,
+        lend_pool.clone(),
+        lend_pool_loan,
+        state.as_ref(),
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let nft_asset = Address::from(position.nft_asset);
+
     let (_, _, _, total_debt, _, health_factor) = if let Some(state) = state {
         lend_pool
-            .get_nft_debt_data(loan_data.nft_asset, loan_data.nft_token_id)
+            .get_nft_debt_data(nft_asset, position.nft_token_id)
             .call_raw()
             .state(&state)
             .await?
     } else {
         lend_pool
-            .get_nft_debt_data(loan_data.nft_asset, loan_data.nft_token_id)
+            .get_nft_debt_data(nft_asset, position.nft_token_id)
             .await?
     };
 
     let loan = Loan {
         health_factor,
-        status,
+        status: position.status,
         total_debt,
-        reserve_asset,
-        nft_asset,
-        loan_id: loan_data.loan_id,
-        nft_token_id: loan_data.nft_token_id,
+        reserve_asset: position.reserve_asset,
+        nft_asset: position.nft_asset,
+        loan_id: position.loan_id,
+        nft_token_id: position.nft_token_id,
     };
 
     Ok(Some(loan))
 }
 
-pub async fn handle_sent_bundle<M: Middleware, S: Signer, P: JsonRpcClient>(
-    results: SentBundle<'_, M, S, P>,
-) -> Result<()> {
-    // realistically only needs 1 check
-    for result in results {
-        match result {
-            Ok(pending_bundle) => match pending_bundle.await {
-                Ok(bundle_hash) => info!(
-                    "Bundle with hash {:?} was included in target block",
-                    bundle_hash.unwrap_or_default()
-                ),
-                Err(PendingBundleError::BundleNotIncluded) => {
-                    error!("Bundle was not included in target block.")
-                }
-                Err(e) => error!("An error occured: {}", e),
-            },
-            Err(e) => error!("An error occured: {}", e),
-        }
+/// status of a loan whose `LoanData.state` is `Auction`. once the bid window
+/// is over it can only be liquidated so we treat it as `RepaidDefaulted`
+pub fn auction_status(
+    loan_data: &LoanData,
+    reserve_asset: ReserveAsset,
+    nft_asset: NftAsset,
+    current_timestamp: U256,
+    bid_end_timestamp: U256,
+) -> Status {
+    if current_timestamp >= bid_end_timestamp {
+        Status::RepaidDefaulted
+    } else {
+        Status::Auction(Auction {
+            current_bid: loan_data.bid_price,
+            current_bidder: loan_data.bidder_address,
+            first_bidder: loan_data.first_bidder_address,
+            bid_end_timestamp,
+            reserve_asset,
+            nft_asset,
+            nft_token_id: loan_data.nft_token_id,
+        })
     }
-    Ok(())
 }
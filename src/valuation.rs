@@ -0,0 +1,73 @@
+use crate::math::percent_mul;
+use ethers::types::U256;
+
+/// rarest attributes of a token that are priced, each costs a bids and a sales request
+pub const RARE_ATTRIBUTES_TO_PRICE: usize = 3;
+
+/// sales of tokens sharing an attribute needed for their median to count
+pub const MIN_COMPARABLE_SALES: usize = 3;
+
+/// recent sales fetched per attribute
+pub const COMPARABLE_SALES_LIMIT: usize = 20;
+
+/// tokens valued at once, each makes its requests one after the other
+pub const TOKEN_VALUATION_CONCURRENCY: usize = 4;
+
+/// Token-level pricing on top of the collection-wide best bid.
+///
+/// Bids on the token or on one of its rare attributes can be sold into right away.
+/// Recent sales of tokens sharing its rarest attribute aren't bids anyone has to honor,
+/// they only cap the bids in case one is off.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenValuation {
+    /// best bid on this specific token
+    pub best_token_bid: Option<U256>,
+    /// best bid on any of its `RARE_ATTRIBUTES_TO_PRICE` rarest attributes
+    pub best_attribute_bid: Option<U256>,
+    /// recent net sale prices of tokens sharing its rarest attribute with enough sales
+    pub comparable_sales: Vec<U256>,
+}
+
+impl TokenValuation {
+    /// Best bid in ETH (1e18), no more than the comparable sales median.
+    /// Zero if nobody bids on the token
+    pub fn value(&self) -> U256 {
+        let best_bid = [self.best_token_bid, self.best_attribute_bid]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or_default();
+
+        match median(&self.comparable_sales) {
+            Some(sales_median) => best_bid.min(sales_median),
+            None => best_bid,
+        }
+    }
+}
+
+/// What a token is bid with: its `token_value`, never below the guarded `collection_price`
+/// nor above `max_multiple` of it (two decimals). Token and attribute bids aren't checked
+/// against the twap, one spoofed bid can't make us overbid by more than that
+pub fn bounded_token_value(collection_price: U256, token_value: U256, max_multiple: U256) -> U256 {
+    token_value
+        .min(percent_mul(collection_price, max_multiple))
+        .max(collection_price)
+}
+
+/// `None` for fewer than `MIN_COMPARABLE_SALES` prices
+pub fn median(prices: &[U256]) -> Option<U256> {
+    if prices.len() < MIN_COMPARABLE_SALES {
+        return None;
+    }
+
+    let mut prices = prices.to_vec();
+    prices.sort();
+
+    let mid = prices.len() / 2;
+
+    if prices.len().is_multiple_of(2) {
+        Some((prices[mid - 1] + prices[mid]) / 2)
+    } else {
+        Some(prices[mid])
+    }
+}
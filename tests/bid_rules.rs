@@ -0,0 +1,41 @@
+#![cfg(test)]
+
+use bend_dao_collector::benddao::bid_rules::{min_first_bid, min_outbid};
+use ethers::{types::U256, utils::parse_ether};
+
+fn eth(amount: &str) -> U256 {
+    parse_ether(amount).unwrap()
+}
+
+#[test]
+fn first_bid_covers_the_debt_at_inclusion() {
+    // nft worth less than the debt, the lend pool wants the debt plus 1%
+    let liquidate_price = eth("10.1");
+    let payback_amount = eth("10");
+
+    assert_eq!(
+        min_first_bid(eth("10.5"), liquidate_price, payback_amount),
+        eth("10.605")
+    );
+
+    // otherwise the liquidate price, as long as it still covers the debt
+    let liquidate_price = eth("12");
+
+    assert_eq!(
+        min_first_bid(eth("10.5"), liquidate_price, payback_amount),
+        eth("12")
+    );
+    // a debt grown past the liquidate price is priced like the lend pool does
+    assert_eq!(
+        min_first_bid(eth("12.5"), liquidate_price, payback_amount),
+        eth("12.625")
+    );
+}
+
+#[test]
+fn outbid_beats_the_previous_bid_by_one_percent_of_the_debt() {
+    assert_eq!(min_outbid(eth("10"), eth("11")), eth("11.1"));
+
+    // a bid that no longer covers the debt
+    assert_eq!(min_outbid(eth("20"), eth("10")), eth("20"));
+}
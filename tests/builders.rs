@@ -0,0 +1,131 @@
+#![cfg(test)]
+
+use anyhow::Result;
+use bend_dao_collector::builders::{load_bundle_signer, Builder, BuilderRegistry, BuilderStats};
+use bend_dao_collector::constants::{BUILDER_COOLDOWN, DEFAULT_BUILDERS, MAX_BUILDER_FAILURES};
+use ethers::{core::rand::thread_rng, signers::LocalWallet, signers::Signer, types::Bytes};
+use std::{collections::BTreeMap, os::unix::fs::PermissionsExt, time::Duration};
+
+fn registry(entries: &[&str]) -> BuilderRegistry {
+    let entries: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+    BuilderRegistry::new(
+        Builder::from_config(&entries, &[]),
+        LocalWallet::new(&mut thread_rng()),
+    )
+}
+
+#[test]
+fn test_parse_builders() -> Result<()> {
+    let builder = Builder::parse("https://rpc.beaverbuild.org")?;
+    assert_eq!(builder.name, "beaverbuild");
+    assert!(builder.enabled);
+
+    let builder = Builder::parse("bloxroute=https://mev.api.blxrbdn.com")?;
+    assert_eq!(builder.name, "bloxroute");
+    assert_eq!(builder.url.as_str(), "https://mev.api.blxrbdn.com/");
+
+    assert!(Builder::parse("not a url").is_err());
+
+    let builders = Builder::from_config(
+        &[
+            "https://rpc.titanbuilder.xyz".to_string(),
+            "https://buildai.net".to_string(),
+        ],
+        &["titanbuilder".to_string()],
+    );
+    assert!(!builders[0].enabled);
+    assert!(builders[1].enabled);
+
+    let builders = Builder::from_config(&["".to_string()], &[]);
+    assert_eq!(builders.len(), DEFAULT_BUILDERS.len());
+
+    Ok(())
+}
+
+#[test]
+fn test_dead_builders_are_dropped() {
+    let mut stats = BuilderStats::default();
+    let now = 1_700_000_000;
+
+    for _ in 0..MAX_BUILDER_FAILURES - 1 {
+        stats.record_response(false, Duration::ZERO, now);
+    }
+    assert!(!stats.is_dropped(now));
+
+    stats.record_response(false, Duration::ZERO, now);
+    assert!(stats.is_dropped(now));
+    assert!(!stats.is_dropped(now + BUILDER_COOLDOWN));
+
+    stats.record_response(true, Duration::from_millis(120), now + BUILDER_COOLDOWN);
+    assert!(!stats.is_dropped(now + BUILDER_COOLDOWN));
+    assert_eq!(stats.consecutive_failures, 0);
+    assert_eq!(stats.average_latency(), Some(120));
+}
+
+#[test]
+fn test_reputable_builders_rank_first() {
+    let registry = registry(&[
+        "https://rpc.beaverbuild.org",
+        "https://rpc.titanbuilder.xyz",
+        "https://rsync-builder.xyz",
+    ]);
+    assert_eq!(registry.ranked(0), vec![0, 1, 2]);
+
+    let extra_data = Bytes::from(b"Titan (titanbuilder.xyz)".to_vec());
+    assert_eq!(
+        registry.record_inclusion(&extra_data).as_deref(),
+        Some("titanbuilder")
+    );
+    assert_eq!(
+        registry.record_inclusion(&Bytes::from(b"geth".to_vec())),
+        None
+    );
+
+    assert_eq!(registry.ranked(0), vec![1, 0, 2]);
+    assert_eq!(registry.stats()[1].1.included, 1);
+}
+
+#[test]
+fn test_builder_stats_are_restored() {
+    let registry = registry(&[
+        "https://rpc.beaverbuild.org",
+        "https://rpc.titanbuilder.xyz",
+    ]);
+    let saved = BTreeMap::from([(
+        "titanbuilder".to_string(),
+        BuilderStats {
+            included: 3,
+            ..Default::default()
+        },
+    )]);
+
+    registry.restore(&saved);
+
+    assert_eq!(registry.stats_of("titanbuilder").unwrap().included, 3);
+    assert_eq!(
+        registry.stats_of("beaverbuild"),
+        Some(BuilderStats::default())
+    );
+    assert_eq!(registry.ranked(0), vec![1, 0]);
+}
+
+#[tokio::test]
+async fn test_bundle_signer_is_persisted() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("bundle-signer-{}.key", std::process::id()));
+    let _ = tokio::fs::remove_file(&path).await;
+
+    let signer = load_bundle_signer(None, &path).await?;
+    let mode = tokio::fs::metadata(&path).await?.permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let reloaded = load_bundle_signer(None, &path).await?;
+    assert_eq!(signer.address(), reloaded.address());
+
+    let key = tokio::fs::read_to_string(&path).await?;
+    let configured = load_bundle_signer(Some(&key), "/nonexistent").await?;
+    assert_eq!(signer.address(), configured.address());
+
+    tokio::fs::remove_file(&path).await?;
+
+    Ok(())
+}
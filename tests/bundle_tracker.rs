@@ -0,0 +1,114 @@
+#![cfg(test)]
+
+use bend_dao_collector::benddao::loan::ReserveAsset;
+use bend_dao_collector::bundle_tracker::{is_outbid, BidBundle, BundleOutcome, BundlePolicy};
+use bend_dao_collector::simulator::bundle::{reverting_tx_hashes, BundleSimulation, TxSimulation};
+use bend_dao_collector::types::AuctionBid;
+use ethers::types::{Address, Bytes, H256, U256};
+use ethers_flashbots::BundleRequest;
+
+#[test]
+fn test_competing_bids_make_ours_moot() {
+    let us = Address::repeat_byte(1);
+    let them = Address::repeat_byte(2);
+    let no_bid = (Address::zero(), U256::zero());
+
+    // nobody bid yet
+    assert!(!is_outbid(no_bid, no_bid, us));
+    // our bid landed
+    assert!(!is_outbid(no_bid, (us, 10.into()), us));
+    // a competing first bid landed
+    assert!(is_outbid(no_bid, (them, 10.into()), us));
+
+    // the bid we're outbidding is still the highest
+    assert!(!is_outbid((them, 10.into()), (them, 10.into()), us));
+    // they raised their own bid
+    assert!(is_outbid((them, 10.into()), (them, 12.into()), us));
+    // someone else outbid them first
+    let other = Address::repeat_byte(3);
+    assert!(is_outbid((them, 10.into()), (other, 12.into()), us));
+}
+
+#[test]
+fn test_bundle_outcomes_are_stored_lowercase() {
+    assert_eq!(BundleOutcome::Expired.to_string(), "expired");
+    assert_eq!(
+        serde_json::to_string(&BundleOutcome::Reverted).unwrap(),
+        r#""reverted""#
+    );
+    assert_eq!(
+        serde_json::to_string(&BundleOutcome::Aborted("nonce too low".into())).unwrap(),
+        r#"{"aborted":"nonce too low"}"#
+    );
+}
+
+fn bid(nft_token_id: u64) -> AuctionBid {
+    AuctionBid {
+        nft_asset: Address::repeat_byte(4),
+        nft_token_id: nft_token_id.into(),
+        bid_price: 10.into(),
+        reserve_asset: ReserveAsset::Weth,
+        expected_profit: 1.into(),
+    }
+}
+
+#[test]
+fn test_split_policy_gives_each_bid_a_bundle() {
+    let bundle = BidBundle {
+        bids: vec![bid(1), bid(2)],
+        twaps: vec![(Address::repeat_byte(4), 20.into())],
+        ..Default::default()
+    };
+
+    assert_eq!(bundle.clone().split(BundlePolicy::Batch).len(), 1);
+
+    let bundles = bundle.split(BundlePolicy::Split);
+    assert_eq!(bundles.len(), 2);
+    assert_eq!(bundles[1].bids[0].nft_token_id, 2.into());
+    assert_eq!(bundles[1].twaps.len(), 1);
+}
+
+#[test]
+fn test_revertible_bids_keep_the_bundle_valid() {
+    let oracle = H256::repeat_byte(1);
+    let first = H256::repeat_byte(2);
+    let second = H256::repeat_byte(3);
+
+    let bundle = BundleRequest::new()
+        .push_transaction(Bytes::from(vec![0x1]))
+        .push_revertible_transaction(Bytes::from(vec![0x2]));
+    assert_eq!(reverting_tx_hashes(&bundle).len(), 1);
+    assert!(reverting_tx_hashes(&BundleRequest::new()).is_empty());
+
+    let tx = |hash, error: Option<&str>| TxSimulation {
+        hash,
+        gas_used: 21_000.into(),
+        error: error.map(str::to_string),
+    };
+    let simulation = |txs| BundleSimulation {
+        gas_used: 63_000.into(),
+        effective_gas_price: 1.into(),
+        txs,
+    };
+
+    // a frontrun bid reverts, the other still goes through
+    let frontrun = simulation(vec![
+        tx(oracle, None),
+        tx(first, Some("NFTPool: bid price less than")),
+        tx(second, None),
+    ]);
+    assert!(!frontrun.is_success());
+    assert!(frontrun.is_success_with(&[first, second]));
+
+    // nothing would be bought
+    let all_reverted = simulation(vec![
+        tx(oracle, None),
+        tx(first, Some("reverted")),
+        tx(second, Some("reverted")),
+    ]);
+    assert!(!all_reverted.is_success_with(&[first, second]));
+
+    // the oracle update isn't allowed to revert
+    let oracle_reverted = simulation(vec![tx(oracle, Some("reverted")), tx(first, None)]);
+    assert!(!oracle_reverted.is_success_with(&[first]));
+}
@@ -0,0 +1,163 @@
+#![cfg(test)]
+
+use anyhow::{anyhow, Result};
+use bend_dao_collector::{
+    benddao::loan::ReserveAsset, constants::*, erc_20::ApproveCall, executor_batch::ExecutorBatch,
+    types::AuctionBid, Erc20, Executor, Weth,
+};
+use ethers::{
+    abi::AbiDecode,
+    contract::ContractFactory,
+    middleware::SignerMiddleware,
+    providers::{Http, Middleware, Provider},
+    signers::{LocalWallet, Signer},
+    types::{Address, U256},
+    utils::Anvil,
+};
+use ethers_solc::Solc;
+use std::sync::Arc;
+
+fn bid(nft_token_id: u64) -> AuctionBid {
+    AuctionBid {
+        nft_asset: BAYC.into(),
+        nft_token_id: nft_token_id.into(),
+        bid_price: U256::exp10(17),
+        reserve_asset: ReserveAsset::Weth,
+        expected_profit: U256::zero(),
+    }
+}
+
+#[test]
+fn test_executor_batch() {
+    let us = Address::from(OUR_EOA_ADDRESS);
+    let mut batch = ExecutorBatch::new();
+    batch
+        .wrap_eth(U256::exp10(18))
+        .approve(WETH.into(), LEND_POOL.into())
+        .auction(&bid(1), us, true)
+        .auction(&bid(2), us, true)
+        .pay_coinbase(U256::exp10(16));
+
+    assert_eq!(batch.calls.len(), 4);
+    assert_eq!(batch.value, U256::exp10(18) + U256::exp10(16));
+    assert_eq!(batch.coinbase_payment, U256::exp10(16));
+
+    let wrap = &batch.calls[0];
+    assert_eq!(wrap.target, Address::from(WETH));
+    assert_eq!(wrap.value, U256::exp10(18));
+    assert!(!wrap.allow_failure);
+
+    let auction = &batch.calls[2];
+    assert_eq!(auction.target, Address::from(LEND_POOL));
+    assert!(auction.value.is_zero());
+    assert!(auction.allow_failure);
+}
+
+#[test]
+fn test_usdt_allowance_is_reset_before_approving() {
+    let mut batch = ExecutorBatch::new();
+    batch
+        .revoke(USDT.into(), LEND_POOL.into())
+        .approve(USDT.into(), LEND_POOL.into());
+
+    let amounts: Vec<U256> = batch
+        .calls
+        .iter()
+        .map(|call| ApproveCall::decode(&call.data).unwrap().amount)
+        .collect();
+    assert_eq!(amounts, vec![U256::zero(), U256::MAX]);
+    assert!(batch.calls.iter().all(|call| !call.allow_failure));
+}
+
+#[tokio::test]
+async fn test_executor_pays_coinbase_only_if_all_calls_succeed() -> Result<()> {
+    let url = dotenv::var("MAINNET_RPC_URL")?;
+    let anvil = Anvil::new().fork(url).spawn();
+
+    let provider = Arc::new(Provider::<Http>::try_from(anvil.endpoint())?);
+    let wallet: LocalWallet = anvil.keys()[0].clone().into();
+    let client = SignerMiddleware::new(provider.clone(), wallet.with_chain_id(anvil.chain_id()));
+    let client = Arc::new(client);
+
+    let output = Solc::default().compile_source("contracts/Executor.sol")?;
+    assert!(!output.has_error(), "{:?}", output.errors);
+    let (abi, bytecode, _) = output
+        .find("Executor")
+        .ok_or_else(|| anyhow!("Executor is missing from the compiler output"))?
+        .into_parts_or_default();
+    let deployed = ContractFactory::new(abi, bytecode, client.clone())
+        .deploy(())?
+        .send()
+        .await?;
+    let executor = Executor::new(deployed.address(), client.clone());
+
+    let weth = Weth::new(Address::from(WETH), client.clone());
+    let weth_erc20 = Erc20::new(Address::from(WETH), client.clone());
+    let us = client.address();
+
+    // the auction on a token that isn't in the lend pool fails and is tolerated
+    let mut batch = ExecutorBatch::new();
+    batch
+        .wrap_eth(U256::exp10(18))
+        .approve(WETH.into(), LEND_POOL.into())
+        .auction(&bid(u64::MAX), us, true)
+        .pay_coinbase(U256::exp10(16));
+    executor
+        .execute(batch.calls, batch.coinbase_payment)
+        .value(batch.value)
+        .send()
+        .await?
+        .await?;
+
+    assert_eq!(weth.balance_of(executor.address()).await?, U256::exp10(18));
+    assert_eq!(
+        weth_erc20
+            .allowance(executor.address(), LEND_POOL.into())
+            .await?,
+        U256::MAX
+    );
+    // the payment was not made, it stays with the executor
+    assert_eq!(
+        provider.get_balance(executor.address(), None).await?,
+        U256::exp10(16)
+    );
+
+    // without failures the coinbase is paid
+    let mut batch = ExecutorBatch::new();
+    batch
+        .wrap_eth(U256::exp10(18))
+        .pay_coinbase(U256::exp10(16));
+    executor
+        .execute(batch.calls, batch.coinbase_payment)
+        .value(batch.value)
+        .send()
+        .await?
+        .await?;
+    assert_eq!(
+        provider.get_balance(executor.address(), None).await?,
+        U256::exp10(16)
+    );
+
+    // a required call that fails reverts the whole batch
+    let mut batch = ExecutorBatch::new();
+    batch
+        .wrap_eth(U256::exp10(18))
+        .auction(&bid(u64::MAX), us, false);
+    let reverted = executor
+        .execute(batch.calls, batch.coinbase_payment)
+        .value(batch.value)
+        .call()
+        .await;
+    assert!(reverted.is_err());
+
+    // only the owner may execute
+    let other: LocalWallet = anvil.keys()[1].clone().into();
+    let other = SignerMiddleware::new(provider.clone(), other.with_chain_id(anvil.chain_id()));
+    let not_owner = Executor::new(executor.address(), Arc::new(other))
+        .execute(vec![], U256::zero())
+        .call()
+        .await;
+    assert!(not_owner.is_err());
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+#![cfg(test)]
+
+use bend_dao_collector::gas_strategy::{FeeMarket, GasStrategy};
+use ethers::types::{FeeHistory, U256};
+
+fn gwei(amount: u64) -> U256 {
+    U256::from(amount) * U256::exp10(9)
+}
+
+fn strategy() -> GasStrategy {
+    GasStrategy {
+        profit_share: 2_000.into(),
+        max_fee_per_gas: gwei(300),
+    }
+}
+
+fn market() -> FeeMarket {
+    FeeMarket {
+        base_fee: gwei(20),
+        priority_fees: vec![gwei(3), gwei(1), gwei(2)],
+    }
+}
+
+#[test]
+fn tips_at_least_what_recent_blocks_did() {
+    // 20% of 0.001 ETH over 300k gas is under a gwei
+    let fees = strategy().fees(&market(), U256::exp10(15), 300_000.into());
+
+    assert_eq!(fees.max_priority_fee_per_gas, gwei(2));
+    assert_eq!(fees.max_fee_per_gas, gwei(42));
+}
+
+#[test]
+fn shares_expected_profit_up_to_the_ceiling() {
+    // 20% of 1.5 ETH over 300k gas is 1000 gwei
+    let fees = strategy().fees(&market(), U256::exp10(18) * 3 / 2, 300_000.into());
+
+    assert_eq!(fees.max_priority_fee_per_gas, gwei(300));
+    assert_eq!(fees.max_fee_per_gas, gwei(300));
+
+    // 20% of 0.015 ETH over 300k gas is 10 gwei
+    let fees = strategy().fees(&market(), U256::exp10(16) * 3 / 2, 300_000.into());
+
+    assert_eq!(fees.max_priority_fee_per_gas, gwei(10));
+    assert_eq!(fees.max_fee_per_gas, gwei(50));
+}
+
+#[test]
+fn reads_the_next_base_fee_from_fee_history() {
+    let history: FeeHistory = serde_json::from_str(
+        r#"{
+            "oldestBlock": "0x1",
+            "baseFeePerGas": ["0x4a817c800", "0x4e3b29200"],
+            "gasUsedRatio": [0.6],
+            "reward": [["0x3b9aca00"]]
+        }"#,
+    )
+    .unwrap();
+
+    let market = FeeMarket::from_fee_history(&history).unwrap();
+
+    assert_eq!(market.base_fee, gwei(21));
+    assert_eq!(market.priority_fee(), gwei(1));
+}
@@ -0,0 +1,164 @@
+#![cfg(test)]
+
+use anyhow::Result;
+use bend_dao_collector::{
+    benddao::{
+        loan::{NftAsset, ReserveAsset},
+        nft_configuration::NftConfiguration,
+        projection::projected_liquidation_timestamp,
+        status::Status,
+    },
+    constants::*,
+    health_factor::{liquidation_threshold, HealthFactorEngine, LoanPosition, ReserveState},
+};
+use ethers::types::{Address, U256};
+
+// same loan as `interest_calcs.rs`
+const LOAN_ID: u64 = 12196;
+const NFT_TOKEN_ID: u64 = 5477;
+const BORROW_RATE_T0: &str = "105768883926509761075108855";
+const BORROW_INDEX_T0: &str = "1089155710661941255265101813";
+const USER_SCALED_DEBT: u64 = 34230761162;
+const TIMESTAMP_T0: u64 = 1704446555;
+const TIMESTAMP_T1: u64 = 1704447839;
+const DEBT_T1: u64 = 37282789555;
+
+/// wei per USDT
+const USDT_PRICE_IN_ETH: u64 = 450_000_000_000_000;
+
+fn engine() -> Result<HealthFactorEngine> {
+    let mut engine = HealthFactorEngine::default();
+
+    engine.set_reserve(
+        ReserveAsset::Usdt,
+        ReserveState {
+            variable_borrow_index: U256::from_dec_str(BORROW_INDEX_T0)?,
+            variable_borrow_rate: U256::from_dec_str(BORROW_RATE_T0)?,
+            last_update_timestamp: U256::from(TIMESTAMP_T0),
+            price_in_eth: U256::from(USDT_PRICE_IN_ETH),
+        },
+    );
+    engine.set_liquidation_threshold(NftAsset::Bayc, U256::from(8_000));
+    engine.set_twap(NftAsset::Bayc, U256::exp10(18) * 25);
+    engine.insert_position(LoanPosition {
+        loan_id: LOAN_ID.into(),
+        status: Status::Active,
+        nft_asset: NftAsset::Bayc,
+        nft_token_id: NFT_TOKEN_ID.into(),
+        reserve_asset: ReserveAsset::Usdt,
+        scaled_debt: USER_SCALED_DEBT.into(),
+    });
+
+    Ok(engine)
+}
+
+#[test]
+fn total_debt_matches_lend_pool() -> Result<()> {
+    let engine = engine()?;
+    let position = engine.position(LOAN_ID.into()).unwrap();
+
+    let total_debt = engine.total_debt(position, TIMESTAMP_T1.into()).unwrap();
+
+    assert_eq!(total_debt, U256::from(DEBT_T1));
+
+    Ok(())
+}
+
+#[test]
+fn health_factor_follows_hypothetical_twaps() -> Result<()> {
+    let engine = engine()?;
+    let position = engine.position(LOAN_ID.into()).unwrap();
+    let timestamp = U256::from(TIMESTAMP_T1);
+
+    // 25 ETH / 0.00045 = 55_555.555555 USDT of collateral, 80% of it over 37_282.789555 USDT
+    let health_factor = engine.health_factor(position, timestamp, &[]).unwrap();
+    let expected = U256::from(44_444_444_444u64) * U256::exp10(18) / U256::from(DEBT_T1);
+    assert_eq!(health_factor / 1_000, expected / 1_000);
+    assert!(health_factor > U256::exp10(18));
+
+    // a twap drop to 15 ETH makes the loan auctionable
+    let twaps = [(Address::from(BAYC), U256::exp10(18) * 15)];
+    let loan = engine.loan(LOAN_ID.into(), timestamp, &twaps).unwrap();
+    assert!(loan.is_auctionable());
+
+    // hypothetical twaps are not stored
+    let loan = engine.loan(LOAN_ID.into(), timestamp, &[]).unwrap();
+    assert!(!loan.is_auctionable());
+
+    Ok(())
+}
+
+#[test]
+fn loans_without_market_data_are_skipped() -> Result<()> {
+    let mut engine = engine()?;
+
+    engine.insert_position(LoanPosition {
+        loan_id: 1.into(),
+        status: Status::Active,
+        nft_asset: NftAsset::Azuki,
+        nft_token_id: 1.into(),
+        reserve_asset: ReserveAsset::Usdt,
+        scaled_debt: USER_SCALED_DEBT.into(),
+    });
+
+    let loans = engine.loans(TIMESTAMP_T1.into(), &[]);
+
+    assert_eq!(loans.len(), 1);
+    assert_eq!(loans[0].loan_id, U256::from(LOAN_ID));
+
+    Ok(())
+}
+
+#[test]
+fn reads_liquidation_threshold_from_nft_configuration() {
+    // ltv 60%, liquidation threshold 80%, liquidation bonus 5%
+    let config = U256::from(6_000) | (U256::from(8_000) << 16) | (U256::from(500) << 32);
+
+    assert_eq!(liquidation_threshold(config), U256::from(8_000));
+}
+
+#[test]
+fn reads_redeem_and_auction_terms_from_nft_configuration() {
+    // redeem 4h, auction 24h, redeem fine 5%, redeem threshold 50%, min bid fine 0.2 ETH
+    let data = (U256::from(8_000) << 16)
+        | (U256::from(4) << 64)
+        | (U256::from(24) << 72)
+        | (U256::from(500) << 80)
+        | (U256::from(5_000) << 96)
+        | (U256::from(2_000) << 112);
+    let config = NftConfiguration::from(data);
+
+    assert_eq!(config.liquidation_threshold, U256::from(8_000));
+    assert_eq!(config.redeem_duration_secs(), U256::from(4 * ONE_HOUR));
+    assert_eq!(config.auction_duration_secs(), U256::from(ONE_DAY));
+    assert_eq!(config.redeem_threshold, U256::from(5_000));
+
+    let one = U256::exp10(18);
+    // 5% of 10 ETH
+    assert_eq!(config.bid_fine(one * 10), one / 2);
+    // 5% of 1 ETH is below the 0.2 ETH floor
+    assert_eq!(config.bid_fine(one), one / 5);
+}
+
+#[test]
+fn projects_when_interest_makes_the_loan_auctionable() -> Result<()> {
+    let engine = engine()?;
+    let position = engine.position(LOAN_ID.into()).unwrap();
+    let from = U256::from(TIMESTAMP_T1);
+    let one = U256::exp10(18);
+
+    // ~10.5% a year doesn't get a 1.19 health factor under 1 within a day
+    assert_eq!(
+        projected_liquidation_timestamp(&engine, LOAN_ID.into(), from, ONE_DAY),
+        None
+    );
+
+    let crossing =
+        projected_liquidation_timestamp(&engine, LOAN_ID.into(), from, 5 * ONE_YEAR as u64)
+            .unwrap();
+
+    assert!(engine.health_factor(position, crossing, &[]).unwrap() < one);
+    assert!(engine.health_factor(position, crossing - 1, &[]).unwrap() >= one);
+
+    Ok(())
+}
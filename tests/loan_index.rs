@@ -0,0 +1,214 @@
+#![cfg(test)]
+
+use bend_dao_collector::{
+    benddao::loan::{NftAsset, ReserveAsset},
+    constants::*,
+    lend_pool_loan::{
+        LoanAuctionedFilter, LoanCreatedFilter, LoanRedeemedFilter, LoanRepaidFilter,
+        LoanUpdatedFilter,
+    },
+    loan_index::{IndexedLoanState, LoanIndex},
+    types::Auction,
+    LendPoolLoanEvents,
+};
+use ethers::types::{Address, H256, U256};
+
+const LOAN_ID: u64 = 12196;
+
+/// hash of `block` on the canonical chain
+fn hash(block: u64) -> H256 {
+    H256::from_low_u64_be(block)
+}
+
+/// borrow index of `n`, in ray
+fn borrow_index(n: u64) -> U256 {
+    U256::from(n) * U256::exp10(27)
+}
+
+fn created(amount: u64) -> LendPoolLoanEvents {
+    LendPoolLoanEvents::LoanCreatedFilter(LoanCreatedFilter {
+        loan_id: LOAN_ID.into(),
+        nft_asset: CRYPTOPUNKS.into(),
+        nft_token_id: 5477.into(),
+        reserve_asset: WETH.into(),
+        amount: amount.into(),
+        borrow_index: borrow_index(1),
+        ..Default::default()
+    })
+}
+
+fn updated(amount_added: u64, amount_taken: u64, borrow_index: U256) -> LendPoolLoanEvents {
+    LendPoolLoanEvents::LoanUpdatedFilter(LoanUpdatedFilter {
+        loan_id: LOAN_ID.into(),
+        amount_added: amount_added.into(),
+        amount_taken: amount_taken.into(),
+        borrow_index,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn tracks_scaled_amount_like_lend_pool_loan() {
+    let mut index = LoanIndex::default();
+
+    index.apply(&created(1_000), 1, hash(1), 0.into());
+    // borrow 1_000 more at a 2x index, which is only 500 scaled
+    index.apply(&updated(1_000, 0, borrow_index(2)), 2, hash(2), 0.into());
+    // repay 400 at a 4x index, 100 scaled
+    index.apply(&updated(0, 400, borrow_index(4)), 3, hash(3), 0.into());
+
+    let loan = index.get(LOAN_ID.into()).unwrap();
+    assert_eq!(loan.scaled_amount, U256::from(1_400));
+    assert_eq!(loan.state, IndexedLoanState::Active);
+    assert_eq!(loan.nft_asset, Address::from(CRYPTOPUNKS));
+}
+
+#[test]
+fn follows_the_loan_lifecycle() {
+    let mut index = LoanIndex::default();
+
+    index.apply(&created(1_000), 1, hash(1), 0.into());
+
+    let auctioned = LendPoolLoanEvents::LoanAuctionedFilter(LoanAuctionedFilter {
+        loan_id: LOAN_ID.into(),
+        ..Default::default()
+    });
+    index.apply(&auctioned, 2, hash(2), 0.into());
+    assert_eq!(index.open_loans().count(), 1);
+    assert!(index.get(LOAN_ID.into()).unwrap().to_position().is_none());
+
+    // an auction is still outbid against the indexed debt
+    let auction = Auction {
+        nft_asset: NftAsset::CryptoPunks,
+        nft_token_id: 5477.into(),
+        current_bid: 900.into(),
+        current_bidder: Address::zero(),
+        first_bidder: Address::zero(),
+        bid_end_timestamp: U256::zero(),
+        reserve_asset: ReserveAsset::Weth,
+    };
+    let position = index
+        .open_loan_of(CRYPTOPUNKS.into(), 5477.into())
+        .and_then(|loan| loan.to_auction_position(&auction))
+        .unwrap();
+    assert_eq!(position.scaled_debt, U256::from(1_000));
+    assert!(position.status.is_in_current_auction());
+
+    let redeemed = LendPoolLoanEvents::LoanRedeemedFilter(LoanRedeemedFilter {
+        loan_id: LOAN_ID.into(),
+        amount_taken: 500.into(),
+        borrow_index: borrow_index(1),
+        ..Default::default()
+    });
+    index.apply(&redeemed, 3, hash(3), 0.into());
+    let position = index.get(LOAN_ID.into()).unwrap().to_position().unwrap();
+    assert_eq!(position.scaled_debt, U256::from(500));
+
+    let repaid = LendPoolLoanEvents::LoanRepaidFilter(LoanRepaidFilter {
+        loan_id: LOAN_ID.into(),
+        ..Default::default()
+    });
+    index.apply(&repaid, 4, hash(4), 0.into());
+    assert_eq!(
+        index.get(LOAN_ID.into()).unwrap().state,
+        IndexedLoanState::Repaid
+    );
+    assert_eq!(index.open_loans().count(), 0);
+}
+
+#[test]
+fn never_applies_a_log_twice() {
+    let mut index = LoanIndex::default();
+    let update = updated(1_000, 0, borrow_index(1));
+
+    assert!(index.apply(&created(1_000), 10, hash(10), 0.into()));
+    assert!(index.apply(&update, 10, hash(10), 1.into()));
+    // a restart re-querying block 10
+    assert!(!index.apply(&created(1_000), 10, hash(10), 0.into()));
+    assert!(!index.apply(&update, 10, hash(10), 1.into()));
+
+    assert_eq!(
+        index.get(LOAN_ID.into()).unwrap().scaled_amount,
+        U256::from(2_000)
+    );
+}
+
+#[test]
+fn live_logs_wait_for_the_backfill() {
+    let mut index = LoanIndex::default();
+
+    // nothing synced yet
+    assert!(!index.apply_live(&created(1_000), 10, hash(10), 0.into()));
+    assert_eq!(index.synced_block(), None);
+
+    let json = r#"{"loans":{},"synced_block":8,"last_applied":null}"#;
+    let mut index: LoanIndex = serde_json::from_str(json).unwrap();
+
+    // block 9 is missing so the log is left for `sync`
+    assert!(!index.apply_live(&created(1_000), 10, hash(10), 0.into()));
+    assert_eq!(index.synced_block(), Some(8));
+
+    assert!(index.apply_live(&created(1_000), 9, hash(9), 0.into()));
+    assert_eq!(index.synced_block(), Some(8));
+    assert!(index.apply_live(&updated(1_000, 0, borrow_index(1)), 10, hash(10), 0.into()));
+    assert_eq!(index.synced_block(), Some(9));
+}
+
+#[test]
+fn reverts_reorged_out_blocks() {
+    let json = r#"{"loans":{},"synced_block":8,"last_applied":null}"#;
+    let mut index: LoanIndex = serde_json::from_str(json).unwrap();
+
+    index.apply_live(&created(1_000), 9, hash(9), 0.into());
+    let repaid = LendPoolLoanEvents::LoanRepaidFilter(LoanRepaidFilter {
+        loan_id: LOAN_ID.into(),
+        ..Default::default()
+    });
+    index.apply_live(&repaid, 10, hash(10), 0.into());
+    index.apply_live(&updated(1_000, 0, borrow_index(1)), 11, hash(11), 0.into());
+    assert_eq!(index.open_loans().count(), 0);
+
+    // a log of another block 10 means the repay and everything after it is gone
+    let reorged_block = index.reorged_block(10, H256::repeat_byte(0xff));
+    assert_eq!(reorged_block, Some(10));
+    assert_eq!(index.revert_from(10), vec![U256::from(LOAN_ID)]);
+
+    let loan = index.get(LOAN_ID.into()).unwrap();
+    assert_eq!(loan.state, IndexedLoanState::Active);
+    assert_eq!(loan.scaled_amount, U256::from(1_000));
+    assert_eq!(index.synced_block(), Some(9));
+
+    // the new block 10 is applied even though the reverted one had a log at the same index
+    assert!(index.apply_live(
+        &updated(500, 0, borrow_index(1)),
+        10,
+        H256::repeat_byte(0xff),
+        0.into()
+    ));
+    assert_eq!(
+        index.get(LOAN_ID.into()).unwrap().scaled_amount,
+        U256::from(1_500)
+    );
+
+    // a removed log reverts its block, a loan created there is dropped
+    assert_eq!(index.journaled_block(hash(9)), Some(9));
+    index.revert_from(9);
+    assert!(index.get(LOAN_ID.into()).is_none());
+    assert_eq!(index.synced_block(), Some(8));
+}
+
+#[test]
+fn forgets_confirmed_blocks() {
+    let mut index = LoanIndex::default();
+
+    index.apply(&created(1_000), 10, hash(10), 0.into());
+    index.prune_journal(
+        10 + DEFAULT_REORG_CONFIRMATION_DEPTH,
+        DEFAULT_REORG_CONFIRMATION_DEPTH,
+    );
+
+    // block 10 can't be reorged out anymore
+    assert_eq!(index.journaled_block(hash(10)), None);
+    assert!(index.revert_from(10).is_empty());
+    assert!(index.get(LOAN_ID.into()).is_some());
+}
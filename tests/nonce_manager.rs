@@ -0,0 +1,111 @@
+#![cfg(test)]
+
+use bend_dao_collector::constants::NONCE_RESERVATION_TTL;
+use bend_dao_collector::nonce_manager::{NonceManager, NonceUse};
+use ethers::types::U256;
+
+const NOW: u64 = 1_704_447_839;
+
+#[test]
+fn test_txs_get_distinct_nonces() {
+    let nonces = NonceManager::new();
+
+    let first = nonces.reserve(7.into(), NonceUse::Transaction, NOW);
+    let second = nonces.reserve(7.into(), NonceUse::Transaction, NOW);
+    assert_eq!((first, second), (7.into(), 8.into()));
+
+    // the first never made it to the mempool, its nonce goes to the next reservation
+    nonces.release(first);
+    assert_eq!(
+        nonces.reserve(7.into(), NonceUse::Transaction, NOW),
+        U256::from(7)
+    );
+}
+
+#[test]
+fn test_bundles_built_together_dont_depend_on_each_other() {
+    let nonces = NonceManager::new();
+
+    // a bundle with a funding tx before its bid, and one with a bid only
+    let first = nonces.reserve_bundle(7.into(), 2, NOW);
+    let second = nonces.reserve_bundle(7.into(), 1, NOW);
+    assert_eq!((first, second), (7.into(), 7.into()));
+
+    // a tx doesn't take a nonce a bundle holds
+    assert_eq!(
+        nonces.reserve(7.into(), NonceUse::Transaction, NOW),
+        U256::from(9)
+    );
+
+    // the first bundle is dropped, the second still starts at the chain nonce and
+    // keeps its nonce from being handed out
+    nonces.release(7.into());
+    nonces.release(8.into());
+    assert_eq!(
+        nonces.reserve(7.into(), NonceUse::Transaction, NOW),
+        U256::from(8)
+    );
+    nonces.release(second);
+    assert_eq!(
+        nonces.reserve(7.into(), NonceUse::Transaction, NOW),
+        U256::from(7)
+    );
+}
+
+#[test]
+fn test_bundles_go_after_pending_txs() {
+    let nonces = NonceManager::new();
+
+    nonces.reserve(7.into(), NonceUse::Transaction, NOW);
+    assert_eq!(nonces.reserve_bundle(7.into(), 2, NOW), U256::from(8));
+
+    // a tx reserved while the bundle was pending sits above it
+    nonces.reserve(7.into(), NonceUse::Transaction, NOW);
+    assert_eq!(nonces.reserve_bundle(7.into(), 3, NOW), U256::from(11));
+    assert_eq!(nonces.reserve_bundle(7.into(), 1, NOW), U256::from(8));
+}
+
+#[test]
+fn test_resyncs_with_the_chain() {
+    let nonces = NonceManager::new();
+
+    nonces.reserve(7.into(), NonceUse::Bundle, NOW);
+    nonces.reserve(7.into(), NonceUse::Transaction, NOW);
+
+    // both landed and a tx was sent outside the bot
+    assert_eq!(
+        nonces.reserve(10.into(), NonceUse::Bundle, NOW),
+        U256::from(10)
+    );
+
+    // a leaked bundle reservation is dropped after the ttl, a pending tx isn't
+    nonces.reserve(10.into(), NonceUse::Transaction, NOW);
+    let later = NOW + NONCE_RESERVATION_TTL;
+    assert_eq!(
+        nonces.reserve(10.into(), NonceUse::Transaction, later),
+        U256::from(10)
+    );
+    assert_eq!(
+        nonces.reserve(10.into(), NonceUse::Transaction, later),
+        U256::from(12)
+    );
+}
+
+#[test]
+fn test_gaps_below_pending_txs() {
+    let nonces = NonceManager::new();
+
+    let bundle = nonces.reserve_bundle(3.into(), 2, NOW);
+    nonces.reserve(3.into(), NonceUse::Transaction, NOW);
+    assert!(nonces.gaps(3.into()).is_empty());
+
+    nonces.release(bundle);
+    assert_eq!(nonces.gaps(3.into()), vec![U256::from(3)]);
+
+    // a bundle above the last pending tx doesn't block anything
+    let nonces = NonceManager::new();
+    nonces.reserve(3.into(), NonceUse::Transaction, NOW);
+    let bundle = nonces.reserve_bundle(3.into(), 2, NOW);
+    nonces.release(bundle);
+    assert!(nonces.gaps(3.into()).is_empty());
+}
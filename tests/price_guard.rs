@@ -0,0 +1,100 @@
+#![cfg(test)]
+
+use bend_dao_collector::price_guard::{deviates, PriceEntry, PriceGuard, PriceRejection};
+use ethers::{types::U256, utils::parse_ether};
+
+const NOW: u64 = 1_717_000_000;
+
+fn eth(amount: &str) -> U256 {
+    parse_ether(amount).unwrap()
+}
+
+fn guard() -> PriceGuard {
+    PriceGuard {
+        max_age: 7_200,
+        max_twap_deviation: 3_000.into(),
+        max_change: 2_000.into(),
+    }
+}
+
+fn entry(price: &str, age: u64, previous: Option<&str>) -> PriceEntry {
+    PriceEntry {
+        price: eth(price),
+        sources: vec!["reservoir"],
+        updated_at: NOW - age,
+        previous: previous.map(eth),
+    }
+}
+
+#[test]
+fn accepts_a_fresh_consistent_price() {
+    let price = guard().check(Some(&entry("40", 60, Some("38"))), Some(eth("45")), NOW);
+    assert_eq!(price, Ok(eth("40")));
+
+    // no twap known, no previous refresh
+    let price = guard().check(Some(&entry("40", 0, None)), None, NOW);
+    assert_eq!(price, Ok(eth("40")));
+}
+
+#[test]
+fn refuses_missing_zero_and_stale_prices() {
+    assert_eq!(guard().check(None, None, NOW), Err(PriceRejection::Missing));
+    assert_eq!(
+        guard().check(Some(&entry("0", 0, None)), None, NOW),
+        Err(PriceRejection::Zero)
+    );
+    assert_eq!(
+        guard().check(Some(&entry("40", 7_201, None)), None, NOW),
+        Err(PriceRejection::Stale {
+            age: 7_201,
+            max_age: 7_200
+        })
+    );
+}
+
+#[test]
+fn refuses_suspicious_prices() {
+    // 40 ETH against a 60 ETH twap is 33% off
+    let rejection = guard()
+        .check(Some(&entry("40", 0, None)), Some(eth("60")), NOW)
+        .unwrap_err();
+    assert!(matches!(rejection, PriceRejection::TwapDeviation { .. }));
+    assert_eq!(
+        rejection.to_string(),
+        "price 40.000000000000000000 ETH is more than 30% away from twap 60.000000000000000000 ETH"
+    );
+
+    // doubled since the last refresh
+    let rejection = guard()
+        .check(Some(&entry("80", 0, Some("40"))), None, NOW)
+        .unwrap_err();
+    assert_eq!(
+        rejection,
+        PriceRejection::PriceJump {
+            price: eth("80"),
+            previous: eth("40"),
+            max_change: 2_000.into(),
+        }
+    );
+}
+
+#[test]
+fn cross_checks_the_reserve_oracle_usdt_price() {
+    // 1 USDT at 1 / 3_000 ETH against coinmarketcap's 1 / 3_100
+    let reserve_oracle = U256::exp10(18) / 3_000;
+    let coinmarketcap = U256::exp10(18) / 3_100;
+
+    assert!(!deviates(reserve_oracle, coinmarketcap, 500.into()));
+    assert!(deviates(reserve_oracle, coinmarketcap, 300.into()));
+
+    let rejection = PriceRejection::CrossCheck {
+        price: reserve_oracle,
+        source: "coinmarketcap",
+        cross_check: coinmarketcap,
+        max_deviation: 300.into(),
+    };
+    assert_eq!(
+        rejection.to_string(),
+        "price 0.000333333333333333 ETH is more than 3% away from 0.000322580645161290 ETH reported by coinmarketcap"
+    );
+}
@@ -0,0 +1,176 @@
+#![cfg(test)]
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use bend_dao_collector::{
+    benddao::loan::NftAsset,
+    blur::bids_response::ExecutableBidsResponse,
+    opensea::offers_response::CollectionOffersResponse,
+    price_source::{aggregate, AggregationPolicy, PriceAggregator, PriceSource, Quote},
+};
+use ethers::{types::U256, utils::parse_ether};
+
+fn eth(amount: &str) -> U256 {
+    parse_ether(amount).unwrap()
+}
+
+fn quote(source: &'static str, price: &str, weight: u64) -> Quote {
+    Quote {
+        source,
+        price: eth(price),
+        weight,
+    }
+}
+
+/// Quotes `price` for every collection, fails if `None`
+struct FixedSource {
+    name: &'static str,
+    price: Option<U256>,
+}
+
+#[async_trait]
+impl PriceSource for FixedSource {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn collection_price(&self, _nft_asset: NftAsset) -> Result<U256> {
+        match self.price {
+            Some(price) => Ok(price),
+            None => bail!("api down"),
+        }
+    }
+}
+
+#[test]
+fn weighted_median_ignores_a_single_outlier() {
+    let quotes = [
+        quote("reservoir", "40", 1),
+        quote("opensea", "41", 1),
+        quote("blur", "400", 1),
+    ];
+    assert_eq!(
+        aggregate(&quotes, AggregationPolicy::Median, 1).unwrap(),
+        eth("41")
+    );
+
+    // unless it outweighs the others
+    let quotes = [
+        quote("reservoir", "40", 1),
+        quote("opensea", "41", 1),
+        quote("blur", "400", 3),
+    ];
+    assert_eq!(
+        aggregate(&quotes, AggregationPolicy::Median, 1).unwrap(),
+        eth("400")
+    );
+
+    // with an even split the lower price wins
+    let quotes = [quote("reservoir", "40", 1), quote("opensea", "50", 1)];
+    assert_eq!(
+        aggregate(&quotes, AggregationPolicy::Median, 1).unwrap(),
+        eth("40")
+    );
+}
+
+#[test]
+fn min_of_n_needs_enough_quotes() {
+    let quotes = [
+        quote("reservoir", "40", 1),
+        quote("opensea", "38", 1),
+        quote("blur", "45", 5),
+    ];
+    assert_eq!(
+        aggregate(&quotes, AggregationPolicy::Min, 3).unwrap(),
+        eth("38")
+    );
+    assert!(aggregate(&quotes[..2], AggregationPolicy::Min, 3).is_err());
+    assert!(aggregate(&[], AggregationPolicy::Median, 0).is_err());
+}
+
+#[tokio::test]
+async fn aggregator_leaves_out_failing_and_disabled_sources() -> Result<()> {
+    let mut aggregator = PriceAggregator::new(AggregationPolicy::Median, 2);
+
+    aggregator.add_source(
+        FixedSource {
+            name: "reservoir",
+            price: Some(eth("40")),
+        },
+        1,
+    );
+    aggregator.add_source(
+        FixedSource {
+            name: "opensea",
+            price: None,
+        },
+        1,
+    );
+    aggregator.add_source(
+        FixedSource {
+            name: "blur",
+            price: Some(eth("1")),
+        },
+        0,
+    );
+
+    assert_eq!(aggregator.sources(), 2);
+
+    // only reservoir answers, short of the 2 sources needed
+    assert!(aggregator
+        .collection_price(NftAsset::CryptoPunks)
+        .await
+        .is_err());
+
+    aggregator.add_source(
+        FixedSource {
+            name: "nft_oracle",
+            price: Some(eth("42")),
+        },
+        1,
+    );
+
+    assert_eq!(
+        aggregator.collection_price(NftAsset::CryptoPunks).await?,
+        eth("40")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn parses_marketplace_responses() {
+    let offers: CollectionOffersResponse = serde_json::from_str(
+        r#"{"offers": [
+            {
+                "order_hash": "0x01",
+                "price": {"currency": "WETH", "decimals": 18, "value": "90000000000000000000"},
+                "protocol_data": {"parameters": {"consideration": [{"itemType": 4, "startAmount": "2"}]}}
+            },
+            {
+                "order_hash": "0x02",
+                "price": {"currency": "WETH", "decimals": 18, "value": "44000000000000000000"},
+                "protocol_data": {"parameters": {"consideration": [{"itemType": 4, "startAmount": "1"}]}}
+            },
+            {
+                "order_hash": "0x03",
+                "price": {"currency": "USDC", "decimals": 6, "value": "999000000000"},
+                "protocol_data": {"parameters": {"consideration": [{"itemType": 4, "startAmount": "1"}]}}
+            }
+        ]}"#,
+    )
+    .unwrap();
+
+    // 90 ETH for 2 is 45 each
+    assert_eq!(offers.get_best_offer().unwrap(), eth("45"));
+
+    let bids: ExecutableBidsResponse = serde_json::from_str(
+        r#"{"success": true, "priceLevels": [
+            {"price": "46.5", "executableSize": 0, "numberBidders": 1},
+            {"price": "43.25", "executableSize": 3, "numberBidders": 2}
+        ]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(bids.get_best_bid().unwrap(), eth("43.25"));
+}
@@ -0,0 +1,132 @@
+#![cfg(test)]
+
+use bend_dao_collector::{
+    benddao::loan::ReserveAsset,
+    profit::{to_eth, Opportunity, ProfitModel},
+};
+use ethers::{
+    types::{I256, U256},
+    utils::{parse_ether, parse_units},
+};
+
+fn eth(amount: &str) -> U256 {
+    parse_ether(amount).unwrap()
+}
+
+fn model() -> ProfitModel {
+    ProfitModel {
+        resale_fee: 50.into(),
+        annual_capital_cost: 0.into(),
+        redemption_probability: 0.into(),
+        min_margin: 300.into(),
+        bundle_tip: U256::zero(),
+        gas_profit_share: 0.into(),
+    }
+}
+
+fn opportunity(bid: &str, value: &str) -> Opportunity {
+    Opportunity {
+        bid: eth(bid),
+        value: eth(value),
+        gas_price: parse_units(20, "gwei").unwrap().into(),
+        bid_gas: 300_000.into(),
+        liquidate_gas: 400_000.into(),
+        holding: 86_400.into(),
+        redeem_fine: U256::zero(),
+    }
+}
+
+#[test]
+fn bids_only_above_the_minimum_margin() {
+    // 50 * 0.995 - 40 - 0.014 of gas, well above 3% of 40
+    let estimate = model().estimate(&opportunity("40", "50"));
+    assert_eq!(estimate.gas, eth("0.014"));
+    assert_eq!(estimate.expected_pnl, I256::from_raw(eth("9.736")));
+    assert!(estimate.is_profitable());
+
+    // 0.995 ETH back on a 1 ETH bid is a loss once fees are paid
+    let estimate = model().estimate(&opportunity("1", "1"));
+    assert!(estimate.expected_pnl.is_negative());
+    assert!(!estimate.is_profitable());
+
+    // 2.4% is positive but short of the 3% margin
+    let estimate = model().estimate(&opportunity("10", "10.3"));
+    assert!(estimate.expected_pnl.is_positive());
+    assert!(!estimate.is_profitable());
+}
+
+#[test]
+fn the_builders_profit_share_counts_against_the_margin() {
+    // 10.37 * 0.995 - 10 - 0.014 of gas is just above 3% of 10
+    let estimate = model().estimate(&opportunity("10", "10.37"));
+    assert_eq!(estimate.expected_pnl, I256::from_raw(eth("0.30415")));
+    assert!(estimate.is_profitable());
+
+    // the gas strategy tips 20% of that to the builder, which leaves less than 3%
+    let model = ProfitModel {
+        gas_profit_share: 2_000.into(),
+        ..model()
+    };
+    let estimate = model.estimate(&opportunity("10", "10.37"));
+    assert_eq!(estimate.profit_share, eth("0.06083"));
+    assert_eq!(estimate.expected_pnl, I256::from_raw(eth("0.24332")));
+    assert!(!estimate.is_profitable());
+
+    // a liquidation isn't priced by the gas strategy
+    let liquidation = model.estimate_liquidation(eth("100"), eth("110"), U256::zero());
+    assert_eq!(liquidation.profit_share, U256::zero());
+}
+
+#[test]
+fn redemptions_pay_the_fine_instead_of_the_resale() {
+    let model = ProfitModel {
+        redemption_probability: 5_000.into(),
+        ..model()
+    };
+
+    let mut opportunity = opportunity("10", "10");
+    opportunity.gas_price = U256::zero();
+
+    // half the time we lose 0.05 of fees reselling
+    let estimate = model.estimate(&opportunity);
+    assert_eq!(estimate.expected_pnl, I256::from_raw(eth("0.025")) * -1);
+
+    // the other half we get 5% of the bid as first bidder
+    opportunity.redeem_fine = eth("0.5");
+    let estimate = model.estimate(&opportunity);
+    assert_eq!(estimate.expected_fine, eth("0.25"));
+    assert_eq!(estimate.expected_pnl, I256::from_raw(eth("0.225")));
+}
+
+#[test]
+fn locked_capital_and_tips_cost() {
+    let model = ProfitModel {
+        annual_capital_cost: 1_000.into(),
+        bundle_tip: eth("0.01"),
+        ..model()
+    };
+
+    let mut opportunity = opportunity("100", "100");
+    opportunity.holding = (365 * 86_400).into();
+
+    let estimate = model.estimate(&opportunity);
+    assert_eq!(estimate.capital_cost, eth("10"));
+    assert_eq!(estimate.tip, eth("0.01"));
+
+    let liquidation = model.estimate_liquidation(eth("100"), eth("110"), U256::zero());
+    assert_eq!(liquidation.capital_cost, U256::zero());
+    assert_eq!(liquidation.tip, U256::zero());
+    assert_eq!(liquidation.expected_pnl, I256::from_raw(eth("9.45")));
+}
+
+#[test]
+fn converts_usdt_to_eth() {
+    // 0.0005 ETH per USDT
+    let usdt_price = eth("0.0005");
+
+    assert_eq!(
+        to_eth(U256::from(2_000_000_000u64), ReserveAsset::Usdt, usdt_price),
+        eth("1")
+    );
+    assert_eq!(to_eth(eth("3"), ReserveAsset::Weth, eth("1")), eth("3"));
+}
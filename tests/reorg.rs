@@ -0,0 +1,126 @@
+#![cfg(test)]
+
+use bend_dao_collector::{
+    benddao::loan::{NftAsset, ReserveAsset},
+    types::{Auction, AuctionChange, PendingAuctions},
+};
+use ethers::types::{Address, H256, U256, U64};
+
+const NFT_TOKEN_ID: u64 = 5477;
+
+fn auction(current_bid: u64) -> Auction {
+    Auction {
+        nft_asset: NftAsset::Bayc,
+        nft_token_id: NFT_TOKEN_ID.into(),
+        current_bid: current_bid.into(),
+        current_bidder: Address::repeat_byte(current_bid as u8),
+        first_bidder: Address::zero(),
+        bid_end_timestamp: 1_704_447_839.into(),
+        reserve_asset: ReserveAsset::Weth,
+    }
+}
+
+/// Applies a log the way `BendDao::react_to_lend_pool_event` does, `None` removes the auction
+fn apply(
+    pending_auctions: &mut PendingAuctions,
+    block_number: u64,
+    block_hash: u64,
+    after: Option<Auction>,
+) {
+    let previous = pending_auctions
+        .get(NftAsset::Bayc, NFT_TOKEN_ID.into())
+        .copied();
+
+    match after {
+        Some(auction) => {
+            pending_auctions.add_update_auction(auction);
+        }
+        None => pending_auctions.remove_auction(NftAsset::Bayc, NFT_TOKEN_ID.into()),
+    }
+
+    pending_auctions.journal(AuctionChange {
+        block_number: block_number.into(),
+        block_hash: H256::from_low_u64_be(block_hash),
+        log_index: U256::zero(),
+        nft_asset: NftAsset::Bayc,
+        nft_token_id: NFT_TOKEN_ID.into(),
+        previous,
+    });
+}
+
+fn current(pending_auctions: &PendingAuctions) -> Option<Auction> {
+    pending_auctions
+        .get(NftAsset::Bayc, NFT_TOKEN_ID.into())
+        .copied()
+}
+
+#[test]
+fn reverts_reorged_out_logs() {
+    let mut pending_auctions = PendingAuctions::default();
+
+    // auction at 100, outbid at 101, redeemed at 102
+    apply(&mut pending_auctions, 100, 0xa0, Some(auction(1)));
+    apply(&mut pending_auctions, 101, 0xa1, Some(auction(2)));
+    apply(&mut pending_auctions, 102, 0xa2, None);
+    assert_eq!(current(&pending_auctions), None);
+
+    // the node flags the redeem as removed
+    let block = pending_auctions.journaled_block(H256::from_low_u64_be(0xa2));
+    assert_eq!(block, Some(U64::from(102)));
+    let reverted = pending_auctions.revert_from(block.unwrap());
+    assert_eq!(reverted.len(), 1);
+    assert_eq!(current(&pending_auctions), Some(auction(2)));
+
+    // a different block 101 shows up, the outbid goes too
+    let block = pending_auctions.reorged_block(101.into(), H256::from_low_u64_be(0xb1));
+    assert_eq!(block, Some(U64::from(101)));
+    pending_auctions.revert_from(block.unwrap());
+    assert_eq!(current(&pending_auctions), Some(auction(1)));
+
+    // the new chain outbids with a different bid
+    apply(&mut pending_auctions, 101, 0xb1, Some(auction(3)));
+    assert_eq!(current(&pending_auctions), Some(auction(3)));
+
+    // and reverting all of it leaves no auction
+    pending_auctions.revert_from(100.into());
+    assert_eq!(current(&pending_auctions), None);
+    assert_eq!(pending_auctions.journal_len(), 0);
+}
+
+#[test]
+fn logs_on_the_same_chain_are_not_reorgs() {
+    let mut pending_auctions = PendingAuctions::default();
+
+    apply(&mut pending_auctions, 100, 0xa0, Some(auction(1)));
+
+    // same block, another log
+    assert_eq!(
+        pending_auctions.reorged_block(100.into(), H256::from_low_u64_be(0xa0)),
+        None
+    );
+    // a later block
+    assert_eq!(
+        pending_auctions.reorged_block(101.into(), H256::from_low_u64_be(0xa1)),
+        None
+    );
+    // a removed log of a block we didn't change anything in
+    assert_eq!(
+        pending_auctions.journaled_block(H256::from_low_u64_be(0xb0)),
+        None
+    );
+}
+
+#[test]
+fn confirmed_changes_are_pruned() {
+    let mut pending_auctions = PendingAuctions::default();
+
+    apply(&mut pending_auctions, 100, 0xa0, Some(auction(1)));
+    apply(&mut pending_auctions, 110, 0xaa, Some(auction(2)));
+
+    pending_auctions.prune_journal(112.into(), 12);
+    assert_eq!(pending_auctions.journal_len(), 1);
+
+    // block 100 is final, only the outbid can be reverted
+    pending_auctions.revert_from(100.into());
+    assert_eq!(current(&pending_auctions), Some(auction(1)));
+}
@@ -0,0 +1,147 @@
+#![cfg(test)]
+
+use anyhow::{bail, Result};
+use bend_dao_collector::resilient_ws::ResilientWs;
+use ethers::{
+    contract::EthEvent,
+    types::{Address, Log, H256, U256, U64},
+};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tokio::{net::TcpListener, time::Duration};
+use tokio_tungstenite::tungstenite::Message;
+
+const CONTRACT: Address = Address::repeat_byte(0xbe);
+
+#[derive(Clone, Debug, EthEvent)]
+#[ethevent(name = "Ping", abi = "Ping(uint256)")]
+struct PingFilter {
+    #[ethevent(indexed)]
+    n: U256,
+}
+
+/// `Ping(n)` emitted at block `n`
+fn ping(n: u64) -> Log {
+    Log {
+        address: CONTRACT,
+        topics: vec![PingFilter::signature(), H256::from_low_u64_be(n)],
+        block_hash: Some(H256::from_low_u64_be(n)),
+        block_number: Some(U64::from(n)),
+        transaction_hash: Some(H256::from_low_u64_be(n)),
+        transaction_index: Some(U64::zero()),
+        log_index: Some(U256::zero()),
+        removed: Some(false),
+        ..Default::default()
+    }
+}
+
+fn hex_to_u64(value: &Value) -> u64 {
+    u64::from_str_radix(value.as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+}
+
+/// Node stand-in that has mined `ping(10)..=ping(12)`.
+///
+/// The first connection is at block 10, delivers `ping(10)` once subscribed and then
+/// drops. `ping(11)` is mined while the bot is disconnected. The second connection is
+/// at block 11 and delivers `ping(11)` again, as a node resubscribed in the middle of a
+/// block would, and `ping(12)`.
+async fn spawn_node() -> Result<(String, Arc<Mutex<usize>>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("ws://{}", listener.local_addr()?);
+    let connections = Arc::new(Mutex::new(0));
+
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let connection = {
+                let mut counter = counter.lock().unwrap();
+                *counter += 1;
+                *counter
+            };
+
+            let (block_number, pushed) = match connection {
+                1 => (10, vec![ping(10)]),
+                _ => (11, vec![ping(11), ping(12)]),
+            };
+
+            tokio::spawn(async move {
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+                while let Some(Ok(Message::Text(text))) = ws.next().await {
+                    let request: Value = serde_json::from_str(&text).unwrap();
+
+                    let result = match request["method"].as_str().unwrap() {
+                        "eth_subscribe" => json!("0x1"),
+                        "eth_unsubscribe" => json!(true),
+                        "eth_blockNumber" => json!(format!("{block_number:#x}")),
+                        "eth_getLogs" => {
+                            let filter = &request["params"][0];
+                            let from_block = hex_to_u64(&filter["fromBlock"]);
+                            let to_block = hex_to_u64(&filter["toBlock"]);
+                            json!((10..=block_number)
+                                .filter(|n| (from_block..=to_block).contains(n))
+                                .map(ping)
+                                .collect::<Vec<_>>())
+                        }
+                        method => panic!("unexpected request {method}"),
+                    };
+
+                    let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+                    ws.send(Message::Text(response.to_string())).await.unwrap();
+
+                    if request["method"] != "eth_subscribe" {
+                        continue;
+                    }
+
+                    for log in &pushed {
+                        let notification = json!({
+                            "jsonrpc": "2.0",
+                            "method": "eth_subscription",
+                            "params": {"subscription": "0x1", "result": log},
+                        });
+                        ws.send(Message::Text(notification.to_string()))
+                            .await
+                            .unwrap();
+                    }
+
+                    if connection == 1 {
+                        // drop the connection without a close frame
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    Ok((url, connections))
+}
+
+#[tokio::test]
+async fn backfills_logs_missed_while_disconnected() -> Result<()> {
+    let (url, connections) = spawn_node().await?;
+    let ws = ResilientWs::connect(&url, None).await?;
+
+    let mut handled = vec![];
+
+    let follow = ws.follow_logs(CONTRACT, |ping: PingFilter, meta, _| {
+        assert_eq!(ping.n, U256::from(meta.block_number.as_u64()));
+        handled.push(meta.block_number.as_u64());
+        let done = handled.len() == 3;
+        async move {
+            if done {
+                bail!("done");
+            }
+            Ok(())
+        }
+    });
+
+    let result = tokio::time::timeout(Duration::from_secs(10), follow).await?;
+
+    // `follow_logs` only returns the handler's error
+    assert_eq!(result.unwrap_err().to_string(), "done");
+    assert_eq!(handled, vec![10, 11, 12]);
+    assert_eq!(*connections.lock().unwrap(), 2);
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+#![cfg(test)]
+
+use anyhow::{bail, Result};
+use bend_dao_collector::{
+    resilient_ws::ResilientWs,
+    rpc_pool::{follow_pending_txs, RpcPool},
+};
+use ethers::{
+    providers::{Middleware, Provider},
+    types::{Transaction, H256, U64},
+};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::{net::TcpListener, task::JoinHandle, time::Duration};
+use tokio_tungstenite::tungstenite::Message;
+
+fn tx(n: u64) -> Transaction {
+    Transaction {
+        hash: H256::from_low_u64_be(n),
+        ..Default::default()
+    }
+}
+
+/// Node stand-in at `block_number` that answers after `delay` and pushes `pending`
+/// to every `newPendingTransactions` subscription. Aborting the handle takes it down.
+async fn spawn_node(
+    block_number: u64,
+    delay: Duration,
+    pending: Vec<Transaction>,
+) -> Result<(String, JoinHandle<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("ws://{}", listener.local_addr()?);
+
+    let handle = tokio::spawn(async move {
+        // one connection at a time so aborting also closes the open one
+        while let Ok((stream, _)) = listener.accept().await {
+            let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+                continue;
+            };
+
+            while let Some(Ok(Message::Text(text))) = ws.next().await {
+                let request: Value = serde_json::from_str(&text).unwrap();
+
+                tokio::time::sleep(delay).await;
+
+                let result = match request["method"].as_str().unwrap() {
+                    "eth_subscribe" => json!("0x1"),
+                    "eth_unsubscribe" => json!(true),
+                    "eth_blockNumber" => json!(format!("{block_number:#x}")),
+                    method => panic!("unexpected request {method}"),
+                };
+
+                let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+                if ws.send(Message::Text(response.to_string())).await.is_err() {
+                    break;
+                }
+
+                if request["method"] != "eth_subscribe" {
+                    continue;
+                }
+
+                for tx in &pending {
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "eth_subscription",
+                        "params": {"subscription": "0x1", "result": tx},
+                    });
+                    ws.send(Message::Text(notification.to_string())).await.ok();
+                }
+            }
+        }
+    });
+
+    Ok((url, handle))
+}
+
+#[tokio::test]
+async fn routes_to_the_fastest_node_and_fails_over() -> Result<()> {
+    let (slow_url, _slow) = spawn_node(1, Duration::from_millis(100), vec![]).await?;
+    let (fast_url, fast) = spawn_node(2, Duration::ZERO, vec![]).await?;
+
+    let pool = RpcPool::connect(&[slow_url, fast_url]).await?;
+    let provider = Provider::new(pool.clone());
+
+    assert_eq!(pool.healthy_nodes(), 2);
+    assert_eq!(provider.get_block_number().await?, U64::from(2));
+
+    fast.abort();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // the fast node is gone, the request fails over to the slow one
+    assert_eq!(provider.get_block_number().await?, U64::from(1));
+    assert_eq!(pool.healthy_nodes(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn fails_if_no_node_is_reachable() -> Result<()> {
+    let (url, node) = spawn_node(1, Duration::ZERO, vec![]).await?;
+    node.abort();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert!(RpcPool::connect(&[url]).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn merges_pending_txs_of_every_node() -> Result<()> {
+    let (first_url, _first) = spawn_node(1, Duration::ZERO, vec![tx(1), tx(2)]).await?;
+    let (second_url, _second) = spawn_node(1, Duration::ZERO, vec![tx(2), tx(3)]).await?;
+
+    let nodes = vec![
+        Arc::new(ResilientWs::connect(&first_url, None).await?),
+        Arc::new(ResilientWs::connect(&second_url, None).await?),
+    ];
+
+    let mut seen = vec![];
+
+    let follow = follow_pending_txs(&nodes, |tx| {
+        seen.push(tx.hash.to_low_u64_be());
+        let done = seen.len() == 3;
+        async move {
+            if done {
+                bail!("done");
+            }
+            Ok(())
+        }
+    });
+
+    let result = tokio::time::timeout(Duration::from_secs(10), follow).await?;
+    assert_eq!(result.unwrap_err().to_string(), "done");
+
+    seen.sort();
+    assert_eq!(seen, vec![1, 2, 3]);
+
+    Ok(())
+}
@@ -0,0 +1,295 @@
+#![cfg(test)]
+
+use anyhow::Result;
+use bend_dao_collector::{
+    benddao::loan::{NftAsset, ReserveAsset},
+    builders::BuilderStats,
+    bundle_tracker::BundleOutcome,
+    store::{archive_path, BidRecord, BundleRecord, FineRecord, Record, Store},
+    types::Auction,
+};
+use ethers::types::{Address, U256};
+use std::path::PathBuf;
+
+fn store_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("store-{name}-{}.jsonl", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    path
+}
+
+fn auction(nft_token_id: u64, current_bid: u64) -> Auction {
+    Auction {
+        nft_asset: NftAsset::CryptoPunks,
+        nft_token_id: nft_token_id.into(),
+        current_bid: current_bid.into(),
+        current_bidder: Address::zero(),
+        first_bidder: Address::zero(),
+        bid_end_timestamp: 1_704_447_839.into(),
+        reserve_asset: ReserveAsset::Weth,
+    }
+}
+
+#[tokio::test]
+async fn restores_state_after_reopening() -> Result<()> {
+    let path = store_path("reopen");
+
+    let store = Store::open(&path).await?;
+    store.append(Record::ClosedLoan { loan_id: 1 }).await?;
+    store.append(Record::Auction(auction(1, 10))).await?;
+    store.append(Record::Auction(auction(2, 10))).await?;
+    // outbid
+    store.append(Record::Auction(auction(1, 20))).await?;
+    store
+        .append(Record::AuctionRemoved {
+            nft_asset: NftAsset::CryptoPunks,
+            nft_token_id: 2.into(),
+        })
+        .await?;
+    store
+        .append(Record::MonitoredLoans {
+            loan_ids: vec![3.into(), 4.into()],
+        })
+        .await?;
+    let before = store.state().await;
+    drop(store);
+
+    let state = Store::open(&path).await?.state().await;
+
+    assert_eq!(state, before);
+    assert!(state.closed_loans.contains(&1));
+    assert_eq!(state.auctions, vec![auction(1, 20)]);
+    assert_eq!(state.monitored_loans, vec![U256::from(3), U256::from(4)]);
+
+    std::fs::remove_file(&path).ok();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn keeps_fines_and_reads_auctions_without_first_bidder() -> Result<()> {
+    let path = store_path("fines");
+
+    // written before auctions tracked their first bidder
+    std::fs::write(
+        &path,
+        r#"{"table":"auction","nft_asset":"CryptoPunks","nft_token_id":"0x1","current_bid":"0xa","current_bidder":"0x0000000000000000000000000000000000000000","bid_end_timestamp":"0x6597cf5f","reserve_asset":"Weth"}"#
+            .to_owned()
+            + "\n",
+    )?;
+
+    let store = Store::open(&path).await?;
+    assert_eq!(store.state().await.auctions, vec![auction(1, 10)]);
+
+    let fine = FineRecord {
+        nft_asset: NftAsset::CryptoPunks,
+        nft_token_id: 1.into(),
+        reserve_asset: ReserveAsset::Weth,
+        fine: 5.into(),
+        timestamp: 1_704_447_839,
+    };
+    store.append(Record::Fine(fine.clone())).await?;
+    drop(store);
+
+    let state = Store::open(&path).await?.state().await;
+    assert_eq!(state.fines, vec![fine]);
+
+    std::fs::remove_file(&path).ok();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn keeps_bundle_outcomes_and_reads_bundles_without_one() -> Result<()> {
+    let path = store_path("bundles");
+
+    // written before bundle outcomes were tracked
+    std::fs::write(
+        &path,
+        r#"{"table":"bundle","block":"0x10","transactions":[],"bundle_hash":null,"included":false,"timestamp":1704447839}"#
+            .to_owned()
+            + "\n",
+    )?;
+
+    let store = Store::open(&path).await?;
+    assert_eq!(store.state().await.bundles[0].outcome, None);
+
+    let bundle = BundleRecord {
+        block: Some(17.into()),
+        transactions: vec![],
+        bundle_hash: None,
+        included: false,
+        outcome: Some(BundleOutcome::Outbid),
+        timestamp: 1_704_447_851,
+    };
+    store.append(Record::Bundle(bundle.clone())).await?;
+    drop(store);
+
+    let state = Store::open(&path).await?.state().await;
+    assert_eq!(state.bundles[1], bundle);
+
+    std::fs::remove_file(&path).ok();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn drops_a_torn_last_record() -> Result<()> {
+    let path = store_path("torn");
+
+    let store = Store::open(&path).await?;
+    store.append(Record::ClosedLoan { loan_id: 1 }).await?;
+    drop(store);
+
+    // crash halfway through writing the second record
+    let mut contents = std::fs::read_to_string(&path)?;
+    contents.push_str(r#"{"table":"closed_loan","loan"#);
+    std::fs::write(&path, contents)?;
+
+    let store = Store::open(&path).await?;
+    store.append(Record::ClosedLoan { loan_id: 2 }).await?;
+    drop(store);
+
+    let state = Store::open(&path).await?.state().await;
+    assert_eq!(
+        state.closed_loans.into_iter().collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+
+    std::fs::remove_file(&path).ok();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn compaction_keeps_the_state() -> Result<()> {
+    let path = store_path("compact");
+
+    let store = Store::open(&path).await?;
+    for current_bid in 1..=50 {
+        store
+            .append(Record::Auction(auction(1, current_bid)))
+            .await?;
+    }
+    let before = store.state().await;
+
+    store.compact().await?;
+    store.append(Record::ClosedLoan { loan_id: 7 }).await?;
+    drop(store);
+
+    let contents = std::fs::read_to_string(&path)?;
+    // closed loan, monitored set, one auction and the record appended after compacting
+    assert_eq!(contents.lines().count(), 3);
+
+    let state = Store::open(&path).await?.state().await;
+    assert_eq!(state.auctions, before.auctions);
+    assert!(state.closed_loans.contains(&7));
+
+    std::fs::remove_file(&path).ok();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn compaction_archives_records_past_retention() -> Result<()> {
+    let path = store_path("retention");
+    let archive = archive_path(&path);
+    std::fs::remove_file(&archive).ok();
+
+    let bid = |timestamp| BidRecord {
+        nft_asset: Address::zero(),
+        nft_token_id: 1.into(),
+        bid_price: 1.into(),
+        timestamp,
+    };
+    let recent = bid(chrono::Utc::now().timestamp() as u64);
+
+    let store = Store::open(&path).await?;
+    store.append(Record::Bid(bid(1_704_447_839))).await?;
+    store.append(Record::Bid(recent.clone())).await?;
+    store.append(Record::ClosedLoan { loan_id: 7 }).await?;
+    store.compact().await?;
+    drop(store);
+
+    let state = Store::open(&path).await?.state().await;
+    assert_eq!(state.bids, vec![recent]);
+    assert!(state.closed_loans.contains(&7));
+
+    let archived: Vec<Record> = std::fs::read_to_string(&archive)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(archived, vec![Record::Bid(bid(1_704_447_839))]);
+
+    std::fs::remove_file(&path).ok();
+    std::fs::remove_file(&archive).ok();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn compacts_while_running() -> Result<()> {
+    let path = store_path("running");
+
+    let store = Store::open(&path).await?;
+    for current_bid in 1..=10_001 {
+        store
+            .append(Record::Auction(auction(1, current_bid)))
+            .await?;
+    }
+    drop(store);
+
+    let contents = std::fs::read_to_string(&path)?;
+    // compacted on the 10,001st record without reopening
+    assert_eq!(contents.lines().count(), 2);
+
+    std::fs::remove_file(&path).ok();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn keeps_the_latest_builder_stats() -> Result<()> {
+    let path = store_path("builder-stats");
+
+    let stats = |included| BuilderStats {
+        sent: 4,
+        included,
+        ..Default::default()
+    };
+
+    let store = Store::open(&path).await?;
+    for included in 1..=2 {
+        store
+            .append(Record::BuilderStats {
+                builder: "titanbuilder".to_string(),
+                stats: stats(included),
+            })
+            .await?;
+    }
+    store.compact().await?;
+    drop(store);
+
+    let state = Store::open(&path).await?.state().await;
+    assert_eq!(state.builder_stats["titanbuilder"], stats(2));
+
+    std::fs::remove_file(&path).ok();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn imports_repaid_defaulted_loans() -> Result<()> {
+    let path = store_path("import");
+    let legacy = store_path("import-legacy");
+    std::fs::write(&legacy, "[1,2,3]")?;
+
+    let store = Store::open(&path).await?;
+    store.import_repaid_defaulted(&legacy).await?;
+
+    assert_eq!(store.state().await.closed_loans.len(), 3);
+
+    std::fs::remove_file(&path).ok();
+    std::fs::remove_file(&legacy).ok();
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+#![cfg(test)]
+
+use bend_dao_collector::{
+    benddao::loan::ReserveAsset,
+    constants::{BID_GAS_ETH, WETH},
+    treasury::{Funding, Treasury},
+    types::{AuctionBid, Balances},
+};
+use ethers::types::U256;
+
+fn ether(amount: u64) -> U256 {
+    U256::from(amount) * U256::exp10(18)
+}
+
+fn treasury() -> Treasury {
+    Treasury {
+        weth_target: ether(5),
+        usdt_target: U256::zero(),
+        gas_reserve: ether(1),
+    }
+}
+
+fn bid(reserve_asset: ReserveAsset, bid_price: U256) -> AuctionBid {
+    AuctionBid {
+        nft_asset: WETH.into(),
+        nft_token_id: U256::one(),
+        bid_price,
+        reserve_asset,
+        expected_profit: U256::zero(),
+    }
+}
+
+#[test]
+fn test_eth_is_wrapped_up_to_the_target() {
+    let mut balances = Balances {
+        eth: ether(10),
+        weth: ether(1),
+        ..Default::default()
+    };
+    let mut funding = Funding::default();
+
+    treasury()
+        .fund(&mut balances, &mut funding, ReserveAsset::Weth, ether(2))
+        .unwrap();
+
+    assert_eq!(funding.wrap, ether(4));
+    assert!(funding.approve_weth);
+    assert!(!funding.approve_usdt);
+    assert_eq!(balances.weth, ether(3));
+    assert_eq!(balances.eth, ether(6) - U256::from(BID_GAS_ETH));
+
+    // already wrapped and approved
+    let mut funding = Funding::default();
+    treasury()
+        .fund(&mut balances, &mut funding, ReserveAsset::Weth, ether(3))
+        .unwrap();
+    assert!(funding.is_empty());
+}
+
+#[test]
+fn test_gas_reserve_is_never_wrapped() {
+    let mut balances = Balances {
+        eth: ether(3),
+        weth: ether(1),
+        is_weth_lend_pool_approved: true,
+        ..Default::default()
+    };
+    let mut funding = Funding::default();
+
+    // 2 ETH less the bid's gas can be wrapped, short of the 2 ETH needed
+    let result = treasury().fund(&mut balances, &mut funding, ReserveAsset::Weth, ether(3));
+    assert!(result.is_err());
+    assert!(funding.is_empty());
+    assert_eq!(balances.eth, ether(3));
+
+    // wraps only what's spare, below the target
+    treasury()
+        .fund(&mut balances, &mut funding, ReserveAsset::Weth, ether(2))
+        .unwrap();
+    assert_eq!(funding.wrap, ether(2) - U256::from(BID_GAS_ETH));
+    assert_eq!(balances.eth, ether(1));
+}
+
+#[test]
+fn test_unfunded_bids_are_left_out() {
+    let balances = Balances {
+        eth: ether(10),
+        usdt: U256::from(1_000) * U256::exp10(6),
+        is_weth_lend_pool_approved: true,
+        ..Default::default()
+    };
+    let bids = vec![
+        bid(ReserveAsset::Usdt, U256::from(2_000) * U256::exp10(6)),
+        bid(ReserveAsset::Usdt, U256::from(500) * U256::exp10(6)),
+        bid(ReserveAsset::Weth, ether(4)),
+        bid(ReserveAsset::Weth, ether(6)),
+    ];
+
+    let (funded, funding) = treasury().fund_bids(balances.clone(), bids.clone());
+
+    assert_eq!(funded.len(), 2);
+    assert_eq!(funded[0].bid_price, bids[1].bid_price);
+    assert_eq!(funded[1].bid_price, bids[2].bid_price);
+    assert_eq!(
+        funding,
+        Funding {
+            wrap: ether(5),
+            approve_weth: false,
+            approve_usdt: true,
+        }
+    );
+
+    assert_eq!(treasury().shortfalls(&balances).len(), 0);
+    let broke = Balances::default();
+    assert_eq!(treasury().shortfalls(&broke).len(), 2);
+}
@@ -0,0 +1,114 @@
+#![cfg(test)]
+
+use bend_dao_collector::{
+    reservoir::{sales_response::SalesResponse, tokens_response::TokensResponse},
+    valuation::{bounded_token_value, median, TokenValuation},
+};
+use ethers::{types::U256, utils::parse_ether};
+
+fn eth(amount: &str) -> U256 {
+    parse_ether(amount).unwrap()
+}
+
+#[test]
+fn values_a_token_at_its_best_bid() {
+    // nothing prices the token
+    assert_eq!(TokenValuation::default().value(), U256::zero());
+
+    // a bid on a rare attribute beats the bid on the token
+    let valuation = TokenValuation {
+        best_token_bid: Some(eth("30")),
+        best_attribute_bid: Some(eth("45")),
+        comparable_sales: vec![],
+    };
+    assert_eq!(valuation.value(), eth("45"));
+
+    // sales aren't bids, they only cap one that's above what comparable tokens sold for
+    let valuation = TokenValuation {
+        best_token_bid: Some(eth("30")),
+        best_attribute_bid: None,
+        comparable_sales: vec![eth("60"), eth("40"), eth("50")],
+    };
+    assert_eq!(valuation.value(), eth("30"));
+
+    let valuation = TokenValuation {
+        best_token_bid: None,
+        best_attribute_bid: Some(eth("80")),
+        comparable_sales: vec![eth("60"), eth("40"), eth("50")],
+    };
+    assert_eq!(valuation.value(), eth("50"));
+
+    // too few sales to compare against
+    let valuation = TokenValuation {
+        best_token_bid: Some(eth("30")),
+        best_attribute_bid: None,
+        comparable_sales: vec![eth("20"), eth("20")],
+    };
+    assert_eq!(valuation.value(), eth("30"));
+
+    // no bid, no value
+    let valuation = TokenValuation {
+        comparable_sales: vec![eth("60"), eth("40"), eth("50")],
+        ..Default::default()
+    };
+    assert_eq!(valuation.value(), U256::zero());
+}
+
+#[test]
+fn bounds_token_values_by_the_collection_price() {
+    let max_multiple = U256::from(20_000);
+
+    // never below the collection price
+    assert_eq!(
+        bounded_token_value(eth("10"), eth("8"), max_multiple),
+        eth("10")
+    );
+    assert_eq!(
+        bounded_token_value(eth("10"), eth("15"), max_multiple),
+        eth("15")
+    );
+    // a spoofed token bid is capped
+    assert_eq!(
+        bounded_token_value(eth("10"), eth("500"), max_multiple),
+        eth("20")
+    );
+}
+
+#[test]
+fn median_of_sales() {
+    assert_eq!(median(&[eth("1"), eth("2")]), None);
+    assert_eq!(median(&[eth("3"), eth("1"), eth("2")]), Some(eth("2")));
+    assert_eq!(
+        median(&[eth("4"), eth("1"), eth("3"), eth("2")]),
+        Some(eth("2.5"))
+    );
+}
+
+#[test]
+fn parses_reservoir_responses() {
+    let tokens: TokensResponse = serde_json::from_str(
+        r#"{"tokens": [{"token": {"tokenId": "5477", "attributes": [
+            {"key": "Fur", "value": "Brown", "tokenCount": 1370},
+            {"key": "Hat", "value": "Trippy Captain's Hat", "tokenCount": 65},
+            {"key": "Eyes", "value": "Bored", "tokenCount": 1714}
+        ]}}]}"#,
+    )
+    .unwrap();
+
+    let rarest: Vec<_> = tokens
+        .rarest_attributes()
+        .into_iter()
+        .map(|attribute| attribute.key)
+        .collect();
+    assert_eq!(rarest, vec!["Hat", "Fur", "Eyes"]);
+
+    let sales: SalesResponse = serde_json::from_str(
+        r#"{"sales": [
+            {"price": {"netAmount": {"native": 31.5, "raw": "31500000000000000000"}}},
+            {"price": {"netAmount": {"native": 29, "raw": "29000000000000000000"}}}
+        ]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(sales.get_net_prices(), vec![eth("31.5"), eth("29")]);
+}